@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::DpkgError;
+use crate::state;
+
+fn bsdtar_bin() -> String {
+    std::env::var("BSDTAR").unwrap_or_else(|_| "bsdtar".to_string())
+}
+
+fn curl_bin() -> String {
+    std::env::var("CURL").unwrap_or_else(|_| "curl".to_string())
+}
+
+/// A `file:` package archive together with the package it contains.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PackageFile {
+    /// Path or URL as written in the config
+    pub source: String,
+    /// Local archive; downloads are kept in the state directory
+    pub path: PathBuf,
+    pub name: String,
+    /// Full `[epoch:]pkgver-pkgrel` from `.PKGINFO`
+    pub version: String,
+}
+
+impl std::fmt::Display for PackageFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "file:{}", self.source)
+    }
+}
+
+fn is_url(source: &str) -> bool {
+    source.starts_with("https://") || source.starts_with("http://")
+}
+
+/// Locate (downloading URLs) and read every declared package archive.
+pub fn inspect_all(config_path: &Path, sources: &[String]) -> Result<Vec<PackageFile>, DpkgError> {
+    sources.iter().map(|s| inspect(config_path, s)).collect()
+}
+
+/// Make `source` available locally and read its name and version from `.PKGINFO`.
+/// Relative paths are relative to the directory holding the config file.
+pub fn inspect(config_path: &Path, source: &str) -> Result<PackageFile, DpkgError> {
+    let path = if is_url(source) {
+        download(source)?
+    } else {
+        config_path.parent().unwrap_or(Path::new(".")).join(source)
+    };
+    if !path.is_file() {
+        return Err(DpkgError::InstallFailed(format!(
+            "Package file not found: {}",
+            path.display()
+        )));
+    }
+
+    let output = Command::new(bsdtar_bin())
+        .arg("-xOf")
+        .arg(&path)
+        .arg(".PKGINFO")
+        .output()
+        .map_err(|e| DpkgError::spawn(&bsdtar_bin(), e))?;
+    if !output.status.success() {
+        return Err(DpkgError::InstallFailed(format!(
+            "Cannot read .PKGINFO from {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let (name, version) = parse_pkginfo(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| {
+            DpkgError::InstallFailed(format!(
+                "{} has no pkgname or pkgver in .PKGINFO",
+                path.display()
+            ))
+        })?;
+
+    Ok(PackageFile {
+        source: source.to_string(),
+        path,
+        name,
+        version,
+    })
+}
+
+/// Package name and full version from `.PKGINFO` text.
+pub fn parse_pkginfo(text: &str) -> Option<(String, String)> {
+    let mut name = None;
+    let mut version = None;
+    for line in text.lines() {
+        match line.split_once(" = ") {
+            Some(("pkgname", value)) => name = Some(value.trim().to_string()),
+            Some(("pkgver", value)) => version = Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+    Some((name?, version?))
+}
+
+/// Archives whose package is not installed, or installed at a different version.
+pub fn pending(
+    files: &[PackageFile],
+    installed_versions: &HashMap<String, String>,
+) -> Vec<PackageFile> {
+    files
+        .iter()
+        .filter(|f| installed_versions.get(&f.name) != Some(&f.version))
+        .cloned()
+        .collect()
+}
+
+/// Download `url` into the state directory, reusing an earlier download.
+/// Archive names carry name, version and architecture, so a cached file is the same package.
+fn download(url: &str) -> Result<PathBuf, DpkgError> {
+    let file_name = url
+        .rsplit('/')
+        .next()
+        .filter(|n| !n.is_empty())
+        .ok_or_else(|| DpkgError::NetworkError(format!("No file name in URL {url}")))?;
+    let dir = state::ensure_state_dir()?.join("downloads");
+    std::fs::create_dir_all(&dir).map_err(|e| {
+        DpkgError::State(format!("Cannot create download directory {}: {e}", dir.display()))
+    })?;
+
+    let path = dir.join(file_name);
+    if path.is_file() {
+        return Ok(path);
+    }
+
+    let partial = path.with_extension("part");
+    let output = Command::new(curl_bin())
+        .args(["--fail", "--silent", "--show-error", "--location", "--output"])
+        .arg(&partial)
+        .arg(url)
+        .output()
+        .map_err(|e| DpkgError::spawn(&curl_bin(), e))?;
+    if !output.status.success() {
+        std::fs::remove_file(&partial).ok();
+        return Err(DpkgError::NetworkError(format!(
+            "Download of {url} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    std::fs::rename(&partial, &path).map_err(|e| {
+        DpkgError::State(format!("Cannot store download {}: {e}", path.display()))
+    })?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pkginfo() {
+        let text = "# Generated by makepkg 6.1.0\npkgname = vendor-tool\npkgbase = vendor-tool\npkgver = 1:1.2-1\npkgdesc = Vendor tool = great\nsize = 1024\n";
+        assert_eq!(
+            parse_pkginfo(text),
+            Some(("vendor-tool".to_string(), "1:1.2-1".to_string()))
+        );
+        assert_eq!(parse_pkginfo("pkgname = x\n"), None);
+    }
+
+    #[test]
+    fn test_pending_compares_versions() {
+        let file = |name: &str, version: &str| PackageFile {
+            source: format!("/srv/{name}.pkg.tar.zst"),
+            path: PathBuf::from(format!("/srv/{name}.pkg.tar.zst")),
+            name: name.to_string(),
+            version: version.to_string(),
+        };
+        let files = vec![file("current", "1.0-1"), file("outdated", "2.0-1"), file("new", "1.0-1")];
+        let installed: HashMap<String, String> = [
+            ("current".to_string(), "1.0-1".to_string()),
+            ("outdated".to_string(), "1.9-1".to_string()),
+        ]
+        .into();
+        let names: Vec<String> = pending(&files, &installed).into_iter().map(|f| f.name).collect();
+        assert_eq!(names, vec!["outdated", "new"]);
+    }
+}
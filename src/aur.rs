@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+
+use crate::error::DpkgError;
+use crate::local;
+use crate::system::{self, escalate, progress};
+
+/// Build AUR packages with makepkg as the build user and install them with pacman -U
+/// as root. Used when dpkg runs as root: yay would have to run pacman itself, and the
+/// build user must never be able to.
+pub fn install(packages: &[String], asdeps: bool, verbose: bool) -> Result<(), DpkgError> {
+    let mut building = Vec::new();
+    for package in packages {
+        install_one(package, asdeps, verbose, &mut building)?;
+    }
+    Ok(())
+}
+
+/// Install `package`, building the AUR packages it needs first. `building` holds the
+/// packages waiting on it, to stop at dependency cycles.
+fn install_one(package: &str, asdeps: bool, verbose: bool, building: &mut Vec<String>) -> Result<(), DpkgError> {
+    if building.iter().any(|p| p == package) {
+        return Err(DpkgError::BuildFailed(format!(
+            "AUR dependency cycle: {} -> {package}",
+            building.join(" -> ")
+        )));
+    }
+    let dir = fetch(package, verbose)?;
+    let srcinfo = local::srcinfo(&dir)?;
+    let (names, version) = local::parse_srcinfo(&srcinfo).ok_or_else(|| {
+        DpkgError::BuildFailed(format!("PKGBUILD of {package} has no pkgname, pkgver or pkgrel"))
+    })?;
+
+    let missing = local::install_build_deps(&local::parse_srcinfo_depends(&srcinfo), verbose)?;
+    building.push(package.to_string());
+    for dep in &missing {
+        let name = dep.split(['<', '>', '=']).next().unwrap_or(dep);
+        install_one(name, true, verbose, building)?;
+    }
+    building.pop();
+
+    if verbose {
+        eprintln!("Building aur:{package} in {}...", dir.display());
+    }
+    // A split package builds several archives; only the one asked for is installed
+    let wanted = if names.iter().any(|n| n == package) { vec![package.to_string()] } else { names };
+    let files = local::make(&dir, &wanted, &version, &format!("aur:{package}"))?;
+    system::install_package_files(&files, asdeps, verbose)
+}
+
+/// Clone or update the AUR repository of `package` with `yay -G`, as the build user.
+fn fetch(package: &str, verbose: bool) -> Result<PathBuf, DpkgError> {
+    let dir = system::aur_clone_dir(package);
+    let parent = dir.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    if verbose {
+        eprintln!("Fetching the PKGBUILD of aur:{package}...");
+    }
+
+    let mut mkdir = escalate::unprivileged("mkdir")?;
+    mkdir.arg("-p").arg(&parent);
+    let output = mkdir.output().map_err(|e| escalate::spawn_error(&mkdir, e))?;
+    if !output.status.success() {
+        return Err(DpkgError::BuildFailed(format!(
+            "Cannot create {}: {}",
+            parent.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let mut command = escalate::unprivileged(system::yay_bin())?;
+    command.current_dir(&parent).args(["-G", "--force", package]);
+    let output = progress::run(&mut command).map_err(|e| escalate::spawn_error(&command, e))?;
+    if !output.status.success() || !dir.join("PKGBUILD").exists() {
+        return Err(DpkgError::BuildFailed(format!(
+            "Cannot fetch the PKGBUILD of aur:{package}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(dir)
+}
@@ -1,6 +1,11 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::{Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+
+use crate::config::{valid_user_name, LangTool};
+use crate::error::explain;
 
 #[derive(Parser, Debug)]
 #[command(name = "dpkg", version, about = "Declarative package manager for Arch Linux")]
@@ -21,6 +26,30 @@ pub struct Cli {
     #[arg(short, long, global = true)]
     pub quiet: bool,
 
+    /// How a failure is reported on stderr
+    #[arg(long, value_enum, env = "DPKG_ERROR_FORMAT", global = true, default_value_t = ErrorFormat::Text)]
+    pub error_format: ErrorFormat,
+
+    /// How pacman, yay and makepkg output is shown while they run
+    #[arg(long, value_enum, env = "DPKG_PROGRESS", global = true, default_value_t = Progress::Compact)]
+    pub progress: Progress,
+
+    /// Manage the system installed under DIR (a chroot or image) instead of this one
+    #[arg(long, value_name = "DIR", env = "DPKG_ROOT", global = true)]
+    pub root: Option<PathBuf>,
+
+    /// pacman database directory of the target (default: DBPath below the root)
+    #[arg(long, value_name = "DIR", global = true)]
+    pub dbpath: Option<PathBuf>,
+
+    /// Package cache directory passed to pacman
+    #[arg(long, value_name = "DIR", global = true)]
+    pub cachedir: Option<PathBuf>,
+
+    /// Select host sections for this hostname instead of the machine's
+    #[arg(long, value_name = "NAME", env = "DPKG_HOST", global = true)]
+    pub host: Option<String>,
+
     #[command(subcommand)]
     pub command: Option<Command>,
 }
@@ -40,6 +69,40 @@ pub enum Command {
         /// Only remove orphans, don't install packages
         #[arg(long)]
         only_remove: bool,
+
+        /// Install exactly the versions recorded in pkg.lock
+        #[arg(long)]
+        locked: bool,
+
+        /// Archive-style local mirror searched for locked versions
+        #[arg(long, value_name = "DIR", requires = "locked")]
+        mirror: Option<PathBuf>,
+
+        /// Only sync packages from these sources (comma-separated)
+        #[arg(long, value_enum, value_delimiter = ',', value_name = "SOURCE")]
+        only_source: Vec<Source>,
+
+        /// How declared packages are kept installed
+        #[arg(long, value_enum, default_value_t = SyncStrategy::Mark, conflicts_with = "locked")]
+        strategy: SyncStrategy,
+
+        /// When a batch of official or AUR packages fails, install them one at a time
+        /// and finish the remaining steps
+        #[arg(long)]
+        keep_going: bool,
+
+        /// Wait for a held pacman database or dpkg lock instead of failing, at most
+        /// SECS seconds if given
+        #[arg(long, value_name = "SECS", num_args = 0..=1, require_equals = true)]
+        wait_lock: Option<Option<u64>>,
+
+        /// When run as root, build AUR packages and local PKGBUILDs as this user
+        #[arg(long, value_name = "USER", env = "DPKG_AUR_USER", value_parser = parse_user_name)]
+        aur_user: Option<String>,
+
+        /// Output format of the dry-run plan
+        #[arg(long, value_enum, default_value_t = PlanFormat::Text)]
+        format: PlanFormat,
     },
 
     /// Display current synchronization status
@@ -49,7 +112,166 @@ pub enum Command {
     Validate,
 
     /// Show differences between config and system state
-    Diff,
+    Diff {
+        /// Only show packages from these sources (comma-separated)
+        #[arg(long, value_enum, value_delimiter = ',', value_name = "SOURCE")]
+        only_source: Vec<Source>,
+    },
+
+    /// Explain why a package is declared or installed
+    Why {
+        /// Package name
+        package: String,
+    },
+
+    /// Check the config for redundant, conflicting and suspicious declarations
+    Lint {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = LintFormat::Text)]
+        format: LintFormat,
+    },
+
+    /// Record exact package versions for this host in pkg.lock
+    Lock,
+
+    /// Describe an error code such as DPKG-E012, or list every code
+    ExplainError {
+        /// Error code (the DPKG- prefix is optional)
+        #[arg(value_parser = parse_error_code)]
+        code: Option<String>,
+    },
+
+    /// Browse the journal of past sync runs
+    History {
+        /// Only list the last N runs
+        #[arg(long, value_name = "N")]
+        last: Option<usize>,
+
+        /// Show full details of one run
+        #[arg(long, value_name = "ID")]
+        show: Option<u64>,
+    },
+
+    /// Install the declared official packages into a new root with pacstrap
+    Bootstrap {
+        /// Directory to install into
+        dir: PathBuf,
+    },
+
+    /// Restore the package set of a previous generation
+    Rollback {
+        /// Generation to restore (default: the one before the latest)
+        generation: Option<u64>,
+
+        /// List recorded generations instead of rolling back
+        #[arg(long)]
+        list: bool,
+
+        /// Skip confirmation
+        #[arg(long)]
+        no_confirm: bool,
+
+        /// Wait for a held pacman database or dpkg lock instead of failing, at most
+        /// SECS seconds if given
+        #[arg(long, value_name = "SECS", num_args = 0..=1, require_equals = true)]
+        wait_lock: Option<Option<u64>>,
+    },
+}
+
+/// How sync tells pacman which packages are wanted.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncStrategy {
+    /// Mark every package as a dependency, then the declared ones as explicit
+    Mark,
+    /// Install a `dpkg-host-<hostname>` meta-package depending on the declared packages
+    Metapackage,
+}
+
+/// Format of the error report printed when a command fails.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Text,
+    /// One JSON object with the code, exit code and message
+    Json,
+}
+
+/// How the output of package operations is shown while they run.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// One line per download, package, hook, warning and error
+    Compact,
+    /// Everything the tools print, unchanged
+    Raw,
+    /// The compact events as JSON lines on stdout
+    Json,
+    /// Nothing; output is only shown when an operation fails
+    Off,
+}
+
+/// Output format of the `sync --dry-run` plan.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanFormat {
+    Text,
+    /// The plan and the simulated pacman transactions as one JSON object
+    Json,
+}
+
+/// Output format of `dpkg lint`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintFormat {
+    Text,
+    Json,
+    /// SARIF 2.1.0, for code scanning tools
+    Sarif,
+}
+
+/// Package source selectable with `--only-source`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Official,
+    Aur,
+    Flatpak,
+    Local,
+    File,
+    Cargo,
+    Pipx,
+    Npm,
+}
+
+impl From<LangTool> for Source {
+    fn from(tool: LangTool) -> Self {
+        match tool {
+            LangTool::Cargo => Source::Cargo,
+            LangTool::Pipx => Source::Pipx,
+            LangTool::Npm => Source::Npm,
+        }
+    }
+}
+
+fn parse_user_name(name: &str) -> Result<String, String> {
+    if valid_user_name(name) {
+        Ok(name.to_string())
+    } else {
+        Err(format!("invalid user name `{name}`"))
+    }
+}
+
+/// Accept known error codes only, normalized to `DPKG-Exxx`.
+fn parse_error_code(code: &str) -> Result<String, String> {
+    explain(code)
+        .map(|info| info.code.to_string())
+        .ok_or_else(|| format!("unknown error code `{code}`; run `dpkg explain-error` to list them"))
+}
+
+/// How long `--wait-lock[=SECS]` waits: not at all without the flag, and without
+/// a limit when no SECS is given.
+pub fn lock_wait(wait_lock: Option<Option<u64>>) -> Option<Duration> {
+    wait_lock.map(|secs| secs.map_or(Duration::MAX, Duration::from_secs))
+}
+
+/// Whether `source` passes an `--only-source` filter; an empty filter selects everything.
+pub fn selects(filter: &[Source], source: Source) -> bool {
+    filter.is_empty() || filter.contains(&source)
 }
 
 impl Cli {
@@ -61,4 +283,16 @@ impl Cli {
             PathBuf::from(home).join(".config/dpkg/pkg.conf")
         }
     }
+
+    /// Reject option combinations clap cannot express: a global flag given before the
+    /// subcommand is not visible to the subcommand's `requires`.
+    pub fn check_combinations(&self) {
+        if let Some(Command::Sync { format: PlanFormat::Json, .. }) = self.command
+            && !self.dry_run
+        {
+            Cli::command()
+                .error(ErrorKind::MissingRequiredArgument, "--format json only applies to a plan; add --dry-run")
+                .exit();
+        }
+    }
 }
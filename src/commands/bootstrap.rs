@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use crate::config::{collect_escalation, collect_exclusions, collect_packages, parse_config};
+use crate::error::DpkgError;
+use crate::output;
+use crate::repos;
+use crate::system::{self, escalate, progress};
+
+fn pacstrap_bin() -> String {
+    std::env::var("PACSTRAP").unwrap_or_else(|_| "pacstrap".to_string())
+}
+
+/// Install the official packages declared for `--host` into a new root with pacstrap.
+/// Groups are expanded with the host's sync databases; the rest of the config is
+/// applied afterwards with `dpkg --root DIR sync`.
+pub fn run(config_path: &Path, dir: &Path, dry_run: bool, verbose: bool, quiet: bool) -> Result<(), DpkgError> {
+    if system::target().root.is_some() {
+        return Err(DpkgError::UnsupportedTarget(
+            "bootstrap installs into the directory it is given; drop --root (or DPKG_ROOT)".to_string(),
+        ));
+    }
+    let config = parse_config(config_path)?;
+    let hostname = system::get_hostname()?;
+    escalate::init(collect_escalation(&config, &hostname));
+    let (raw_official, desired_aur) = collect_packages(&config, &hostname);
+    let groups = system::get_group_members(&raw_official)?;
+    let exclusions = collect_exclusions(&config, &hostname);
+    let packages = system::expand_package_groups(&raw_official, &groups, &exclusions);
+
+    if packages.is_empty() {
+        return Err(DpkgError::InstallFailed(format!(
+            "No official packages are declared for {hostname}\n  Hint: Pass the target's hostname with --host"
+        )));
+    }
+    if !desired_aur.is_empty() && !quiet {
+        output::warning(&format!(
+            "Skipping {} AUR packages; pacstrap only installs from the repositories",
+            desired_aur.len()
+        ));
+    }
+
+    if dry_run {
+        if !quiet {
+            output::dry_run(&format!(
+                "Would run pacstrap into {} with {} packages for {hostname}:",
+                dir.display(),
+                packages.len()
+            ));
+            for package in &packages {
+                output::added(package, "");
+            }
+        }
+        return Ok(());
+    }
+
+    if verbose {
+        eprintln!("Running pacstrap into {} with {} packages...", dir.display(), packages.len());
+    }
+    let mut command = escalate::command(pacstrap_bin());
+    command.arg("-K").arg(dir).args(&packages);
+    let output = progress::run(&mut command).map_err(|e| escalate::spawn_error(&command, e))?;
+    if !output.status.success() {
+        return Err(system::pacman_error(&output.stderr, DpkgError::InstallFailed));
+    }
+
+    // Later `dpkg --root DIR` runs select the same host sections without --host
+    let hostname_file = dir.join("etc/hostname");
+    if !hostname_file.exists() {
+        repos::root_write(&hostname_file, &format!("{hostname}\n"), false)?;
+    }
+
+    if !quiet {
+        output::success(&format!(
+            "Bootstrapped {} with {} packages for {hostname}",
+            dir.display(),
+            packages.len()
+        ));
+        output::info(&format!(
+            "Apply the rest of the config with: dpkg --root {} sync",
+            dir.display()
+        ));
+    }
+    Ok(())
+}
@@ -1,22 +1,48 @@
 use std::collections::HashSet;
 use std::path::Path;
 
-use crate::config::{collect_packages, parse_config};
+use crate::archive;
+use crate::cli::{self, Source};
+use crate::commands::sync;
+use crate::config::{
+    collect_exclusions, collect_file_packages, collect_flatpaks, collect_held, collect_keys, collect_lang_packages, collect_local_packages,
+    collect_optdeps, collect_packages, collect_repos, collect_services, parse_config,
+};
 use crate::error::DpkgError;
+use crate::hold;
+use crate::keyring::{self, KeyState};
+use crate::local;
+use crate::lock;
 use crate::output;
+use crate::repos;
 use crate::system;
 
-pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
+pub fn run(config_path: &Path, only_source: &[Source], quiet: bool) -> Result<(), DpkgError> {
     let config = parse_config(config_path)?;
     let hostname = system::get_hostname()?;
     let (raw_official, desired_aur) = collect_packages(&config, &hostname);
     let groups = system::get_group_members(&raw_official)?;
-    let desired_official = system::expand_package_groups(&raw_official, &groups);
+    let exclusions = collect_exclusions(&config, &hostname);
+    let desired_official = system::expand_package_groups(&raw_official, &groups, &exclusions);
 
     let installed = system::get_explicitly_installed()?;
     let installed_set: HashSet<&str> = installed.iter().map(|s| s.as_str()).collect();
+    let local_builds =
+        local::inspect_all(config_path, &collect_local_packages(&config, &hostname))?;
+
+    let package_files =
+        archive::inspect_all(config_path, &collect_file_packages(&config, &hostname))?;
+
+    let optdeps = collect_optdeps(&config, &hostname);
 
     let mut all_desired: HashSet<&str> = HashSet::new();
+    all_desired.extend(optdeps.iter().map(|d| d.name.as_str()));
+    for build in &local_builds {
+        all_desired.extend(build.names.iter().map(|s| s.as_str()));
+    }
+    for file in &package_files {
+        all_desired.insert(file.name.as_str());
+    }
     for p in &desired_official {
         all_desired.insert(p.as_str());
     }
@@ -25,9 +51,11 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
     }
 
     let mut has_diff = false;
+    let shows = |source: Source| cli::selects(only_source, source);
+    let shows_pacman = shows(Source::Official) || shows(Source::Aur);
 
     // Missing official packages
-    for pkg in &desired_official {
+    for pkg in desired_official.iter().filter(|_| shows(Source::Official)) {
         if !installed_set.contains(pkg.as_str()) {
             if !quiet {
                 output::added(pkg, "// not installed");
@@ -37,7 +65,7 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
     }
 
     // Missing AUR packages
-    for pkg in &desired_aur {
+    for pkg in desired_aur.iter().filter(|_| shows(Source::Aur)) {
         if !installed_set.contains(pkg.as_str()) {
             if !quiet {
                 output::added(&format!("aur:{pkg}"), "// not installed (AUR)");
@@ -46,8 +74,64 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
         }
     }
 
+    // Optional dependencies that are not installed, even as a dependency
+    if shows_pacman && !optdeps.is_empty() {
+        let all_installed = system::get_all_installed()?;
+        for dep in optdeps.iter().filter(|d| !all_installed.contains(&d.name)) {
+            if !quiet {
+                output::added(&dep.name, &format!("// not installed (optional for {})", dep.parent));
+            }
+            has_diff = true;
+        }
+    }
+
+    // Local PKGBUILDs that are missing, outdated or changed since their last build
+    if shows(Source::Local) && !local_builds.is_empty() {
+        let versions = system::get_installed_versions()?;
+        for build in local::pending(&local_builds, &versions)? {
+            if !quiet {
+                let installed: Vec<&String> =
+                    build.names.iter().filter_map(|n| versions.get(n)).collect();
+                if installed.is_empty() {
+                    output::added(
+                        &build.to_string(),
+                        &format!("// {} not installed", build.version),
+                    );
+                } else if installed.iter().all(|v| **v == build.version) {
+                    output::changed(&build.to_string(), "// PKGBUILD changed, would rebuild");
+                } else {
+                    output::changed(
+                        &build.to_string(),
+                        &format!("// {} installed, PKGBUILD has {}", installed[0], build.version),
+                    );
+                }
+            }
+            has_diff = true;
+        }
+    }
+
+    // Package files whose package is missing or at another version
+    if shows(Source::File) && !package_files.is_empty() {
+        let versions = system::get_installed_versions()?;
+        for file in archive::pending(&package_files, &versions) {
+            if !quiet {
+                match versions.get(&file.name) {
+                    None => output::added(
+                        &file.to_string(),
+                        &format!("// {} {} not installed (file)", file.name, file.version),
+                    ),
+                    Some(installed) => output::changed(
+                        &file.to_string(),
+                        &format!("// {installed} installed, file has {} (file)", file.version),
+                    ),
+                }
+            }
+            has_diff = true;
+        }
+    }
+
     // Installed but not in config — would be removed
-    for pkg in &installed {
+    for pkg in installed.iter().filter(|_| shows_pacman) {
         if !all_desired.contains(pkg.as_str()) {
             if !quiet {
                 output::removed(pkg, "// not in config, would be removed");
@@ -55,7 +139,7 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
             has_diff = true;
         }
     }
-    let orphans = system::get_orphans()?;
+    let orphans = if shows_pacman { system::get_orphans()? } else { Vec::new() };
     for pkg in &orphans {
         if !all_desired.contains(pkg.as_str()) && !installed_set.contains(pkg.as_str()) {
             if !quiet {
@@ -65,6 +149,138 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
         }
     }
 
+    // Flatpak apps: missing and undeclared
+    if shows(Source::Flatpak) {
+        let (flatpak_missing, flatpak_extra) =
+            sync::plan_flatpaks(&collect_flatpaks(&config, &hostname))?;
+        for app in &flatpak_missing {
+            if !quiet {
+                output::added(&app.to_string(), "// not installed (Flatpak)");
+            }
+            has_diff = true;
+        }
+        for app in &flatpak_extra {
+            if !quiet {
+                output::removed(&app.to_string(), "// not in config, would be removed");
+            }
+            has_diff = true;
+        }
+    }
+
+    // cargo/pipx/npm tools: missing and undeclared
+    let desired_lang: Vec<_> = collect_lang_packages(&config, &hostname)
+        .into_iter()
+        .filter(|p| shows(p.tool.into()))
+        .collect();
+    let (lang_missing, lang_extra) = sync::plan_lang_packages(&desired_lang)?;
+    for pkg in &lang_missing {
+        if !quiet {
+            output::added(&pkg.to_string(), &format!("// not installed ({})", pkg.tool.name()));
+        }
+        has_diff = true;
+    }
+    for pkg in &lang_extra {
+        if !quiet {
+            output::removed(&pkg.to_string(), "// not in config, would be removed");
+        }
+        has_diff = true;
+    }
+
+    // Services and lockfile versions are not package sources; a filtered diff skips them
+    if !only_source.is_empty() {
+        if !has_diff && !quiet {
+            output::success("System is in sync with configuration");
+        }
+        return Ok(());
+    }
+
+    // Managed pacman repositories
+    let repo_changes = repos::plan(&collect_repos(&config, &hostname))?;
+    if !quiet {
+        for repo in &repo_changes.added {
+            output::added(&repo.to_string(), &format!("// {} not configured", repo.server));
+        }
+        for repo in &repo_changes.changed {
+            output::changed(&repo.to_string(), &format!("// would change to {}", repo.server));
+        }
+        for name in &repo_changes.removed {
+            output::removed(&format!("repo:{name}"), "// not in config, would be removed");
+        }
+        if repo_changes.include_missing {
+            output::changed(
+                &repos::pacman_conf_path().display().to_string(),
+                &format!("// would Include {}", repos::include_path().display()),
+            );
+        }
+    }
+    has_diff |= !repo_changes.is_empty();
+
+    // Holds missing from or left over in the managed IgnorePkg include
+    let hold_changes = hold::plan(&collect_held(&config, &hostname))?;
+    if !quiet {
+        for name in &hold_changes.added {
+            output::added(&format!("{name} [hold]"), "// not in IgnorePkg");
+        }
+        for name in &hold_changes.removed {
+            output::removed(&format!("{name} [hold]"), "// no longer held in config");
+        }
+        if hold_changes.include_missing {
+            output::changed(
+                &repos::pacman_conf_path().display().to_string(),
+                &format!("// would Include {}", hold::include_path().display()),
+            );
+        }
+    }
+    has_diff |= !hold_changes.is_empty();
+
+    // Declared keys pacman does not trust yet
+    let pending_keys = keyring::plan(config_path, &collect_keys(&config, &hostname))?;
+    if !quiet {
+        for key in &pending_keys {
+            match key.state {
+                KeyState::Missing => output::added(&key.to_string(), "// not in keyring"),
+                KeyState::Untrusted => output::changed(&key.to_string(), "// not locally signed"),
+            }
+        }
+    }
+    has_diff |= !pending_keys.is_empty();
+
+    // Services not in their declared state
+    let services = collect_services(&config, &hostname);
+    let enabled = sync::query_enabled_services(&services)?;
+    for svc in sync::compute_service_changes(&services, &enabled) {
+        if !quiet {
+            if svc.enabled {
+                output::added(&svc.to_string(), "// not enabled");
+            } else {
+                output::removed(&svc.to_string(), "// enabled, would be disabled");
+            }
+        }
+        has_diff = true;
+    }
+
+    // Installed versions that differ from the lockfile
+    if let Some(lockfile) = lock::load(&lock::lock_path(config_path))?
+        && let Some(locked) = lockfile.for_host(&hostname)
+    {
+        let versions = system::get_installed_versions()?;
+        for pkg in desired_official.iter().chain(&desired_aur) {
+            let (Some(installed), Some(locked)) = (versions.get(pkg), locked.get(pkg.as_str()))
+            else {
+                continue;
+            };
+            if *installed != locked.version {
+                if !quiet {
+                    output::changed(
+                        pkg,
+                        &format!("// {installed} installed, {} locked", locked.version),
+                    );
+                }
+                has_diff = true;
+            }
+        }
+    }
+
     if !has_diff && !quiet {
         output::success("System is in sync with configuration");
     }
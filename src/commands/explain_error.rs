@@ -0,0 +1,19 @@
+use crate::error::{explain, DpkgError, ERRORS};
+use crate::output;
+
+/// Describe one error code, or list every code with its exit code.
+pub fn run(code: Option<&str>) -> Result<(), DpkgError> {
+    let Some(info) = code.and_then(explain) else {
+        output::plain(&format!("{:<10} {:<5} Meaning", "Code", "Exit"));
+        for info in ERRORS {
+            output::plain(&format!("{:<10} {:<5} {}", info.code, info.exit_code, info.title));
+        }
+        return Ok(());
+    };
+
+    output::info(&format!("{}: {}", info.code, info.title));
+    output::plain(&format!("Exit code: {}", info.exit_code));
+    output::plain("");
+    output::plain(info.explanation);
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use crate::error::DpkgError;
+use crate::output;
+use crate::state::{self, history, history::JournalEntry};
+
+pub fn run(last: Option<usize>, show: Option<u64>, quiet: bool) -> Result<(), DpkgError> {
+    let entries = history::read_entries()?;
+
+    if let Some(id) = show {
+        let entry = entries
+            .iter()
+            .find(|e| e.id == id)
+            .ok_or_else(|| DpkgError::State(format!("No history entry with ID {id}")))?;
+        if !quiet {
+            print_entry(entry);
+        }
+        return Ok(());
+    }
+
+    if quiet {
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        output::info("No sync history recorded yet");
+        return Ok(());
+    }
+
+    let skip = last.map_or(0, |n| entries.len().saturating_sub(n));
+    output::plain(&format!(
+        "{:>4}  {:<19}  {:<16}  {:>8}  {:>8}  Result",
+        "ID", "Date (UTC)", "Host", "Install", "Remove"
+    ));
+    for entry in entries.iter().skip(skip) {
+        let installs = entry.plan.to_install_official.len() + entry.plan.to_install_aur.len();
+        let line = format!(
+            "{:>4}  {:<19}  {:<16}  {:>8}  {:>8}  {}",
+            entry.id,
+            state::format_timestamp(entry.timestamp),
+            entry.hostname,
+            installs,
+            entry.plan.to_remove.len(),
+            if entry.succeeded() { "ok" } else { "failed" }
+        );
+        if entry.succeeded() {
+            output::plain(&line);
+        } else {
+            output::warning(&line);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_entry(entry: &JournalEntry) {
+    output::info(&format!("Sync #{}", entry.id));
+    output::plain(&format!("  Date:     {} UTC", state::format_timestamp(entry.timestamp)));
+    output::plain(&format!("  Host:     {}", entry.hostname));
+    output::plain(&format!("  Config:   {}", entry.config_path));
+    output::plain(&format!("  SHA-256:  {}", entry.config_hash));
+    println!();
+
+    output::plain("Plan:");
+    for pkg in &entry.plan.to_install_official {
+        output::added(pkg, "// install");
+    }
+    for pkg in &entry.plan.to_install_aur {
+        output::added(&format!("aur:{pkg}"), "// install (AUR)");
+    }
+    for pkg in &entry.plan.to_remove {
+        output::removed(pkg, "// remove");
+    }
+    println!();
+
+    output::plain("Steps:");
+    for step in &entry.steps {
+        match &step.error {
+            None => output::success(&format!("  ok      {}", step.name)),
+            Some(err) => match &step.code {
+                Some(code) => output::error(&format!("  failed  {} [{code}]: {err}", step.name)),
+                None => output::error(&format!("  failed  {}: {err}", step.name)),
+            },
+        }
+    }
+    println!();
+
+    output::plain("Package versions:");
+    if entry.versions.is_empty() {
+        output::plain("  (no changes)");
+    }
+    for change in &entry.versions {
+        let before = change.before.as_deref().unwrap_or("-");
+        let after = change.after.as_deref().unwrap_or("-");
+        output::plain(&format!("  {:<30} {before} -> {after}", change.name));
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::cli::LintFormat;
+use crate::config::{parse_config_str, read_config, PackageSource};
+use crate::error::DpkgError;
+use crate::lint::{self, Finding, LintContext, Severity, RULES};
+use crate::output;
+use crate::system;
+
+/// Check the config for redundant, conflicting and suspicious declarations. Fails if
+/// any finding is an error.
+pub fn run(config_path: &Path, format: LintFormat, quiet: bool) -> Result<(), DpkgError> {
+    let input = read_config(config_path)?;
+    let config = parse_config_str(&input)?;
+
+    let names: Vec<String> = config
+        .sections
+        .iter()
+        .flat_map(|s| s.packages.iter())
+        .filter(|p| matches!(p.source, PackageSource::Official | PackageSource::Aur))
+        .map(|p| p.name.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let context = LintContext {
+        hostname: system::get_hostname()?,
+        repo_packages: system::get_repo_info(&names)?.into_keys().collect(),
+        depends: system::get_depends(&names)?,
+        groups: system::get_group_members(&names)?.into_keys().collect(),
+    };
+
+    let findings = lint::lint(&config, &lint::parse_directives(&input), &context);
+
+    match format {
+        LintFormat::Text => {
+            if !quiet {
+                print_text(config_path, &findings);
+            }
+        }
+        LintFormat::Json => {
+            let json = serde_json::to_string_pretty(&findings)
+                .map_err(|e| DpkgError::State(format!("Cannot serialize findings: {e}")))?;
+            println!("{json}");
+        }
+        LintFormat::Sarif => {
+            let json = serde_json::to_string_pretty(&sarif(config_path, &findings))
+                .map_err(|e| DpkgError::State(format!("Cannot serialize findings: {e}")))?;
+            println!("{json}");
+        }
+    }
+
+    let errors = findings.iter().filter(|f| f.severity == Severity::Error).count();
+    if errors > 0 {
+        return Err(DpkgError::Lint(format!("{errors} error(s) in {}", config_path.display())));
+    }
+    Ok(())
+}
+
+fn print_text(config_path: &Path, findings: &[Finding]) {
+    if findings.is_empty() {
+        output::success(&format!("No lint findings in {}", config_path.display()));
+        return;
+    }
+    for finding in findings {
+        let msg = format!(
+            "{}:{}: {}[{}]: {}",
+            config_path.display(),
+            finding.line,
+            finding.severity,
+            finding.rule,
+            finding.message
+        );
+        match finding.severity {
+            Severity::Error => output::error(&msg),
+            Severity::Warning => output::warning(&msg),
+            Severity::Note => output::plain(&msg),
+        }
+    }
+    let count = |severity| findings.iter().filter(|f| f.severity == severity).count();
+    output::plain(&format!(
+        "\n{} error(s), {} warning(s), {} note(s)",
+        count(Severity::Error),
+        count(Severity::Warning),
+        count(Severity::Note)
+    ));
+}
+
+/// A SARIF 2.1.0 log with one run, for code scanning tools.
+fn sarif(config_path: &Path, findings: &[Finding]) -> serde_json::Value {
+    let rules: Vec<_> = RULES
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.id,
+                "shortDescription": { "text": rule.description },
+                "defaultConfiguration": { "level": sarif_level(rule.severity) },
+            })
+        })
+        .collect();
+    let results: Vec<_> = findings
+        .iter()
+        .map(|finding| {
+            json!({
+                "ruleId": finding.rule,
+                "level": sarif_level(finding.severity),
+                "message": { "text": finding.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": config_path.display().to_string() },
+                        "region": { "startLine": finding.line },
+                    },
+                }],
+            })
+        })
+        .collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "dpkg",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+fn sarif_level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Note => "note",
+        Severity::Warning => "warning",
+        Severity::Error => "error",
+    }
+}
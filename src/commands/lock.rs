@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use crate::config::{collect_exclusions, collect_packages, parse_config};
+use crate::error::DpkgError;
+use crate::lock::{self, LockFile, LockedPackage};
+use crate::output;
+use crate::system;
+
+pub fn run(config_path: &Path, dry_run: bool, quiet: bool) -> Result<(), DpkgError> {
+    let config = parse_config(config_path)?;
+    let hostname = system::get_hostname()?;
+    let (raw_official, desired_aur) = collect_packages(&config, &hostname);
+    let groups = system::get_group_members(&raw_official)?;
+    let exclusions = collect_exclusions(&config, &hostname);
+    let desired_official = system::expand_package_groups(&raw_official, &groups, &exclusions);
+
+    let installed = system::get_installed_versions()?;
+    let repo_info = system::get_repo_info(&desired_official)?;
+
+    let mut locked = Vec::new();
+    let mut unresolved = Vec::new();
+
+    // Installed versions are what this host was tested with; fall back to the repo version.
+    for name in &desired_official {
+        let Some(info) = repo_info.get(name) else {
+            unresolved.push(name.clone());
+            continue;
+        };
+        locked.push(LockedPackage {
+            name: name.clone(),
+            version: installed.get(name).unwrap_or(&info.version).clone(),
+            repo: info.repo.clone(),
+            commit: None,
+        });
+    }
+
+    // AUR packages can only be locked once built and installed
+    for name in &desired_aur {
+        let Some(version) = installed.get(name) else {
+            unresolved.push(format!("aur:{name}"));
+            continue;
+        };
+        locked.push(LockedPackage {
+            name: name.clone(),
+            version: version.clone(),
+            repo: "aur".to_string(),
+            commit: system::aur_commit(name),
+        });
+    }
+
+    if !unresolved.is_empty() {
+        return Err(DpkgError::Lock(format!(
+            "Cannot determine versions for: {}\n  Hint: Official packages must exist in a sync database; AUR packages must be installed",
+            unresolved.join(", ")
+        )));
+    }
+
+    let path = lock::lock_path(config_path);
+    let mut lockfile = lock::load(&path)?.unwrap_or_else(LockFile::new);
+    let count = locked.len();
+    lockfile.hosts.insert(hostname.clone(), locked);
+
+    if dry_run {
+        if !quiet {
+            output::dry_run(&format!(
+                "Would lock {count} packages for {hostname} in {}",
+                path.display()
+            ));
+        }
+        return Ok(());
+    }
+
+    lock::save(&path, &lockfile)?;
+    if !quiet {
+        output::success(&format!(
+            "Locked {count} packages for {hostname} in {}",
+            path.display()
+        ));
+    }
+
+    Ok(())
+}
@@ -1,4 +1,11 @@
+pub mod bootstrap;
 pub mod diff;
+pub mod explain_error;
+pub mod history;
+pub mod lint;
+pub mod lock;
+pub mod rollback;
 pub mod status;
 pub mod sync;
 pub mod validate;
+pub mod why;
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::commands::sync;
+use crate::config::{collect_escalation, parse_config};
+use crate::dblock;
+use crate::error::DpkgError;
+use crate::output;
+use crate::state::{self, generations, generations::Generation};
+use crate::system::{self, escalate, InstalledPackage};
+
+pub struct RollbackOptions {
+    pub dry_run: bool,
+    pub verbose: bool,
+    pub quiet: bool,
+    pub no_confirm: bool,
+    pub wait_lock: Option<Duration>,
+}
+
+/// A package version that must be restored but is no longer in the cache.
+#[derive(Debug, PartialEq)]
+pub struct Uncached {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RollbackPlan {
+    /// Cached archives to install with `pacman -U` (missing or different version).
+    pub to_install: Vec<PathBuf>,
+    pub uncached: Vec<Uncached>,
+    pub to_remove: Vec<String>,
+    pub to_mark_explicit: Vec<String>,
+    pub to_mark_deps: Vec<String>,
+}
+
+impl RollbackPlan {
+    pub fn is_empty(&self) -> bool {
+        self.to_install.is_empty()
+            && self.to_remove.is_empty()
+            && self.to_mark_explicit.is_empty()
+            && self.to_mark_deps.is_empty()
+    }
+}
+
+/// Pure computation: what must change to turn `current` back into `target`,
+/// using only package archives present in `cached_files`.
+pub fn compute_rollback_plan(
+    target: &[InstalledPackage],
+    current: &[InstalledPackage],
+    cached_files: &[PathBuf],
+) -> RollbackPlan {
+    let current_map: HashMap<&str, &InstalledPackage> =
+        current.iter().map(|p| (p.name.as_str(), p)).collect();
+    let target_map: HashMap<&str, &InstalledPackage> =
+        target.iter().map(|p| (p.name.as_str(), p)).collect();
+
+    let mut plan = RollbackPlan {
+        to_install: Vec::new(),
+        uncached: Vec::new(),
+        to_remove: Vec::new(),
+        to_mark_explicit: Vec::new(),
+        to_mark_deps: Vec::new(),
+    };
+
+    for pkg in target {
+        let installed = current_map.get(pkg.name.as_str());
+        let mut present_after = installed.is_some();
+        if installed.is_none_or(|p| p.version != pkg.version) {
+            match system::find_package_file(cached_files, &pkg.name, &pkg.version) {
+                Some(file) => {
+                    plan.to_install.push(file.clone());
+                    present_after = true;
+                }
+                None => plan.uncached.push(Uncached {
+                    name: pkg.name.clone(),
+                    version: pkg.version.clone(),
+                }),
+            }
+        }
+        if !present_after {
+            continue;
+        }
+
+        // pacman -U installs new packages as explicit and keeps the reason on up/downgrades
+        let explicit_after = installed.is_none_or(|p| p.explicit);
+        if pkg.explicit && !explicit_after {
+            plan.to_mark_explicit.push(pkg.name.clone());
+        } else if !pkg.explicit && explicit_after {
+            plan.to_mark_deps.push(pkg.name.clone());
+        }
+    }
+
+    for pkg in current {
+        if !target_map.contains_key(pkg.name.as_str()) {
+            plan.to_remove.push(pkg.name.clone());
+        }
+    }
+
+    plan
+}
+
+pub fn run(
+    config_path: &Path,
+    generation: Option<u64>,
+    list: bool,
+    options: &RollbackOptions,
+) -> Result<(), DpkgError> {
+    let ids = generations::list_ids()?;
+
+    if list {
+        if !options.quiet {
+            print_generations(&ids)?;
+        }
+        return Ok(());
+    }
+
+    // Default target: the generation before the latest one
+    let target_id = match generation {
+        Some(id) => id,
+        None => ids
+            .len()
+            .checked_sub(2)
+            .map(|i| ids[i])
+            .ok_or_else(|| DpkgError::State("No previous generation to roll back to".to_string()))?,
+    };
+    let target = generations::load(target_id)?;
+
+    // The escalation method comes from the config, as for sync. A broken config must
+    // not stand in the way of undoing the sync it broke, so it falls back to detection
+    let configured = parse_config(config_path)
+        .ok()
+        .and_then(|config| collect_escalation(&config, &target.hostname));
+    escalate::init(configured);
+
+    // Locked before reading the system, so the confirmed plan is the one applied
+    let _sync_lock = if options.dry_run {
+        None
+    } else {
+        let lock = dblock::acquire(options.wait_lock, options.quiet)?;
+        dblock::wait_for_pacman(options.wait_lock, options.quiet)?;
+        Some(lock)
+    };
+
+    let current = system::get_installed_packages()?;
+    let cached = system::list_cached_packages(&system::pacman_cache_dir());
+    let plan = compute_rollback_plan(&target.packages, &current, &cached);
+
+    if !options.quiet {
+        print_plan(&target, &plan);
+    }
+
+    if !plan.uncached.is_empty() {
+        output::warning(&format!(
+            "{} package versions from generation {target_id} are no longer cached in {} and will not be restored:",
+            plan.uncached.len(),
+            system::pacman_cache_dir().display()
+        ));
+        for pkg in &plan.uncached {
+            output::plain(&format!("  {} {}", pkg.name, pkg.version));
+        }
+    }
+
+    if options.dry_run {
+        if !options.quiet {
+            output::dry_run("No changes made (dry run)");
+        }
+        return Ok(());
+    }
+
+    if plan.is_empty() {
+        if !options.quiet {
+            output::success(&format!("System already matches generation {target_id}"));
+        }
+        return Ok(());
+    }
+
+    if !options.no_confirm && !confirm_rollback(target_id)? {
+        return Err(DpkgError::UserCancelled);
+    }
+
+    escalate::validate()?;
+
+    system::install_files(&plan.to_install, options.verbose)?;
+    system::remove_packages(&plan.to_remove, options.verbose)?;
+    system::mark_as_explicit(&plan.to_mark_explicit, options.verbose)?;
+    system::mark_as_deps(&plan.to_mark_deps, options.verbose)?;
+
+    sync::record_generation(&target.hostname, options.quiet);
+
+    if !options.quiet {
+        output::success(&format!("Rolled back to generation {target_id}"));
+    }
+
+    Ok(())
+}
+
+fn print_generations(ids: &[u64]) -> Result<(), DpkgError> {
+    if ids.is_empty() {
+        output::info("No generations recorded yet");
+        return Ok(());
+    }
+
+    output::plain(&format!(
+        "{:>4}  {:<19}  {:<16}  {:>8}",
+        "Gen", "Date (UTC)", "Host", "Packages"
+    ));
+    for &id in ids {
+        let generation = generations::load(id)?;
+        output::plain(&format!(
+            "{:>4}  {:<19}  {:<16}  {:>8}",
+            generation.id,
+            state::format_timestamp(generation.timestamp),
+            generation.hostname,
+            generation.packages.len()
+        ));
+    }
+    Ok(())
+}
+
+fn print_plan(target: &Generation, plan: &RollbackPlan) {
+    output::info(&format!(
+        "Rolling back to generation {} ({} UTC)",
+        target.id,
+        state::format_timestamp(target.timestamp)
+    ));
+    println!();
+
+    for file in &plan.to_install {
+        let name = file.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        output::added(&name, "// from cache");
+    }
+    for pkg in &plan.to_remove {
+        output::removed(pkg, "// not in generation");
+    }
+    for pkg in &plan.to_mark_explicit {
+        output::plain(&format!("  {pkg:<30} // mark as explicit"));
+    }
+    for pkg in &plan.to_mark_deps {
+        output::plain(&format!("  {pkg:<30} // mark as dependency"));
+    }
+    if plan.is_empty() {
+        output::plain("  (no changes)");
+    }
+    println!();
+}
+
+fn confirm_rollback(id: u64) -> Result<bool, DpkgError> {
+    print!("Proceed with rollback to generation {id}? [y/N]: ");
+    io::stdout().flush().ok();
+
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .map_err(|_| DpkgError::UserCancelled)?;
+
+    let answer = input.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pkg(name: &str, version: &str, explicit: bool) -> InstalledPackage {
+        InstalledPackage {
+            name: name.to_string(),
+            version: version.to_string(),
+            explicit,
+        }
+    }
+
+    fn cache(names: &[&str]) -> Vec<PathBuf> {
+        names
+            .iter()
+            .map(|n| PathBuf::from(format!("/var/cache/pacman/pkg/{n}")))
+            .collect()
+    }
+
+    #[test]
+    fn restores_removed_package_from_cache() {
+        let target = vec![pkg("base", "3-2", true), pkg("tlp", "1.6-1", true)];
+        let current = vec![pkg("base", "3-2", true)];
+        let files = cache(&["tlp-1.6-1-any.pkg.tar.zst", "tlp-1.7-1-any.pkg.tar.zst"]);
+
+        let plan = compute_rollback_plan(&target, &current, &files);
+        assert_eq!(
+            plan.to_install,
+            cache(&["tlp-1.6-1-any.pkg.tar.zst"])
+        );
+        assert!(plan.uncached.is_empty());
+        assert!(plan.to_remove.is_empty());
+        assert!(plan.to_mark_deps.is_empty());
+    }
+
+    #[test]
+    fn downgrades_changed_version() {
+        let target = vec![pkg("git", "2.44.0-1", true)];
+        let current = vec![pkg("git", "2.45.0-1", true)];
+        let files = cache(&["git-2.44.0-1-x86_64.pkg.tar.zst", "git-2.44.0-1-x86_64.pkg.tar.zst.sig"]);
+
+        let plan = compute_rollback_plan(&target, &current, &files);
+        assert_eq!(plan.to_install, cache(&["git-2.44.0-1-x86_64.pkg.tar.zst"]));
+    }
+
+    #[test]
+    fn reports_uncached_versions() {
+        let target = vec![pkg("steam", "1.0-1", true)];
+        let plan = compute_rollback_plan(&target, &[], &cache(&["steam-1.0-10-x86_64.pkg.tar.zst"]));
+        assert!(plan.to_install.is_empty());
+        assert_eq!(
+            plan.uncached,
+            vec![Uncached {
+                name: "steam".to_string(),
+                version: "1.0-1".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn removes_packages_added_since_generation() {
+        let target = vec![pkg("base", "3-2", true)];
+        let current = vec![pkg("base", "3-2", true), pkg("kilo-bin", "1.0-1", true)];
+        let plan = compute_rollback_plan(&target, &current, &[]);
+        assert_eq!(plan.to_remove, vec!["kilo-bin".to_string()]);
+    }
+
+    #[test]
+    fn restores_install_reasons() {
+        let target = vec![pkg("glibc", "2.39-1", false), pkg("git", "2.45.0-1", true)];
+        let current = vec![pkg("glibc", "2.39-1", true), pkg("git", "2.45.0-1", false)];
+        let plan = compute_rollback_plan(&target, &current, &[]);
+        assert_eq!(plan.to_mark_deps, vec!["glibc".to_string()]);
+        assert_eq!(plan.to_mark_explicit, vec!["git".to_string()]);
+    }
+
+    #[test]
+    fn reinstalled_dependency_is_marked_as_dep() {
+        let target = vec![pkg("libfoo", "1.0-1", false)];
+        let files = cache(&["libfoo-1.0-1-x86_64.pkg.tar.zst"]);
+        let plan = compute_rollback_plan(&target, &[], &files);
+        assert_eq!(plan.to_mark_deps, vec!["libfoo".to_string()]);
+    }
+
+    #[test]
+    fn identical_state_is_empty_plan() {
+        let packages = vec![pkg("base", "3-2", true), pkg("glibc", "2.39-1", false)];
+        let plan = compute_rollback_plan(&packages, &packages, &[]);
+        assert!(plan.is_empty());
+        assert!(plan.uncached.is_empty());
+    }
+}
@@ -1,9 +1,19 @@
 use std::collections::HashSet;
 use std::path::Path;
 
-use crate::config::{collect_packages, parse_config, Header, PackageSource};
+use crate::archive;
+use crate::commands::sync;
+use crate::config::{
+    collect_exclusions, collect_file_packages, collect_flatpaks, collect_held, collect_keys, collect_lang_packages, collect_local_packages,
+    collect_optdeps, collect_packages, collect_repos, collect_services, parse_config, Header, LangTool, PackageSource,
+};
 use crate::error::DpkgError;
+use crate::hold;
+use crate::keyring::{self, KeyState};
+use crate::local;
 use crate::output;
+use crate::repos;
+use crate::state::groups;
 use crate::system;
 
 pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
@@ -11,7 +21,8 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
     let hostname = system::get_hostname()?;
     let (raw_official, desired_aur) = collect_packages(&config, &hostname);
     let groups = system::get_group_members(&raw_official)?;
-    let desired_official = system::expand_package_groups(&raw_official, &groups);
+    let exclusions = collect_exclusions(&config, &hostname);
+    let desired_official = system::expand_package_groups(&raw_official, &groups, &exclusions);
 
     let installed = system::get_explicitly_installed()?;
     let installed_set: HashSet<&str> = installed.iter().map(|s| s.as_str()).collect();
@@ -39,7 +50,35 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
         .map(|s| s.packages.len())
         .sum();
 
-    let total = desired_official.len() + desired_aur.len();
+    let local_builds =
+        local::inspect_all(config_path, &collect_local_packages(&config, &hostname))?;
+    let local_pending = if local_builds.is_empty() {
+        Vec::new()
+    } else {
+        local::pending(&local_builds, &system::get_installed_versions()?)?
+    };
+    let package_files =
+        archive::inspect_all(config_path, &collect_file_packages(&config, &hostname))?;
+    let files_pending = if package_files.is_empty() {
+        Vec::new()
+    } else {
+        archive::pending(&package_files, &system::get_installed_versions()?)
+    };
+    let optdeps = collect_optdeps(&config, &hostname);
+    let optdeps_missing: Vec<_> = if optdeps.is_empty() {
+        Vec::new()
+    } else {
+        let all_installed = system::get_all_installed()?;
+        optdeps.iter().filter(|d| !all_installed.contains(&d.name)).collect()
+    };
+    let lang_packages = collect_lang_packages(&config, &hostname);
+    let (lang_missing, lang_extra) = sync::plan_lang_packages(&lang_packages)?;
+
+    let total = desired_official.len()
+        + desired_aur.len()
+        + local_builds.len()
+        + package_files.len()
+        + lang_packages.len();
 
     output::plain("Package Summary:");
     output::plain(&format!("  Common packages (## *): {common_count}"));
@@ -59,6 +98,31 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
 
     output::plain(&format!("  Installed (official): {installed_official_count}"));
     output::plain(&format!("  Installed (AUR): {installed_aur_count}"));
+    if !local_builds.is_empty() {
+        output::plain(&format!(
+            "  Installed (local): {}",
+            local_builds.len() - local_pending.len()
+        ));
+    }
+    if !package_files.is_empty() {
+        output::plain(&format!(
+            "  Installed (file): {}",
+            package_files.len() - files_pending.len()
+        ));
+    }
+    if !optdeps.is_empty() {
+        output::plain(&format!(
+            "  Installed (optional deps): {}",
+            optdeps.len() - optdeps_missing.len()
+        ));
+    }
+    for tool in LangTool::ALL {
+        let declared = lang_packages.iter().filter(|p| p.tool == tool).count();
+        if declared > 0 {
+            let missing = lang_missing.iter().filter(|p| p.tool == tool).count();
+            output::plain(&format!("  Installed ({}): {}", tool.name(), declared - missing));
+        }
+    }
     println!();
 
     // Missing packages
@@ -70,7 +134,12 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
         .iter()
         .filter(|p| !installed_set.contains(p.as_str()))
         .collect();
-    let missing_count = missing_official.len() + missing_aur.len();
+    let missing_count = missing_official.len()
+        + missing_aur.len()
+        + local_pending.len()
+        + files_pending.len()
+        + optdeps_missing.len()
+        + lang_missing.len();
 
     output::plain(&format!("  Missing: {missing_count}"));
     for pkg in &missing_official {
@@ -79,6 +148,18 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
     for pkg in &missing_aur {
         output::plain(&format!("    - aur:{pkg}"));
     }
+    for dep in &optdeps_missing {
+        output::plain(&format!("    - {dep}"));
+    }
+    for build in &local_pending {
+        output::plain(&format!("    - {build} ({})", build.version));
+    }
+    for file in &files_pending {
+        output::plain(&format!("    - {file} ({})", file.version));
+    }
+    for pkg in &lang_missing {
+        output::plain(&format!("    - {pkg}"));
+    }
 
     // Packages to remove (installed but not in config)
     let mut all_desired: HashSet<&str> = HashSet::new();
@@ -88,6 +169,13 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
     for p in &desired_aur {
         all_desired.insert(p.as_str());
     }
+    for build in &local_builds {
+        all_desired.extend(build.names.iter().map(|s| s.as_str()));
+    }
+    for file in &package_files {
+        all_desired.insert(file.name.as_str());
+    }
+    all_desired.extend(optdeps.iter().map(|d| d.name.as_str()));
     let unwanted_explicit: Vec<&String> = installed
         .iter()
         .filter(|p| !all_desired.contains(p.as_str()))
@@ -97,7 +185,7 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
         .iter()
         .filter(|p| !all_desired.contains(p.as_str()) && !installed_set.contains(p.as_str()))
         .collect();
-    let to_remove_count = unwanted_explicit.len() + unwanted_orphans.len();
+    let to_remove_count = unwanted_explicit.len() + unwanted_orphans.len() + lang_extra.len();
 
     println!();
     output::plain(&format!("  Would remove: {to_remove_count}"));
@@ -107,6 +195,126 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
     for pkg in &unwanted_orphans {
         output::plain(&format!("    - {pkg}"));
     }
+    for pkg in &lang_extra {
+        output::plain(&format!("    - {pkg}"));
+    }
+
+    // Group membership changes since the last sync
+    let group_drift = groups::compute_drift(&groups::load()?, &groups, &exclusions);
+    for change in &group_drift {
+        println!();
+        if !change.added.is_empty() {
+            output::warning(&format!(
+                "  Group {} gained members since last sync: {}",
+                change.group,
+                change.added.join(", ")
+            ));
+        }
+        if !change.removed.is_empty() {
+            output::plain(&format!(
+                "  Group {} lost members since last sync: {}",
+                change.group,
+                change.removed.join(", ")
+            ));
+        }
+    }
+
+    // Flatpak apps
+    let flatpaks = collect_flatpaks(&config, &hostname);
+    let (flatpak_missing, flatpak_extra) = sync::plan_flatpaks(&flatpaks)?;
+    if !flatpaks.is_empty() || !flatpak_extra.is_empty() {
+        println!();
+        output::plain(&format!("  Flatpak apps declared: {}", flatpaks.len()));
+        output::plain(&format!("  Flatpak missing: {}", flatpak_missing.len()));
+        for app in &flatpak_missing {
+            output::plain(&format!("    - {app}"));
+        }
+        output::plain(&format!("  Flatpak would remove: {}", flatpak_extra.len()));
+        for app in &flatpak_extra {
+            output::plain(&format!("    - {app}"));
+        }
+    }
+
+    // Repositories
+    let desired_repos = collect_repos(&config, &hostname);
+    let repo_changes = repos::plan(&desired_repos)?;
+    if !desired_repos.is_empty() || !repo_changes.is_empty() {
+        println!();
+        output::plain(&format!("  Repositories declared: {}", desired_repos.len()));
+        let out_of_sync = repo_changes.added.len()
+            + repo_changes.changed.len()
+            + repo_changes.removed.len();
+        output::plain(&format!("  Repositories out of sync: {out_of_sync}"));
+        if repo_changes.include_missing {
+            output::plain(&format!(
+                "    - {} is not included from {}",
+                repos::include_path().display(),
+                repos::pacman_conf_path().display()
+            ));
+        }
+    }
+
+    // Held packages, and whether the repositories have moved on
+    let held = collect_held(&config, &hostname);
+    if !held.is_empty() {
+        let hold_changes = hold::plan(&held)?;
+        let versions = system::get_installed_versions()?;
+        let repo_info = system::get_repo_info(&held)?;
+        println!();
+        output::plain(&format!("  Held packages: {}", held.len()));
+        for name in &held {
+            match versions.get(name) {
+                Some(version) => output::plain(&format!("    - {name} {version}")),
+                None => output::plain(&format!("    - {name} (not installed)")),
+            }
+        }
+        if !hold_changes.is_empty() {
+            output::warning(&format!(
+                "  IgnorePkg is out of date in {}; run dpkg sync",
+                hold::include_path().display()
+            ));
+        }
+        for name in &held {
+            if let (Some(installed), Some(info)) = (versions.get(name), repo_info.get(name))
+                && system::vercmp(installed, &info.version) == std::cmp::Ordering::Less
+            {
+                output::warning(&format!(
+                    "  {name} is held at {installed}, behind {} {}",
+                    info.repo, info.version
+                ));
+            }
+        }
+    }
+
+    // Signing keys
+    let desired_keys = collect_keys(&config, &hostname);
+    if !desired_keys.is_empty() {
+        let untrusted = keyring::plan(config_path, &desired_keys)?;
+        println!();
+        output::plain(&format!("  Keys declared: {}", desired_keys.len()));
+        output::plain(&format!("  Keys untrusted: {}", untrusted.len()));
+        for key in &untrusted {
+            let reason = match key.state {
+                KeyState::Missing => "not in keyring",
+                KeyState::Untrusted => "not locally signed",
+            };
+            output::plain(&format!("    - {key} ({reason})"));
+        }
+    }
+
+    // Services
+    let services = collect_services(&config, &hostname);
+    if !services.is_empty() {
+        let enabled = sync::query_enabled_services(&services)?;
+        let changes = sync::compute_service_changes(&services, &enabled);
+        println!();
+        output::plain(&format!("  Services declared: {}", services.len()));
+        output::plain(&format!("  Services out of sync: {}", changes.len()));
+        for svc in &changes {
+            let action = if svc.enabled { "enable" } else { "disable" };
+            output::plain(&format!("    - {svc} (would {action})"));
+        }
+    }
 
     // Section listing
     println!();
@@ -133,7 +341,42 @@ pub fn run(config_path: &Path, quiet: bool) -> Result<(), DpkgError> {
                 .iter()
                 .filter(|p| p.source == PackageSource::Aur)
                 .count();
-            if aur > 0 {
+            let flatpak = section
+                .packages
+                .iter()
+                .filter(|p| matches!(p.source, PackageSource::Flatpak { .. }))
+                .count();
+            let lang = section
+                .packages
+                .iter()
+                .filter(|p| matches!(p.source, PackageSource::Lang(_)))
+                .count();
+            let local = section
+                .packages
+                .iter()
+                .filter(|p| p.source == PackageSource::Local)
+                .count();
+            let file = section
+                .packages
+                .iter()
+                .filter(|p| p.source == PackageSource::File)
+                .count();
+            if flatpak > 0 || lang > 0 || local > 0 || file > 0 {
+                let mut parts = format!("{official} official + {aur} AUR");
+                if local > 0 {
+                    parts.push_str(&format!(" + {local} local"));
+                }
+                if file > 0 {
+                    parts.push_str(&format!(" + {file} file"));
+                }
+                if flatpak > 0 {
+                    parts.push_str(&format!(" + {flatpak} Flatpak"));
+                }
+                if lang > 0 {
+                    parts.push_str(&format!(" + {lang} cargo/pipx/npm"));
+                }
+                format!("{pkg_count} packages, {parts}{suffix}")
+            } else if aur > 0 {
                 format!("{pkg_count} packages, {official} official + {aur} AUR{suffix}")
             } else {
                 format!("{pkg_count} packages{suffix}")
@@ -1,11 +1,32 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::{self, Write};
-use std::path::Path;
-
-use crate::config::{collect_packages, parse_config};
-use crate::error::DpkgError;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cli::{self, PlanFormat, Source, SyncStrategy};
+use crate::archive::{self, PackageFile};
+use crate::config::{
+    collect_aur_user, collect_escalation, collect_exclusions, collect_file_packages, collect_flatpaks, collect_held, collect_keys, collect_lang_packages, collect_local_packages,
+    collect_optdeps, collect_packages, collect_repos, collect_services, parse_config, FlatpakApp, Hook, HookEvent, LangPackage,
+    GroupExclusion, LangTool, OptDep, PacmanRepo, Service,
+};
+use crate::dblock;
+use crate::error::{Diagnostic, DpkgError, PackageOutcome, Span};
+use crate::hold::{self, HoldChanges};
+use crate::hooks::{self, HookContext};
+use crate::keyring::{self, KeyState, PendingKey};
+use crate::local::{self, LocalBuild};
+use crate::lock;
+use crate::metapackage::{self, MetaPackage};
 use crate::output;
-use crate::system;
+use crate::repos::{self, RepoChanges};
+use crate::state::history::{self, JournalEntry, StepRecord};
+use crate::state::groups::{self, GroupDrift};
+use crate::state::{self, generations};
+use crate::system::simulate::{self, SimulatedPackage, Simulation};
+use crate::system::{self, escalate, flatpak, lang, systemctl, RepoInfo};
 
 pub struct SyncOptions {
     pub dry_run: bool,
@@ -14,13 +35,65 @@ pub struct SyncOptions {
     pub no_confirm: bool,
     pub only_install: bool,
     pub only_remove: bool,
+    /// Install exactly the versions recorded in the lockfile
+    pub locked: bool,
+    /// Archive-style mirror directory searched for locked versions
+    pub mirror: Option<PathBuf>,
+    /// Restrict the run to these package sources (empty: all)
+    pub only_source: Vec<Source>,
+    pub strategy: SyncStrategy,
+    /// Retry a failed official or AUR batch one package at a time and carry on
+    pub keep_going: bool,
+    /// How long to wait for a held pacman or dpkg lock (`None`: fail at once)
+    pub wait_lock: Option<Duration>,
+    /// Build user when running as root; overrides the config's `aur-user:`
+    pub aur_user: Option<String>,
+    /// How a dry run prints the plan
+    pub format: PlanFormat,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct SyncPlan {
     pub to_install_official: Vec<String>,
     pub to_install_aur: Vec<String>,
     pub to_remove: Vec<String>,
+    /// Units whose enablement must change; `enabled` is the target state.
+    #[serde(default)]
+    pub service_changes: Vec<Service>,
+    #[serde(default)]
+    pub flatpak_install: Vec<FlatpakApp>,
+    /// Installed Flatpak apps that are not declared
+    #[serde(default)]
+    pub flatpak_remove: Vec<FlatpakApp>,
+    #[serde(default)]
+    pub lang_install: Vec<LangPackage>,
+    /// Installed cargo/pipx/npm tools that are not declared
+    #[serde(default)]
+    pub lang_remove: Vec<LangPackage>,
+    /// Group membership changes since the last sync
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub group_drift: Vec<GroupDrift>,
+    /// Declared optional dependencies that are not installed
+    #[serde(default)]
+    pub optdep_install: Vec<OptDep>,
+    /// `local:` PKGBUILDs to build and install with `pacman -U`
+    #[serde(default)]
+    pub local_install: Vec<LocalBuild>,
+    /// `file:` archives that are missing or at a different version
+    #[serde(default)]
+    pub file_install: Vec<PackageFile>,
+    /// Declared signing keys to import and/or locally sign
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<PendingKey>,
+    /// Managed pacman repositories to add, change or drop
+    #[serde(default, skip_serializing_if = "RepoChanges::is_empty")]
+    pub repo_changes: RepoChanges,
+    /// Packages to add to or drop from the managed IgnorePkg include
+    #[serde(default, skip_serializing_if = "HoldChanges::is_empty")]
+    pub hold_changes: HoldChanges,
+    /// Meta-package to (re)build under `--strategy=metapackage`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metapackage: Option<MetaPackage>,
 }
 
 /// Pure computation: given desired packages, what's installed, and current orphans,
@@ -70,7 +143,201 @@ pub fn compute_sync_plan(
         to_install_official,
         to_install_aur,
         to_remove,
+        service_changes: Vec::new(),
+        flatpak_install: Vec::new(),
+        flatpak_remove: Vec::new(),
+        lang_install: Vec::new(),
+        lang_remove: Vec::new(),
+        group_drift: Vec::new(),
+        optdep_install: Vec::new(),
+        local_install: Vec::new(),
+        file_install: Vec::new(),
+        keys: Vec::new(),
+        hold_changes: HoldChanges::default(),
+        repo_changes: RepoChanges::default(),
+        metapackage: None,
+    }
+}
+
+/// Pure computation: declared Flatpak apps that are missing, and installed apps
+/// (per installation) that are not declared. An installation without any declaration
+/// is left alone, so adopting dpkg doesn't wipe existing apps.
+pub fn compute_flatpak_changes(
+    desired: &[FlatpakApp],
+    installed_system: &[String],
+    installed_user: &[String],
+) -> (Vec<FlatpakApp>, Vec<FlatpakApp>) {
+    let installed = |user: bool| if user { installed_user } else { installed_system };
+
+    let to_install = desired
+        .iter()
+        .filter(|app| !installed(app.user).contains(&app.id))
+        .cloned()
+        .collect();
+
+    let to_remove = [false, true]
+        .into_iter()
+        .filter(|user| desired.iter().any(|app| app.user == *user))
+        .flat_map(|user| {
+            installed(user)
+                .iter()
+                .filter(move |id| !desired.iter().any(|app| app.user == user && &app.id == *id))
+                .map(move |id| FlatpakApp {
+                    id: id.clone(),
+                    remote: None,
+                    user,
+                })
+        })
+        .collect();
+
+    (to_install, to_remove)
+}
+
+/// Query both Flatpak installations and compute missing and undeclared apps.
+/// Without the flatpak CLI there is nothing to manage, unless apps are declared.
+pub fn plan_flatpaks(
+    desired: &[FlatpakApp],
+) -> Result<(Vec<FlatpakApp>, Vec<FlatpakApp>), DpkgError> {
+    if !flatpak::is_available() {
+        if desired.is_empty() {
+            return Ok((Vec::new(), Vec::new()));
+        }
+        return Err(DpkgError::BackendMissing {
+            program: "flatpak".to_string(),
+            reason: "Flatpak apps are declared but flatpak is not installed".to_string(),
+        });
+    }
+    let system_apps = flatpak::list_installed(false)?;
+    let user_apps = flatpak::list_installed(true)?;
+    Ok(compute_flatpak_changes(desired, &system_apps, &user_apps))
+}
+
+/// Pure computation: declared cargo/pipx/npm tools that are missing, and installed
+/// tools that are not declared. Only tools present in `installed` are managed.
+pub fn compute_lang_changes(
+    desired: &[LangPackage],
+    installed: &BTreeMap<LangTool, Vec<String>>,
+) -> (Vec<LangPackage>, Vec<LangPackage>) {
+    let to_install = desired
+        .iter()
+        .filter(|pkg| {
+            installed
+                .get(&pkg.tool)
+                .is_some_and(|names| !names.contains(&pkg.name))
+        })
+        .cloned()
+        .collect();
+
+    let to_remove = installed
+        .iter()
+        .flat_map(|(tool, names)| {
+            names
+                .iter()
+                .filter(|name| !desired.iter().any(|p| p.tool == *tool && &p.name == *name))
+                .map(|name| LangPackage {
+                    tool: *tool,
+                    name: name.clone(),
+                })
+        })
+        .collect();
+
+    (to_install, to_remove)
+}
+
+/// Query every language package manager that has declarations. A tool with nothing
+/// declared is left alone, so adopting dpkg doesn't wipe existing `cargo install`s.
+pub fn plan_lang_packages(
+    desired: &[LangPackage],
+) -> Result<(Vec<LangPackage>, Vec<LangPackage>), DpkgError> {
+    let mut installed = BTreeMap::new();
+    for tool in LangTool::ALL {
+        if desired.iter().any(|p| p.tool == tool) {
+            installed.insert(tool, lang::list_installed(tool)?);
+        }
+    }
+    Ok(compute_lang_changes(desired, &installed))
+}
+
+/// Drop everything outside the `--only-source` filter from the plan. Pacman removals
+/// stay only when both official and AUR packages are selected; services are not a
+/// package source and are skipped by any filtered run.
+pub fn restrict_to_sources(plan: &mut SyncPlan, filter: &[Source]) {
+    if filter.is_empty() {
+        return;
+    }
+    if !cli::selects(filter, Source::Official) {
+        plan.to_install_official.clear();
+        plan.metapackage = None;
+    }
+    if !cli::selects(filter, Source::Aur) {
+        plan.to_install_aur.clear();
     }
+    if !reconciles_pacman(filter) {
+        plan.to_remove.clear();
+    }
+    if !manages_pacman(filter) {
+        plan.optdep_install.clear();
+        plan.repo_changes = RepoChanges::default();
+        plan.keys.clear();
+        plan.hold_changes = HoldChanges::default();
+    }
+    if !cli::selects(filter, Source::Local) {
+        plan.local_install.clear();
+    }
+    if !cli::selects(filter, Source::File) {
+        plan.file_install.clear();
+    }
+    if !cli::selects(filter, Source::Flatpak) {
+        plan.flatpak_install.clear();
+        plan.flatpak_remove.clear();
+    }
+    plan.lang_install.retain(|p| cli::selects(filter, p.tool.into()));
+    plan.lang_remove.retain(|p| cli::selects(filter, p.tool.into()));
+    plan.service_changes.clear();
+}
+
+/// Whether a run with this filter touches the pacman database at all.
+fn manages_pacman(filter: &[Source]) -> bool {
+    cli::selects(filter, Source::Official) || cli::selects(filter, Source::Aur)
+}
+
+/// Whether a run with this filter resets install reasons and removes orphans. Both
+/// cover the whole pacman database, so they need official and AUR packages selected.
+fn reconciles_pacman(filter: &[Source]) -> bool {
+    cli::selects(filter, Source::Official) && cli::selects(filter, Source::Aur)
+}
+
+/// Group tools by the package manager that handles them.
+fn by_tool(packages: &[LangPackage]) -> BTreeMap<LangTool, Vec<String>> {
+    let mut grouped: BTreeMap<LangTool, Vec<String>> = BTreeMap::new();
+    for pkg in packages {
+        grouped.entry(pkg.tool).or_default().push(pkg.name.clone());
+    }
+    grouped
+}
+
+/// Pure computation: which declared services are not in their declared state.
+/// `enabled` holds the `(unit, user)` pairs that are currently enabled.
+pub fn compute_service_changes(
+    desired: &[Service],
+    enabled: &HashSet<(String, bool)>,
+) -> Vec<Service> {
+    desired
+        .iter()
+        .filter(|svc| enabled.contains(&(svc.unit.clone(), svc.user)) != svc.enabled)
+        .cloned()
+        .collect()
+}
+
+/// Query systemd for the current state of every declared service.
+pub fn query_enabled_services(desired: &[Service]) -> Result<HashSet<(String, bool)>, DpkgError> {
+    let mut enabled = HashSet::new();
+    for svc in desired {
+        if systemctl::is_enabled(&svc.unit, svc.user)? {
+            enabled.insert((svc.unit.clone(), svc.user));
+        }
+    }
+    Ok(enabled)
 }
 
 /// Filter desired packages to only those already installed on the system.
@@ -84,15 +351,146 @@ pub fn filter_installed(desired: &[String], all_installed: &HashSet<&str>) -> Ve
         .collect()
 }
 
+/// Pure computation: exclusions must be on a group line and name one of its members.
+pub fn check_exclusions(
+    exclusions: &[GroupExclusion],
+    groups: &HashMap<String, Vec<String>>,
+) -> Result<(), DpkgError> {
+    for exclusion in exclusions {
+        let Some(members) = groups.get(&exclusion.group) else {
+            return Err(Diagnostic::new(
+                Span::line(exclusion.line),
+                format!(
+                    "`{}` is not a package group, so it has no members to exclude",
+                    exclusion.group
+                ),
+            )
+            .into());
+        };
+        if let Some(unknown) = exclusion.members.iter().find(|m| !members.contains(m)) {
+            return Err(Diagnostic::new(
+                Span::line(exclusion.line),
+                format!("`{unknown}` is not a member of group `{}`", exclusion.group),
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Pure computation: fail on the first declared optional dependency that is not in its
+/// parent's optdepends. Parents pacman does not know (yet) are not checked.
+pub fn check_optdeps(
+    optdeps: &[OptDep],
+    optdepends: &HashMap<String, Vec<String>>,
+) -> Result<(), DpkgError> {
+    for dep in optdeps {
+        if let Some(known) = optdepends.get(&dep.parent)
+            && !known.contains(&dep.name)
+        {
+            let hint = if known.is_empty() {
+                format!("{} has no optional dependencies", dep.parent)
+            } else {
+                format!("Optional dependencies of {}: {}", dep.parent, known.join(", "))
+            };
+            return Err(Diagnostic::new(
+                Span::line(dep.line),
+                format!("`{}` is not an optional dependency of `{}`", dep.name, dep.parent),
+            )
+            .with_hint(hint)
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Pure computation: under `--root` only pacman packages and system services reach the
+/// target; AUR packages do too when they can be built on the host. Fail on declarations
+/// that would land on the host or cannot be installed.
+pub fn check_target_support(
+    aur: &[String],
+    can_build: bool,
+    flatpaks: &[FlatpakApp],
+    lang: &[LangPackage],
+    services: &[Service],
+) -> Result<(), DpkgError> {
+    if !aur.is_empty() && !can_build {
+        return Err(DpkgError::UnsupportedTarget(format!(
+            "AUR packages cannot be built for the target: {}\n  Hint: Set a build user with --aur-user or aur-user:<name>; the packages are built on the host and installed with pacman -U --root",
+            aur.join(", ")
+        )));
+    }
+    let declared: Vec<String> = flatpaks
+        .iter()
+        .map(|app| app.to_string())
+        .chain(lang.iter().map(|pkg| pkg.to_string()))
+        .chain(services.iter().filter(|s| s.user).map(|s| s.to_string()))
+        .collect();
+    if !declared.is_empty() {
+        return Err(DpkgError::UnsupportedTarget(format!(
+            "these would be applied to the host, not the target: {}\n  Hint: Move them into a host section the target does not match",
+            declared.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Pure computation: under `--root`, optional dependencies must come from the target's
+/// repositories; yay would install AUR ones into the host.
+pub fn check_target_optdeps(optdeps: &[String], in_repos: &HashMap<String, RepoInfo>) -> Result<(), DpkgError> {
+    let aur: Vec<&str> = optdeps
+        .iter()
+        .filter(|name| !in_repos.contains_key(*name))
+        .map(|name| name.as_str())
+        .collect();
+    if aur.is_empty() {
+        return Ok(());
+    }
+    Err(DpkgError::UnsupportedTarget(format!(
+        "optional dependencies outside the target's repositories cannot be installed: {}\n  Hint: Declare them as aur: packages instead",
+        aur.join(", ")
+    )))
+}
+
 pub fn run(config_path: &Path, options: &SyncOptions) -> Result<(), DpkgError> {
+    // Held until the sync returns, so a concurrent sync cannot plan against a system
+    // that is about to change
+    let _sync_lock = if options.dry_run {
+        None
+    } else {
+        Some(dblock::acquire(options.wait_lock, options.quiet)?)
+    };
+
     // 1. Parse configuration
     let config = parse_config(config_path)?;
     let hostname = system::get_hostname()?;
+    escalate::init(collect_escalation(&config, &hostname));
+    escalate::set_build_user(options.aur_user.clone().or_else(|| collect_aur_user(&config, &hostname)));
     let (raw_official, desired_aur) = collect_packages(&config, &hostname);
+    let mut hooks = hooks::discover(&config, config_path, &hostname);
+    let alternate_root = system::target().root.is_some();
+    if alternate_root {
+        check_target_support(
+            &desired_aur,
+            escalate::can_build(),
+            &collect_flatpaks(&config, &hostname),
+            &collect_lang_packages(&config, &hostname),
+            &collect_services(&config, &hostname),
+        )?;
+        // Hooks act on the host, so they sit out syncs of another root
+        if !hooks.is_empty() && !options.quiet && options.format == PlanFormat::Text {
+            output::warning(&format!("Skipping {} hooks; they run on the host, not in the target", hooks.len()));
+        }
+        hooks.clear();
+    }
     let groups = system::get_group_members(&raw_official)?;
-    let desired_official = system::expand_package_groups(&raw_official, &groups);
+    let exclusions = collect_exclusions(&config, &hostname);
+    check_exclusions(&exclusions, &groups)?;
+    let mut desired_official = system::expand_package_groups(&raw_official, &groups, &exclusions);
+    let group_drift = groups::compute_drift(&groups::load()?, &groups, &exclusions);
 
-    if options.verbose {
+    // A JSON plan is the only thing on stdout
+    if options.verbose && options.format == PlanFormat::Text {
         output::info(&format!("Configuration: {}", config_path.display()));
         output::info(&format!("Hostname: {hostname}"));
         output::info(&format!(
@@ -116,83 +514,434 @@ pub fn run(config_path: &Path, options: &SyncOptions) -> Result<(), DpkgError> {
     let all_installed = system::get_all_installed()?;
     let all_installed_set: HashSet<&str> = all_installed.iter().map(|s| s.as_str()).collect();
 
+    // Members a group gained since the last sync are only installed once confirmed;
+    // declined ones are left out now and offered again next time
+    let new_members: Vec<String> = group_drift
+        .iter()
+        .flat_map(|d| d.added.iter())
+        .filter(|m| !all_installed_set.contains(m.as_str()))
+        .cloned()
+        .collect();
+    let declined = if new_members.is_empty() || options.dry_run || options.no_confirm {
+        Vec::new()
+    } else if options.only_remove || !cli::selects(&options.only_source, Source::Official) {
+        new_members
+    } else {
+        print_group_drift(&group_drift);
+        if confirm("Install the new group members?")? {
+            Vec::new()
+        } else {
+            new_members
+        }
+    };
+    desired_official.retain(|p| !declined.contains(p));
+
     let orphans = system::get_orphans()?;
-    let plan = compute_sync_plan(&desired_official, &desired_aur, &installed, orphans);
+    let mut plan = compute_sync_plan(&desired_official, &desired_aur, &installed, orphans);
+    plan.group_drift = group_drift;
+
+    // Packages built from local PKGBUILDs or shipped as archives are declared too,
+    // just not by name
+    let local_builds = local::inspect_all(config_path, &collect_local_packages(&config, &hostname))?;
+    let package_files =
+        archive::inspect_all(config_path, &collect_file_packages(&config, &hostname))?;
+    let desired_archives: Vec<String> = local_builds
+        .iter()
+        .flat_map(|b| b.names.clone())
+        .chain(package_files.iter().map(|f| f.name.clone()))
+        .collect();
+    plan.to_remove.retain(|p| !desired_archives.contains(p));
+    if !desired_archives.is_empty() {
+        let versions = system::get_installed_versions()?;
+        plan.local_install = local::pending(&local_builds, &versions)?;
+        plan.file_install = archive::pending(&package_files, &versions);
+    }
+
+    // Optional dependencies must really be optdepends of their parent. They are
+    // installed as dependencies and kept while declared
+    let desired_optdeps = collect_optdeps(&config, &hostname);
+    if !desired_optdeps.is_empty() {
+        let mut parents: Vec<String> = desired_optdeps.iter().map(|d| d.parent.clone()).collect();
+        parents.sort();
+        parents.dedup();
+        check_optdeps(&desired_optdeps, &system::get_optdepends(&parents)?)?;
+        plan.to_remove.retain(|p| !desired_optdeps.iter().any(|d| d.name == *p));
+        plan.optdep_install = desired_optdeps
+            .iter()
+            .filter(|d| !all_installed_set.contains(d.name.as_str()))
+            .cloned()
+            .collect();
+        if alternate_root && !plan.optdep_install.is_empty() {
+            let names: Vec<String> = plan.optdep_install.iter().map(|d| d.name.clone()).collect();
+            check_target_optdeps(&names, &system::get_repo_info(&names)?)?;
+        }
+    }
+
+    // Metapackage strategy: the meta-package itself is wanted, and is only rebuilt
+    // when the declared official packages change
+    if options.strategy == SyncStrategy::Metapackage {
+        let meta_name = metapackage::package_name(&hostname);
+        plan.to_remove.retain(|p| *p != meta_name);
+        let installed_depends = system::get_installed_depends(&meta_name)?;
+        plan.metapackage =
+            metapackage::plan(&hostname, &desired_official, installed_depends.as_deref());
+    }
+
+    plan.keys = keyring::plan(config_path, &collect_keys(&config, &hostname))?;
+
+    let desired_repos = collect_repos(&config, &hostname);
+    plan.repo_changes = repos::plan(&desired_repos)?;
+
+    let held = collect_held(&config, &hostname);
+    plan.hold_changes = hold::plan(&held)?;
+
+    let desired_services = collect_services(&config, &hostname);
+    let enabled_services = query_enabled_services(&desired_services)?;
+    plan.service_changes = compute_service_changes(&desired_services, &enabled_services);
+
+    // The host's Flatpak installations are not the target's
+    if !alternate_root {
+        let desired_flatpaks = collect_flatpaks(&config, &hostname);
+        (plan.flatpak_install, plan.flatpak_remove) = plan_flatpaks(&desired_flatpaks)?;
+    }
+
+    let desired_lang = collect_lang_packages(&config, &hostname);
+    (plan.lang_install, plan.lang_remove) = plan_lang_packages(&desired_lang)?;
+
+    restrict_to_sources(&mut plan, &options.only_source);
 
     let SyncPlan {
         to_install_official,
         to_install_aur,
         to_remove,
+        ..
     } = &plan;
 
-    // 4. Dry run — just print and exit
-    if options.dry_run {
-        print_plan(
+    // Locked mode: find every exact archive up front so nothing changes if one is missing
+    let locked_files = if options.locked && manages_pacman(&options.only_source) {
+        resolve_locked(
             config_path,
             &hostname,
-            to_install_official,
-            to_install_aur,
-            to_remove,
-            options.quiet,
-        );
+            &desired_official,
+            &desired_aur,
+            options.mirror.as_deref(),
+        )?
+    } else {
+        Vec::new()
+    };
+
+    let ctx = SyncContext {
+        config_path,
+        hostname: &hostname,
+        plan: &plan,
+        locked_files: &locked_files,
+        desired_official: &desired_official,
+        desired_aur: &desired_aur,
+        desired_archives: &desired_archives,
+        desired_repos: &desired_repos,
+        held: &held,
+        all_installed_set: &all_installed_set,
+        hooks: &hooks,
+    };
+
+    // 4. Dry run — just print and exit; hooks are listed but not run. pacman resolves
+    // the official installs and removals so pulled-in dependencies show up too
+    if options.dry_run {
+        let simulation = if options.locked || !manages_pacman(&options.only_source) {
+            None
+        } else {
+            let install: &[String] = if options.only_remove { &[] } else { to_install_official };
+            let remove: &[String] = if options.only_install { &[] } else { to_remove };
+            Some(simulate::simulate(install, remove)?)
+        };
+        match options.format {
+            PlanFormat::Text => print_plan(&ctx, options, simulation.as_ref()),
+            PlanFormat::Json => {
+                let json = serde_json::json!({
+                    "hostname": hostname,
+                    "plan": plan,
+                    "simulation": simulation,
+                });
+                let json = serde_json::to_string_pretty(&json)
+                    .map_err(|e| DpkgError::State(format!("Cannot serialize plan: {e}")))?;
+                println!("{json}");
+            }
+        }
         return Ok(());
     }
 
     // Check if there's nothing to do
-    let nothing_to_install = to_install_official.is_empty() && to_install_aur.is_empty();
-    let nothing_to_remove = to_remove.is_empty();
-
-    if nothing_to_install && nothing_to_remove {
+    let nothing_to_install = plan.flatpak_install.is_empty()
+        && plan.lang_install.is_empty()
+        && plan.local_install.is_empty()
+        && plan.file_install.is_empty()
+        && plan.optdep_install.is_empty()
+        && plan.metapackage.is_none()
+        && if options.locked {
+            locked_files.is_empty()
+        } else {
+            to_install_official.is_empty() && to_install_aur.is_empty()
+        };
+    let nothing_to_remove =
+        to_remove.is_empty() && plan.flatpak_remove.is_empty() && plan.lang_remove.is_empty();
+    let nothing_to_reconcile = plan.service_changes.is_empty()
+        && plan.repo_changes.is_empty()
+        && plan.keys.is_empty()
+        && plan.hold_changes.is_empty();
+
+    if nothing_to_install && nothing_to_remove && nothing_to_reconcile {
         if !options.quiet {
             output::success("System is already in sync with configuration");
         }
+        record_generation(&hostname, options.quiet);
+        record_groups(&groups, &declined);
         return Ok(());
     }
 
-    // 5. Execute changes
+    // A pacman already running would make the first step fail after planning, or a
+    // later one after earlier steps changed the system
+    if manages_pacman(&options.only_source) {
+        dblock::wait_for_pacman(options.wait_lock, options.quiet)?;
+    }
+    escalate::validate()?;
+
+    // 5. Execute changes, recording each step in the history journal
+    let versions_before = system::get_installed_versions()?;
+    let mut steps = Vec::new();
+    let result = execute_plan(&ctx, options, &mut steps);
+    record_history(config_path, &hostname, &plan, steps, &versions_before);
+    if let Err(e) = result {
+        // The packages that did install still make up a new generation, and the
+        // group drift was acted on, so it is not reported again
+        if matches!(e, DpkgError::PartialInstall(_)) {
+            record_generation(&hostname, options.quiet);
+            record_groups(&groups, &declined);
+        }
+        return Err(e);
+    }
+    record_generation(&hostname, options.quiet);
+    record_groups(&groups, &declined);
 
-    // Mark all as deps → mark desired as explicit → remove orphans
-    if !options.only_install {
-        system::mark_all_as_deps(options.verbose)?;
+    if !options.quiet {
+        output::success("Sync complete");
+    }
+
+    Ok(())
+}
 
-        let installed_official = filter_installed(&desired_official, &all_installed_set);
-        system::mark_as_explicit(&installed_official, options.verbose)?;
+/// Inputs to plan execution and printing, computed once in `run`.
+struct SyncContext<'a> {
+    config_path: &'a Path,
+    hostname: &'a str,
+    plan: &'a SyncPlan,
+    locked_files: &'a [PathBuf],
+    desired_official: &'a [String],
+    desired_aur: &'a [String],
+    desired_archives: &'a [String],
+    desired_repos: &'a [PacmanRepo],
+    held: &'a [String],
+    all_installed_set: &'a HashSet<&'a str>,
+    hooks: &'a [Hook],
+}
+
+fn execute_plan(
+    ctx: &SyncContext,
+    options: &SyncOptions,
+    steps: &mut Vec<StepRecord>,
+) -> Result<(), DpkgError> {
+    let SyncPlan {
+        to_install_official,
+        to_install_aur,
+        to_remove,
+        ..
+    } = ctx.plan;
+    let (locked_files, desired_official, desired_aur, all_installed_set) = (
+        ctx.locked_files,
+        ctx.desired_official,
+        ctx.desired_aur,
+        ctx.all_installed_set,
+    );
+    let hook_ctx = HookContext {
+        config_path: ctx.config_path,
+        hostname: ctx.hostname,
+        plan: ctx.plan,
+    };
+    let triggered = hooks::planned(ctx.hooks, ctx.plan);
+    // Per-package results of batches retried under --keep-going
+    let mut outcomes: Vec<PackageOutcome> = Vec::new();
+    // Post hooks don't stop the remaining steps; the first failure is reported at the end
+    let mut hook_failure = None;
+    let mut run_hooks = |steps: &mut Vec<StepRecord>, matches: &dyn Fn(&HookEvent) -> bool| {
+        for hook in triggered.iter().filter(|h| matches(&h.event)) {
+            let result = record_step(
+                steps,
+                &format!("hook:{}", hook.event),
+                hooks::run(hook, &hook_ctx, options.verbose),
+            );
+            if let Err(e) = result
+                && hook_failure.is_none()
+            {
+                hook_failure = Some(e);
+            }
+        }
+    };
+
+    // A failing pre-sync hook aborts before anything is changed
+    for hook in triggered.iter().filter(|h| h.event == HookEvent::PreSync) {
+        record_step(
+            steps,
+            &format!("hook:{}", hook.event),
+            hooks::run(hook, &hook_ctx, options.verbose),
+        )?;
+    }
+
+    // Keys come first: repository databases and packages are verified against them
+    if !ctx.plan.keys.is_empty() {
+        if !options.quiet {
+            output::info("Updating pacman keyring...");
+        }
+        record_step(steps, "update_keys", keyring::apply(&ctx.plan.keys, options.verbose))?;
+    }
+
+    // Repositories come before anything is installed from them
+    if !ctx.plan.repo_changes.is_empty() {
+        if !options.quiet {
+            output::info("Updating pacman repositories...");
+        }
+        record_step(
+            steps,
+            "update_repos",
+            repos::apply(ctx.desired_repos, &ctx.plan.repo_changes, options.verbose),
+        )?;
+    }
+
+    // Holds are in place before anything is installed or upgraded
+    if !ctx.plan.hold_changes.is_empty() {
+        if !options.quiet {
+            output::info("Updating held packages...");
+        }
+        record_step(
+            steps,
+            "update_holds",
+            hold::apply(ctx.held, &ctx.plan.hold_changes, options.verbose),
+        )?;
+    }
+
+    // The meta-package goes first: it pulls in the declared official packages, and
+    // must be installed before they are marked as dependencies
+    if let Some(meta) = &ctx.plan.metapackage
+        && !options.only_remove
+    {
+        if !options.quiet {
+            output::info(&format!(
+                "Installing {} ({} dependencies)...",
+                meta.name,
+                meta.depends.len()
+            ));
+        }
+        record_step(
+            steps,
+            "install_metapackage",
+            metapackage::build_and_install(meta, ctx.hostname, options.verbose),
+        )?;
+    }
 
-        let installed_aur = filter_installed(&desired_aur, &all_installed_set);
-        system::mark_as_explicit(&installed_aur, options.verbose)?;
+    // Mark all as deps → mark desired as explicit → remove orphans
+    if !options.only_install {
+        if options.strategy == SyncStrategy::Metapackage && reconciles_pacman(&options.only_source) {
+            mark_for_metapackage(ctx, options, steps)?;
+        } else if reconciles_pacman(&options.only_source) {
+            record_step(steps, "mark_all_as_deps", system::mark_all_as_deps(options.verbose))?;
+
+            let installed_official = filter_installed(desired_official, all_installed_set);
+            let installed_aur = filter_installed(desired_aur, all_installed_set);
+            let installed_local = filter_installed(ctx.desired_archives, all_installed_set);
+            let explicit_result = system::mark_as_explicit(&installed_official, options.verbose)
+                .and_then(|_| system::mark_as_explicit(&installed_aur, options.verbose))
+                .and_then(|_| system::mark_as_explicit(&installed_local, options.verbose));
+            record_step(steps, "mark_as_explicit", explicit_result)?;
+        }
 
-        if !to_remove.is_empty() {
+        let flatpak_remove = &ctx.plan.flatpak_remove;
+        let lang_remove = &ctx.plan.lang_remove;
+        if !to_remove.is_empty() || !flatpak_remove.is_empty() || !lang_remove.is_empty() {
             if !options.quiet {
                 output::warning("The following packages will be removed:");
                 for pkg in to_remove {
                     output::plain(&format!("  {pkg}"));
                 }
+                for app in flatpak_remove {
+                    output::plain(&format!("  {app}"));
+                }
+                for pkg in lang_remove {
+                    output::plain(&format!("  {pkg}"));
+                }
             }
 
-            if options.no_confirm || confirm_removal()? {
-                system::remove_orphans(options.verbose)?;
-                if !options.quiet {
-                    output::success(&format!(
-                        "Removed {} packages",
-                        to_remove.len()
-                    ));
-                }
-            } else {
-                return Err(DpkgError::UserCancelled);
+            let confirmed = options.no_confirm || confirm("Proceed with removal?")?;
+            if !confirmed {
+                return record_step(steps, "remove_orphans", Err(DpkgError::UserCancelled));
+            }
+
+            if !to_remove.is_empty() {
+                record_step(steps, "remove_orphans", system::remove_orphans(options.verbose))?;
+            }
+            if !flatpak_remove.is_empty() {
+                record_step(
+                    steps,
+                    "remove_flatpaks",
+                    flatpak::uninstall(flatpak_remove, options.verbose),
+                )?;
             }
+            for (tool, names) in by_tool(lang_remove) {
+                record_step(
+                    steps,
+                    &format!("remove_{}", tool.name()),
+                    lang::uninstall(tool, &names, options.verbose),
+                )?;
+            }
+            if !options.quiet {
+                output::success(&format!(
+                    "Removed {} packages",
+                    to_remove.len() + flatpak_remove.len() + lang_remove.len()
+                ));
+            }
+            run_hooks(steps, &|e| matches!(e, HookEvent::PostRemove(_)));
         }
     }
 
     // Install missing packages
-    if !options.only_remove {
-        if !to_install_official.is_empty() {
+    if !options.only_remove && options.locked {
+        if !locked_files.is_empty() {
+            if !options.quiet {
+                output::info(&format!(
+                    "Installing {} locked packages...",
+                    locked_files.len()
+                ));
+            }
+            record_step(
+                steps,
+                "install_locked",
+                system::install_files(locked_files, options.verbose),
+            )?;
+        }
+    } else if !options.only_remove {
+        // Under the metapackage strategy pacman -U already pulled these in
+        if !to_install_official.is_empty() && options.strategy == SyncStrategy::Mark {
             if !options.quiet {
                 output::info(&format!(
                     "Installing {} official packages...",
                     to_install_official.len()
                 ));
             }
-            system::install_official(to_install_official, options.verbose)?;
+            install_batch(
+                steps,
+                "install_official",
+                to_install_official,
+                (system::install_official, system::get_depends),
+                options,
+                &mut outcomes,
+            )?;
         }
 
         if !to_install_aur.is_empty() {
@@ -202,37 +951,510 @@ pub fn run(config_path: &Path, options: &SyncOptions) -> Result<(), DpkgError> {
                     to_install_aur.len()
                 ));
             }
-            system::install_aur(to_install_aur, options.verbose)?;
+            install_batch(
+                steps,
+                "install_aur",
+                to_install_aur,
+                (system::install_aur, system::get_aur_depends),
+                options,
+                &mut outcomes,
+            )?;
         }
     }
 
-    if !options.quiet {
-        output::success("Sync complete");
+    // Optional dependencies come after their parents
+    if !options.only_remove && !ctx.plan.optdep_install.is_empty() {
+        if !options.quiet {
+            output::info(&format!(
+                "Installing {} optional dependencies...",
+                ctx.plan.optdep_install.len()
+            ));
+        }
+        let names: Vec<String> = ctx.plan.optdep_install.iter().map(|d| d.name.clone()).collect();
+        let result = system::get_repo_info(&names).and_then(|in_repos| {
+            let (repo, aur): (Vec<String>, Vec<String>) =
+                names.iter().cloned().partition(|n| in_repos.contains_key(n));
+            system::install_optdeps(&repo, &aur, options.verbose)
+        });
+        record_step(steps, "install_optdeps", result)?;
+    }
+
+    // Package files and local PKGBUILDs may depend on the packages installed above
+    if !options.only_remove && !ctx.plan.file_install.is_empty() {
+        if !options.quiet {
+            output::info(&format!(
+                "Installing {} package files...",
+                ctx.plan.file_install.len()
+            ));
+        }
+        let files: Vec<PathBuf> = ctx.plan.file_install.iter().map(|f| f.path.clone()).collect();
+        record_step(
+            steps,
+            "install_files",
+            system::install_files(&files, options.verbose),
+        )?;
+    }
+
+    if !options.only_remove && !ctx.plan.local_install.is_empty() {
+        if !options.quiet {
+            output::info(&format!(
+                "Building {} local packages...",
+                ctx.plan.local_install.len()
+            ));
+        }
+        let mut files = Vec::new();
+        for build in &ctx.plan.local_install {
+            let result = local::build(build, options.verbose).map(|built| files.extend(built));
+            record_step(steps, &format!("build_local:{}", build.source), result)?;
+        }
+        record_step(
+            steps,
+            "install_local",
+            system::install_files(&files, options.verbose),
+        )?;
+    }
+
+    if !options.only_remove && !ctx.plan.flatpak_install.is_empty() {
+        if !options.quiet {
+            output::info(&format!(
+                "Installing {} Flatpak apps...",
+                ctx.plan.flatpak_install.len()
+            ));
+        }
+        record_step(
+            steps,
+            "install_flatpaks",
+            flatpak::install(&ctx.plan.flatpak_install, options.verbose),
+        )?;
+    }
+
+    if !options.only_remove {
+        for (tool, names) in by_tool(&ctx.plan.lang_install) {
+            if !options.quiet {
+                output::info(&format!("Installing {} {} packages...", names.len(), tool.name()));
+            }
+            let result = record_step(
+                steps,
+                &format!("install_{}", tool.name()),
+                lang::install(tool, &names, options.verbose),
+            );
+            // pipx installs one tool at a time; under --keep-going its failures join
+            // the summary like those of a retried batch
+            match result {
+                Err(DpkgError::PartialInstall(partial)) if options.keep_going => outcomes.extend(partial),
+                result => result?,
+            }
+        }
+    }
+
+    // Reconcile services once the packages shipping their units are installed
+    let service_changes: Vec<Service> = ctx
+        .plan
+        .service_changes
+        .iter()
+        .filter(|svc| if svc.enabled { !options.only_remove } else { !options.only_install })
+        .cloned()
+        .collect();
+    if !service_changes.is_empty() {
+        if !options.quiet {
+            output::info(&format!(
+                "Updating {} services...",
+                service_changes.len()
+            ));
+        }
+        record_step(
+            steps,
+            "reconcile_services",
+            systemctl::apply(&service_changes, options.verbose),
+        )?;
     }
 
+    // Packages that failed or were skipped under --keep-going were not installed
+    if !options.only_remove {
+        let failed: HashSet<&str> = outcomes
+            .iter()
+            .filter(|o| o.error.is_some())
+            .map(|o| o.name.as_str())
+            .collect();
+        run_hooks(steps, &|e| matches!(e, HookEvent::PostInstall(pkg) if !failed.contains(pkg.as_str())));
+    }
+    run_hooks(steps, &|e| *e == HookEvent::PostSync);
+
+    if outcomes.iter().any(|o| o.error.is_some()) {
+        if !options.quiet {
+            print_install_summary(&outcomes);
+        }
+        return Err(DpkgError::PartialInstall(outcomes));
+    }
+    match hook_failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+type Installer = fn(&[String], bool) -> Result<(), DpkgError>;
+type DependsQuery = fn(&[String]) -> Result<HashMap<String, Vec<String>>, DpkgError>;
+
+/// Install `packages` in one batch. Under `--keep-going` a failed batch is retried one
+/// package at a time, dependencies first, skipping packages whose dependency failed;
+/// the results go to `outcomes` and the sync carries on.
+fn install_batch(
+    steps: &mut Vec<StepRecord>,
+    name: &str,
+    packages: &[String],
+    (install, depends): (Installer, DependsQuery),
+    options: &SyncOptions,
+    outcomes: &mut Vec<PackageOutcome>,
+) -> Result<(), DpkgError> {
+    let result = install(packages, options.verbose);
+    // One at a time cannot succeed while another process holds pacman's database
+    if !options.keep_going || matches!(result, Ok(()) | Err(DpkgError::DatabaseLocked(_))) {
+        return record_step(steps, name, result);
+    }
+    record_step(steps, name, result).ok();
+
+    if !options.quiet {
+        output::warning(&format!(
+            "Batch install failed; installing {} packages one at a time...",
+            packages.len()
+        ));
+    }
+    // Without dependency information the configured order is kept
+    let depends = depends(packages).unwrap_or_default();
+    let mut failed: Vec<String> = Vec::new();
+    for package in dependency_order(packages, &depends) {
+        let failed_dep = depends
+            .get(&package)
+            .and_then(|deps| deps.iter().find(|d| failed.contains(d)));
+        let result = match failed_dep {
+            Some(dep) => Err(DpkgError::InstallFailed(format!(
+                "skipped because its dependency {dep} failed"
+            ))),
+            None => install(std::slice::from_ref(&package), options.verbose),
+        };
+        let result = record_step(steps, &format!("{name}:{package}"), result);
+        if result.is_err() {
+            failed.push(package.clone());
+        }
+        outcomes.push(PackageOutcome {
+            name: package,
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
     Ok(())
 }
 
-fn print_plan(
+/// Pure computation: `packages` reordered so each comes after those of its dependencies
+/// that are also in `packages`. Independent packages keep their order; cycles are broken
+/// at the first package reached.
+pub fn dependency_order(
+    packages: &[String],
+    depends: &HashMap<String, Vec<String>>,
+) -> Vec<String> {
+    fn visit<'a>(
+        package: &'a String,
+        packages: &'a [String],
+        depends: &'a HashMap<String, Vec<String>>,
+        visited: &mut HashSet<&'a String>,
+        ordered: &mut Vec<String>,
+    ) {
+        if !visited.insert(package) {
+            return;
+        }
+        for dep in depends.get(package).into_iter().flatten() {
+            if let Some(dep) = packages.iter().find(|p| *p == dep) {
+                visit(dep, packages, depends, visited, ordered);
+            }
+        }
+        ordered.push(package.clone());
+    }
+
+    let mut visited = HashSet::new();
+    let mut ordered = Vec::with_capacity(packages.len());
+    for package in packages {
+        visit(package, packages, depends, &mut visited, &mut ordered);
+    }
+    ordered
+}
+
+/// One line per package retried under `--keep-going`.
+fn print_install_summary(outcomes: &[PackageOutcome]) {
+    output::plain("");
+    output::plain("Install summary:");
+    for outcome in outcomes {
+        match &outcome.error {
+            None => output::success(&format!("  {:<30} installed", outcome.name)),
+            Some(error) => {
+                let first_line = error.lines().next().unwrap_or_default();
+                output::error(&format!("  {:<30} failed: {first_line}", outcome.name));
+            }
+        }
+    }
+    let failed = outcomes.iter().filter(|o| o.error.is_some()).count();
+    output::plain(&format!(
+        "{} installed, {failed} failed",
+        outcomes.len() - failed
+    ));
+}
+
+/// Metapackage strategy: only touch the install reasons that matter. Undeclared
+/// explicit packages and the meta-package's dependencies become dependencies (so
+/// dropping a line from the config orphans the package), everything else that is
+/// declared but not an official package stays explicit.
+fn mark_for_metapackage(
+    ctx: &SyncContext,
+    options: &SyncOptions,
+    steps: &mut Vec<StepRecord>,
+) -> Result<(), DpkgError> {
+    let undeclared = filter_installed(&ctx.plan.to_remove, ctx.all_installed_set);
+    record_step(
+        steps,
+        "mark_undeclared_as_deps",
+        system::mark_as_deps(&undeclared, options.verbose),
+    )?;
+
+    let meta_name = metapackage::package_name(ctx.hostname);
+    let required = system::get_installed_depends(&meta_name)?.unwrap_or_default();
+    let required = filter_installed(&required, ctx.all_installed_set);
+    record_step(
+        steps,
+        "mark_declared_as_deps",
+        system::mark_as_deps(&required, options.verbose),
+    )?;
+
+    let installed_aur = filter_installed(ctx.desired_aur, ctx.all_installed_set);
+    let installed_archives = filter_installed(ctx.desired_archives, ctx.all_installed_set);
+    let explicit_result = system::mark_as_explicit(&installed_aur, options.verbose)
+        .and_then(|_| system::mark_as_explicit(&installed_archives, options.verbose));
+    record_step(steps, "mark_as_explicit", explicit_result)
+}
+
+/// Find the archive of every locked package that is missing or at the wrong version.
+/// Searches the pacman cache, yay's build directory (AUR) and the optional mirror.
+fn resolve_locked(
+    config_path: &Path,
+    hostname: &str,
+    desired_official: &[String],
+    desired_aur: &[String],
+    mirror: Option<&Path>,
+) -> Result<Vec<PathBuf>, DpkgError> {
+    let lock_path = lock::lock_path(config_path);
+    let lockfile = lock::load(&lock_path)?.ok_or_else(|| {
+        DpkgError::Lock(format!(
+            "No lockfile at {}\n  Hint: Run `dpkg lock` first",
+            lock_path.display()
+        ))
+    })?;
+    let locked = lockfile.for_host(hostname).ok_or_else(|| {
+        DpkgError::Lock(format!(
+            "Lockfile {} has no entry for host {hostname}\n  Hint: Run `dpkg lock` on this host",
+            lock_path.display()
+        ))
+    })?;
+
+    let installed = system::get_installed_versions()?;
+    let cache = system::list_cached_packages(&system::pacman_cache_dir());
+    let candidates = |name: &str| {
+        let mut files = cache.clone();
+        files.extend(system::list_cached_packages(&system::aur_clone_dir(name)));
+        if let Some(mirror) = mirror {
+            files.extend(lock::mirror_candidates(mirror, name));
+        }
+        files
+    };
+
+    let desired: Vec<String> = desired_official.iter().chain(desired_aur).cloned().collect();
+    lock::resolve_locked_files(&desired, &locked, &installed, &candidates)
+}
+
+/// Record the outcome of a sync step and pass the result through.
+fn record_step(
+    steps: &mut Vec<StepRecord>,
+    name: &str,
+    result: Result<(), DpkgError>,
+) -> Result<(), DpkgError> {
+    steps.push(StepRecord {
+        name: name.to_string(),
+        ok: result.is_ok(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+        code: result.as_ref().err().map(|e| e.code().to_string()),
+    });
+    result
+}
+
+/// Append this run to the history journal. Failures only warn: the sync itself
+/// has already happened and must not be reported as failed because of bookkeeping.
+fn record_history(
     config_path: &Path,
     hostname: &str,
-    to_install_official: &[String],
-    to_install_aur: &[String],
-    to_remove: &[String],
-    quiet: bool,
+    plan: &SyncPlan,
+    steps: Vec<StepRecord>,
+    versions_before: &HashMap<String, String>,
 ) {
-    if quiet {
+    let entry = history::next_id().and_then(|id| {
+        let config_hash = std::fs::read(config_path)
+            .map(|data| state::sha256_hex(&data))
+            .unwrap_or_default();
+        let versions_after = system::get_installed_versions()?;
+        Ok(JournalEntry {
+            id,
+            timestamp: state::now_unix(),
+            config_path: config_path.display().to_string(),
+            config_hash,
+            hostname: hostname.to_string(),
+            plan: plan.clone(),
+            steps,
+            versions: history::version_changes(versions_before, &versions_after),
+        })
+    });
+
+    if let Err(e) = entry.and_then(|entry| history::append(&entry)) {
+        output::warning(&format!("Could not record sync history: {e}"));
+    }
+}
+
+/// Remember group membership for drift detection; a failure does not fail the sync.
+fn record_groups(groups: &HashMap<String, Vec<String>>, declined: &[String]) {
+    if let Err(e) = groups::record(groups, declined) {
+        output::warning(&format!("Could not record group membership: {e}"));
+    }
+}
+
+fn print_group_drift(drift: &[GroupDrift]) {
+    for change in drift {
+        if !change.added.is_empty() {
+            output::warning(&format!(
+                "Group {} gained members since last sync: {}",
+                change.group,
+                change.added.join(", ")
+            ));
+        }
+        if !change.removed.is_empty() {
+            output::plain(&format!(
+                "Group {} lost members since last sync: {}",
+                change.group,
+                change.removed.join(", ")
+            ));
+        }
+    }
+}
+
+/// Snapshot the installed package set as a new generation after a successful sync.
+pub fn record_generation(hostname: &str, quiet: bool) {
+    match system::get_installed_packages()
+        .and_then(|packages| generations::record(hostname, packages))
+    {
+        Ok(Some(id)) if !quiet => output::info(&format!("Recorded generation {id}")),
+        Ok(_) => {}
+        Err(e) => output::warning(&format!("Could not record generation: {e}")),
+    }
+}
+
+fn print_plan(ctx: &SyncContext, options: &SyncOptions, simulation: Option<&Simulation>) {
+    if options.quiet {
         return;
     }
 
+    let (config_path, hostname, locked_files) = (ctx.config_path, ctx.hostname, ctx.locked_files);
+    let SyncPlan {
+        to_install_official,
+        to_install_aur,
+        to_remove,
+        ..
+    } = ctx.plan;
+    // Locked mode lists the exact archives instead of package names
+    let (to_install_official, to_install_aur): (&[String], &[String]) = if options.locked {
+        (&[], &[])
+    } else {
+        (to_install_official, to_install_aur)
+    };
+
     output::dry_run(&format!("Configuration: {}", config_path.display()));
     output::dry_run(&format!("Hostname: {hostname}"));
     println!();
 
+    if !ctx.plan.keys.is_empty() {
+        output::dry_run("Would update pacman keyring:");
+        for key in &ctx.plan.keys {
+            let action = match (key.state, &key.file) {
+                (KeyState::Missing, Some(file)) => format!("import from {}, sign", file.display()),
+                (KeyState::Missing, None) => "receive from keyserver, sign".to_string(),
+                (KeyState::Untrusted, _) => "locally sign".to_string(),
+            };
+            output::plain(&format!("  + {key} ({action})"));
+        }
+        println!();
+    }
+
+    let repo_changes = &ctx.plan.repo_changes;
+    if !repo_changes.is_empty() {
+        output::dry_run("Would update pacman repositories:");
+        for repo in &repo_changes.added {
+            output::plain(&format!("  + {repo} ({})", repo.server));
+        }
+        for repo in &repo_changes.changed {
+            output::plain(&format!("  ~ {repo} ({})", repo.server));
+        }
+        for name in &repo_changes.removed {
+            output::plain(&format!("  - repo:{name}"));
+        }
+        if repo_changes.include_missing {
+            output::plain(&format!(
+                "  Include {} from {}",
+                repos::include_path().display(),
+                repos::pacman_conf_path().display()
+            ));
+        }
+        println!();
+    }
+
+    let hold_changes = &ctx.plan.hold_changes;
+    if !hold_changes.is_empty() {
+        output::dry_run("Would update held packages (IgnorePkg):");
+        for name in &hold_changes.added {
+            output::plain(&format!("  + {name}"));
+        }
+        for name in &hold_changes.removed {
+            output::plain(&format!("  - {name}"));
+        }
+        if hold_changes.include_missing {
+            output::plain(&format!(
+                "  Include {} from [options] in {}",
+                hold::include_path().display(),
+                repos::pacman_conf_path().display()
+            ));
+        }
+        println!();
+    }
+
+    if !ctx.plan.group_drift.is_empty() {
+        output::dry_run("Groups changed since last sync:");
+        for change in &ctx.plan.group_drift {
+            for member in &change.added {
+                let note = if options.no_confirm { "" } else { " (sync asks before installing)" };
+                output::plain(&format!("  + {member} (new in {}){note}", change.group));
+            }
+            for member in &change.removed {
+                output::plain(&format!("  - {member} (no longer in {})", change.group));
+            }
+        }
+        println!();
+    }
+
+    if let Some(meta) = &ctx.plan.metapackage {
+        output::dry_run(&format!(
+            "Would build and install meta-package {} ({} dependencies)",
+            meta.name,
+            meta.depends.len()
+        ));
+        println!();
+    }
+
     if !to_install_official.is_empty() {
         output::dry_run("Would install (official):");
+        let simulated = simulation.map_or(&[][..], |s| &s.install[..]);
         for pkg in to_install_official {
-            output::plain(&format!("  {pkg}"));
+            print_simulated(pkg, simulated, "pulls in");
         }
         println!();
     }
@@ -245,24 +1467,178 @@ fn print_plan(
         println!();
     }
 
-    if !to_remove.is_empty() {
+    if !ctx.plan.optdep_install.is_empty() {
+        output::dry_run("Would install (optional dependencies, as deps):");
+        for dep in &ctx.plan.optdep_install {
+            output::plain(&format!("  {dep}"));
+        }
+        println!();
+    }
+
+    if !locked_files.is_empty() {
+        output::dry_run("Would install (locked versions):");
+        for file in locked_files {
+            output::plain(&format!("  {}", file.display()));
+        }
+        println!();
+    }
+
+    if !ctx.plan.file_install.is_empty() {
+        output::dry_run("Would install (package files):");
+        for file in &ctx.plan.file_install {
+            output::plain(&format!("  {file} ({} {})", file.name, file.version));
+        }
+        println!();
+    }
+
+    if !ctx.plan.local_install.is_empty() {
+        output::dry_run("Would build and install (local):");
+        for build in &ctx.plan.local_install {
+            output::plain(&format!("  {build} ({} {})", build.names.join(" "), build.version));
+        }
+        println!();
+    }
+
+    if !ctx.plan.flatpak_install.is_empty() {
+        output::dry_run("Would install (Flatpak):");
+        for app in &ctx.plan.flatpak_install {
+            output::plain(&format!("  {app}"));
+        }
+        println!();
+    }
+
+    for (tool, names) in by_tool(&ctx.plan.lang_install) {
+        output::dry_run(&format!("Would install ({}):", tool.name()));
+        for name in names {
+            output::plain(&format!("  {name}"));
+        }
+        println!();
+    }
+
+    let lang_remove = &ctx.plan.lang_remove;
+    if !to_remove.is_empty() || !ctx.plan.flatpak_remove.is_empty() || !lang_remove.is_empty() {
         output::dry_run("Would remove:");
+        let simulated = simulation.map_or(&[][..], |s| &s.remove[..]);
         for pkg in to_remove {
+            print_simulated(pkg, simulated, "also removes");
+        }
+        for app in &ctx.plan.flatpak_remove {
+            output::plain(&format!("  {app}"));
+        }
+        for pkg in lang_remove {
             output::plain(&format!("  {pkg}"));
         }
         println!();
     }
 
-    if to_install_official.is_empty() && to_install_aur.is_empty() && to_remove.is_empty() {
+    if let Some(simulation) = simulation {
+        print_transaction_summary(simulation);
+    }
+
+    let enable: Vec<&Service> = ctx.plan.service_changes.iter().filter(|s| s.enabled).collect();
+    if !enable.is_empty() {
+        output::dry_run("Would enable services:");
+        for svc in enable {
+            output::plain(&format!("  {svc}"));
+        }
+        println!();
+    }
+
+    let disable: Vec<&Service> = ctx.plan.service_changes.iter().filter(|s| !s.enabled).collect();
+    if !disable.is_empty() {
+        output::dry_run("Would disable services:");
+        for svc in disable {
+            output::plain(&format!("  {svc}"));
+        }
+        println!();
+    }
+
+    let triggered = hooks::planned(ctx.hooks, ctx.plan);
+    if !triggered.is_empty() {
+        output::dry_run("Would run hooks:");
+        for hook in &triggered {
+            output::plain(&format!("  {}: {}", hook.event, hook.command));
+        }
+        println!();
+    }
+
+    if to_install_official.is_empty()
+        && to_install_aur.is_empty()
+        && to_remove.is_empty()
+        && locked_files.is_empty()
+        && ctx.plan.service_changes.is_empty()
+        && ctx.plan.flatpak_install.is_empty()
+        && ctx.plan.flatpak_remove.is_empty()
+        && ctx.plan.lang_install.is_empty()
+        && lang_remove.is_empty()
+        && ctx.plan.local_install.is_empty()
+        && ctx.plan.file_install.is_empty()
+        && ctx.plan.optdep_install.is_empty()
+        && ctx.plan.metapackage.is_none()
+        && repo_changes.is_empty()
+        && ctx.plan.keys.is_empty()
+        && hold_changes.is_empty()
+    {
         output::dry_run("No changes needed");
     } else {
         output::dry_run("No changes made (dry run)");
     }
 }
 
-fn confirm_removal() -> Result<bool, DpkgError> {
+/// One planned package with what pacman resolved for it, if anything.
+fn print_simulated(name: &str, simulated: &[SimulatedPackage], pulls_in: &str) {
+    let Some(package) = simulated.iter().find(|p| p.package == name) else {
+        output::plain(&format!("  {name}"));
+        return;
+    };
+    let download = if package.download_size > 0 {
+        format!("download {}, ", simulate::format_size(package.download_size as i64, false))
+    } else {
+        String::new()
+    };
+    output::plain(&format!(
+        "  {name} {} ({download}installed {})",
+        package.version,
+        simulate::format_size(package.installed_size_delta, true)
+    ));
+    if !package.dependencies.is_empty() {
+        let count = package.dependencies.len();
+        output::plain(&format!(
+            "      {pulls_in} {count} {}: {}",
+            if count == 1 { "package" } else { "packages" },
+            package.dependencies.join(", ")
+        ));
+    }
+    for conflict in &package.conflicts {
+        output::warning(&format!("      conflict: {conflict}"));
+    }
+}
+
+/// Totals of the simulated transactions, and whatever pacman could not resolve.
+fn print_transaction_summary(simulation: &Simulation) {
+    if simulation.install.is_empty() && simulation.remove.is_empty() && simulation.errors.is_empty() {
+        return;
+    }
+    output::dry_run(&format!(
+        "Transaction: download {}, installed size {}",
+        simulate::format_size(simulation.download_size as i64, false),
+        simulate::format_size(simulation.installed_size_delta, true)
+    ));
+    if !simulation.other_dependencies.is_empty() {
+        output::plain(&format!("  Also pulled in: {}", simulation.other_dependencies.join(", ")));
+    }
+    for conflict in &simulation.conflicts {
+        output::warning(&format!("  Conflict: {conflict}"));
+    }
+    for error in &simulation.errors {
+        output::warning(&format!("  pacman: {error}"));
+    }
+    println!();
+}
+
+fn confirm(question: &str) -> Result<bool, DpkgError> {
     println!();
-    print!("Proceed with removal? [y/N]: ");
+    print!("{question} [y/N]: ");
     io::stdout().flush().ok();
 
     let mut input = String::new();
@@ -277,7 +1653,7 @@ fn confirm_removal() -> Result<bool, DpkgError> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{collect_packages, parse_config_str};
+    use crate::config::{collect_exclusions, collect_optdeps, collect_packages, parse_config_str};
 
     fn s(val: &str) -> String {
         val.to_string()
@@ -335,7 +1711,7 @@ mod tests {
         let fake_aur = s("zzz-nonexistent-aur-pkg-test");
         assert!(!installed.contains(&fake_aur));
 
-        let plan = compute_sync_plan(&[], &[fake_aur.clone()], &installed, vec![]);
+        let plan = compute_sync_plan(&[], std::slice::from_ref(&fake_aur), &installed, vec![]);
         assert_eq!(plan.to_install_aur, vec![fake_aur]);
     }
 
@@ -366,7 +1742,7 @@ mod tests {
             .find(|p| installed.contains(p))
             .expect("at least one AUR package should be explicitly installed");
 
-        let plan = compute_sync_plan(&[], &[aur_pkg.clone()], &installed, vec![]);
+        let plan = compute_sync_plan(&[], std::slice::from_ref(aur_pkg), &installed, vec![]);
         assert!(
             plan.to_install_aur.is_empty(),
             "should not reinstall already-installed AUR package {aur_pkg}, got: {:?}",
@@ -397,7 +1773,7 @@ mod tests {
 
         let orphan = orphans[0].clone();
         let plan = compute_sync_plan(
-            &[orphan.clone()],
+            std::slice::from_ref(&orphan),
             &[],
             &sv(&[]),
             orphans.clone(),
@@ -514,4 +1890,222 @@ mod tests {
         let result = filter_installed(&desired, &all_installed);
         assert!(result.is_empty());
     }
+
+    // ── Test 13: services are only changed when not in their declared state ──
+
+    #[test]
+    fn service_changes_only_for_mismatches() {
+        let svc = |unit: &str, enabled: bool, user: bool| Service {
+            unit: unit.to_string(),
+            enabled,
+            user,
+        };
+        let desired = vec![
+            svc("docker.service", true, false),
+            svc("bluetooth.service", false, false),
+            svc("pipewire.service", true, true),
+            svc("sshd.service", true, false),
+        ];
+        let enabled: HashSet<(String, bool)> = [
+            (s("bluetooth.service"), false),
+            (s("sshd.service"), false),
+            // enabled for the system manager only; the user unit is still disabled
+            (s("pipewire.service"), false),
+        ]
+        .into();
+
+        let changes = compute_service_changes(&desired, &enabled);
+        assert_eq!(
+            changes,
+            vec![
+                svc("docker.service", true, false),
+                svc("bluetooth.service", false, false),
+                svc("pipewire.service", true, true),
+            ]
+        );
+    }
+
+    // ── Test 14: Flatpak apps are matched per installation ──
+
+    #[test]
+    fn flatpak_changes_per_installation() {
+        let app = |id: &str, user: bool| FlatpakApp {
+            id: id.to_string(),
+            remote: None,
+            user,
+        };
+        let desired = vec![
+            app("org.mozilla.firefox", false),
+            app("com.spotify.Client", true),
+        ];
+        let system_apps = sv(&["org.mozilla.firefox", "com.spotify.Client"]);
+        let user_apps = sv(&["org.gimp.GIMP"]);
+
+        let (install, remove) = compute_flatpak_changes(&desired, &system_apps, &user_apps);
+        assert_eq!(install, vec![app("com.spotify.Client", true)]);
+        assert_eq!(
+            remove,
+            vec![app("com.spotify.Client", false), app("org.gimp.GIMP", true)]
+        );
+
+        // Only the installation with declarations is reconciled
+        let (_, remove) = compute_flatpak_changes(&desired[..1], &system_apps, &user_apps);
+        assert_eq!(remove, vec![app("com.spotify.Client", false)]);
+        let (install, remove) = compute_flatpak_changes(&[], &system_apps, &user_apps);
+        assert!(install.is_empty() && remove.is_empty());
+    }
+
+    // ── Test 15: only tools with declarations are reconciled ──
+
+    #[test]
+    fn lang_changes_only_for_managed_tools() {
+        let pkg = |tool: LangTool, name: &str| LangPackage {
+            tool,
+            name: name.to_string(),
+        };
+        let desired = vec![pkg(LangTool::Cargo, "ripgrep"), pkg(LangTool::Cargo, "bat")];
+        let installed: BTreeMap<LangTool, Vec<String>> =
+            [(LangTool::Cargo, sv(&["bat", "cargo-edit"]))].into();
+
+        let (install, remove) = compute_lang_changes(&desired, &installed);
+        assert_eq!(install, vec![pkg(LangTool::Cargo, "ripgrep")]);
+        assert_eq!(remove, vec![pkg(LangTool::Cargo, "cargo-edit")]);
+    }
+
+    // ── Test 16: --only-source drops other sources from the plan ──
+
+    #[test]
+    fn only_source_restricts_plan() {
+        let plan = SyncPlan {
+            to_install_official: sv(&["git"]),
+            to_install_aur: sv(&["yay-bin"]),
+            to_remove: sv(&["tlp"]),
+            service_changes: vec![Service {
+                unit: s("sshd.service"),
+                enabled: true,
+                user: false,
+            }],
+            lang_install: vec![
+                LangPackage { tool: LangTool::Cargo, name: s("ripgrep") },
+                LangPackage { tool: LangTool::Npm, name: s("prettier") },
+            ],
+            ..Default::default()
+        };
+
+        let mut unfiltered = plan.clone();
+        restrict_to_sources(&mut unfiltered, &[]);
+        assert_eq!(unfiltered, plan);
+
+        let mut cargo_only = plan.clone();
+        restrict_to_sources(&mut cargo_only, &[Source::Cargo]);
+        assert_eq!(
+            cargo_only,
+            SyncPlan {
+                lang_install: vec![LangPackage { tool: LangTool::Cargo, name: s("ripgrep") }],
+                ..Default::default()
+            }
+        );
+
+        let mut aur_only = plan.clone();
+        restrict_to_sources(&mut aur_only, &[Source::Aur]);
+        assert!(aur_only.to_install_official.is_empty());
+        assert_eq!(aur_only.to_install_aur, sv(&["yay-bin"]));
+        assert!(aur_only.to_remove.is_empty());
+
+        let mut pacman_only = plan;
+        restrict_to_sources(&mut pacman_only, &[Source::Official, Source::Aur]);
+        assert_eq!(pacman_only.to_remove, sv(&["tlp"]));
+    }
+
+    // ── Test 17: optional dependencies must be listed by their parent ──
+
+    #[test]
+    fn optdeps_checked_against_parent() {
+        let config = parse_config_str("## *\nmpv +yt-dlp +vapoursynth\naur:mytool +helper\n").unwrap();
+        let optdeps = collect_optdeps(&config, "host");
+        assert_eq!(optdeps.len(), 3);
+        assert_eq!(optdeps[0].parent, "mpv");
+        assert_eq!(optdeps[0].line, 2);
+
+        // mytool is unknown to pacman, so its optdeps are not checked
+        let mut optdepends: HashMap<String, Vec<String>> =
+            [(s("mpv"), sv(&["yt-dlp", "vapoursynth", "lua52"]))].into();
+        assert!(check_optdeps(&optdeps, &optdepends).is_ok());
+
+        optdepends.insert(s("mpv"), sv(&["yt-dlp"]));
+        let err = check_optdeps(&optdeps, &optdepends).unwrap_err();
+        assert!(matches!(err, DpkgError::ConfigParse(ref d) if d[0].span.line == 2));
+        assert!(err.to_string().contains("`vapoursynth` is not an optional dependency of `mpv`"));
+    }
+
+    // ── Test 18: exclusions must name members of a declared group ──
+
+    #[test]
+    fn exclusions_checked_against_group() {
+        let config = parse_config_str("## *\ngnome -epiphany\ngit\n").unwrap();
+        let exclusions = collect_exclusions(&config, "host");
+        let mut groups: HashMap<String, Vec<String>> =
+            [(s("gnome"), sv(&["nautilus", "epiphany"]))].into();
+        assert!(check_exclusions(&exclusions, &groups).is_ok());
+        assert_eq!(
+            system::expand_package_groups(&sv(&["gnome", "git"]), &groups, &exclusions),
+            sv(&["nautilus", "git"])
+        );
+
+        groups.insert(s("gnome"), sv(&["nautilus"]));
+        let err = check_exclusions(&exclusions, &groups).unwrap_err();
+        assert!(matches!(err, DpkgError::ConfigParse(ref d) if d[0].span.line == 2));
+        assert!(check_exclusions(&exclusions, &HashMap::new()).is_err());
+    }
+
+    // ── Test 19: --keep-going retries dependencies before their dependents ──
+
+    #[test]
+    fn dependency_order_puts_dependencies_first() {
+        let depends: HashMap<String, Vec<String>> = [
+            (s("app"), sv(&["libfoo", "glibc"])),
+            (s("libfoo"), sv(&["libbar"])),
+            (s("cycle-a"), sv(&["cycle-b"])),
+            (s("cycle-b"), sv(&["cycle-a"])),
+        ]
+        .into();
+        assert_eq!(
+            dependency_order(&sv(&["app", "git", "libbar", "libfoo"]), &depends),
+            sv(&["libbar", "libfoo", "app", "git"])
+        );
+        assert_eq!(
+            dependency_order(&sv(&["cycle-a", "cycle-b"]), &depends),
+            sv(&["cycle-b", "cycle-a"])
+        );
+        assert_eq!(dependency_order(&sv(&["b", "a"]), &HashMap::new()), sv(&["b", "a"]));
+    }
+
+    // ── Test 20: an alternate root only takes what can be installed into it ──
+
+    #[test]
+    fn target_support_rejects_host_only_declarations() {
+        let config = parse_config_str(
+            "## *\ngit\nservice:sshd\n\n## @desktop\naur:yay-bin\nuser-service:syncthing\n",
+        )
+        .unwrap();
+        let (_, aur) = collect_packages(&config, "image");
+        assert!(check_target_support(&aur, false, &[], &[], &collect_services(&config, "image")).is_ok());
+
+        // AUR packages are fine as long as they can be built on the host
+        let (_, aur) = collect_packages(&config, "desktop");
+        assert!(check_target_support(&aur, true, &[], &[], &[]).is_ok());
+        let err = check_target_support(&aur, false, &[], &[], &[]).unwrap_err();
+        assert_eq!(err.code(), "DPKG-E024");
+        assert!(err.to_string().contains("yay-bin"));
+        let err = check_target_support(&[], true, &[], &[], &collect_services(&config, "desktop")).unwrap_err();
+        assert!(err.to_string().contains("user-service:syncthing"));
+
+        let in_repos = HashMap::from([(
+            "ffmpeg".to_string(),
+            RepoInfo { repo: "extra".to_string(), version: "2:7.1-1".to_string() },
+        )]);
+        assert!(check_target_optdeps(&sv(&["ffmpeg"]), &in_repos).is_ok());
+        let err = check_target_optdeps(&sv(&["ffmpeg", "mpv-git"]), &in_repos).unwrap_err();
+        assert!(err.to_string().contains("mpv-git") && !err.to_string().contains("ffmpeg"));
+    }
 }
@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use crate::config::{parse_config, PackageSource};
+use crate::error::DpkgError;
+use crate::output;
+use crate::system;
+
+/// Explain why `package` is (or would be) on this host: the config lines that declare
+/// it, directly, through a group or as an optional dependency, and otherwise what
+/// pacman says requires it.
+pub fn run(config_path: &Path, package: &str, quiet: bool) -> Result<(), DpkgError> {
+    let config = parse_config(config_path)?;
+    let hostname = system::get_hostname()?;
+
+    let group_names: Vec<String> = config
+        .sections
+        .iter()
+        .flat_map(|s| s.packages.iter())
+        .filter(|p| p.source == PackageSource::Official)
+        .map(|p| p.name.clone())
+        .collect();
+    let groups = system::get_group_members(&group_names)?;
+
+    let mut reasons = Vec::new();
+    let mut other_hosts = Vec::new();
+    for section in &config.sections {
+        for declared in &section.packages {
+            let location = format!("{} (line {})", section.header, declared.line);
+            let reason = if declared.name == package {
+                let hold = if declared.hold { ", held" } else { "" };
+                Some(format!("declared in {location}{hold}"))
+            } else if declared.optdeps.iter().any(|d| d == package) {
+                Some(format!("optional dependency of {}, declared in {location}", declared.name))
+            } else if groups.get(&declared.name).is_some_and(|m| m.iter().any(|p| p == package))
+                && !declared.excludes.iter().any(|e| e == package)
+            {
+                Some(format!("member of group {}, declared in {location}", declared.name))
+            } else {
+                None
+            };
+            match reason {
+                Some(reason) if section.applies_to(&hostname) => reasons.push(reason),
+                Some(reason) => other_hosts.push(reason),
+                None => {}
+            }
+        }
+    }
+
+    let installed = system::get_installed_info(package)?;
+    if quiet {
+        return Ok(());
+    }
+
+    if reasons.is_empty() {
+        output::warning(&format!("{package} is not declared for {hostname}"));
+    } else {
+        output::success(&format!("{package} is wanted on {hostname}:"));
+        for reason in &reasons {
+            output::plain(&format!("  {reason}"));
+        }
+    }
+    for reason in &other_hosts {
+        output::plain(&format!("  {reason}, which does not apply to {hostname}"));
+    }
+
+    let Some(fields) = installed else {
+        output::plain("  Not installed");
+        return Ok(());
+    };
+    let field = |key: &str| fields.get(key).filter(|v| v.as_str() != "None");
+    if let Some(version) = fields.get("Version") {
+        output::plain(&format!("  Installed: {version}"));
+    }
+    if let Some(reason) = fields.get("Install Reason") {
+        output::plain(&format!("  Install reason: {reason}"));
+    }
+    if let Some(required_by) = field("Required By") {
+        output::plain(&format!("  Required by: {}", required_by.split_whitespace().collect::<Vec<_>>().join(", ")));
+    }
+    if let Some(optional_for) = field("Optional For") {
+        output::plain(&format!("  Optional for: {}", optional_for.split_whitespace().collect::<Vec<_>>().join(", ")));
+    }
+    Ok(())
+}
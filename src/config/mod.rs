@@ -1,7 +1,10 @@
 pub mod parser;
 pub mod types;
 
-pub use parser::parse_config;
-#[cfg(test)]
-pub use parser::parse_config_str;
-pub use types::{collect_packages, Header, PackageSource};
+pub use parser::{parse_config, parse_config_str, read_config};
+pub use types::{
+    collect_aur_user, collect_escalation, collect_exclusions, collect_file_packages, collect_flatpaks, collect_held, collect_hooks, collect_keys,
+    collect_lang_packages, collect_local_packages, collect_optdeps, collect_packages, collect_repos,
+    collect_services, Config, Escalation, FlatpakApp, GroupExclusion, Header, Hook, HookEvent, LangPackage, LangTool, OptDep,
+    Package, PackageSource, PacmanRepo, Section, Service, SigningKey, valid_user_name,
+};
@@ -1,123 +1,456 @@
 use std::path::Path;
 
-use crate::config::types::{Config, Header, Package, PackageSource, Section};
-use crate::error::DpkgError;
+use crate::config::types::{
+    Config, Escalation, Header, Hook, HookEvent, LangTool, Package, PackageSource, PacmanRepo, Section,
+    Service, SigningKey, valid_user_name,
+};
+use crate::error::{Diagnostic, DpkgError, Span};
 
 pub fn parse_config(path: &Path) -> Result<Config, DpkgError> {
+    parse_config_str(&read_config(path)?)
+}
+
+/// Read the config file, telling a missing file apart from an unreadable one.
+pub fn read_config(path: &Path) -> Result<String, DpkgError> {
     if !path.exists() {
         return Err(DpkgError::ConfigNotFound {
             path: path.to_path_buf(),
         });
     }
-    let content = std::fs::read_to_string(path).map_err(|e| {
+    std::fs::read_to_string(path).map_err(|e| {
         if e.kind() == std::io::ErrorKind::PermissionDenied {
             DpkgError::PermissionDenied(format!("Cannot read config file: {}", path.display()))
         } else {
-            DpkgError::ConfigParse {
-                line: 0,
-                message: format!("Failed to read config file: {e}"),
+            DpkgError::ConfigUnreadable {
+                path: path.to_path_buf(),
+                reason: e.to_string(),
             }
         }
-    })?;
-    parse_config_str(&content)
+    })
+}
+
+/// Split a raw line into its content and its `//` comment, if any. The first `//`
+/// starts the comment, except the `//` of a `scheme://` URL in a `file:` or `repo:` value.
+pub fn split_comment(raw_line: &str) -> (&str, Option<&str>) {
+    let trimmed = raw_line.trim_start();
+    let value_start = ["file:", "repo:"]
+        .iter()
+        .find(|prefix| trimmed.starts_with(*prefix))
+        .map(|prefix| raw_line.len() - trimmed.len() + prefix.len());
+
+    let comment = raw_line.match_indices("//").map(|(pos, _)| pos).find(|&pos| {
+        !value_start.is_some_and(|start| pos > start && ends_with_scheme(&raw_line[start..pos]))
+    });
+    match comment {
+        Some(pos) => (&raw_line[..pos], Some(&raw_line[pos + 2..])),
+        None => (raw_line, None),
+    }
 }
 
+/// Whether `text` ends with a URL scheme and its colon, like `https:`.
+fn ends_with_scheme(text: &str) -> bool {
+    let Some(before_colon) = text.strip_suffix(':') else {
+        return false;
+    };
+    let scheme_start = before_colon
+        .rfind(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')))
+        .map_or(0, |i| i + 1);
+    before_colon[scheme_start..]
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic())
+}
+
+/// A config line being parsed, for pointing errors at the part of it they concern.
+struct SourceLine<'a> {
+    text: &'a str,
+    num: usize,
+}
+
+impl SourceLine<'_> {
+    /// Span of `part`, a slice of this line. An empty `part` marks where something is missing.
+    fn span(&self, part: &str) -> Span {
+        let offset = (part.as_ptr() as usize)
+            .checked_sub(self.text.as_ptr() as usize)
+            .filter(|&o| o <= self.text.len() && self.text.is_char_boundary(o))
+            .unwrap_or(0);
+        Span {
+            line: self.num,
+            column: self.text[..offset].chars().count() + 1,
+            length: part.chars().count().max(1),
+        }
+    }
+
+    fn error(&self, part: &str, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(self.span(part), message).with_source(self.text)
+    }
+}
+
+/// Parse the whole config, collecting every error instead of stopping at the first.
 pub fn parse_config_str(input: &str) -> Result<Config, DpkgError> {
     let mut sections: Vec<Section> = Vec::new();
+    let mut errors: Vec<Diagnostic> = Vec::new();
 
     for (line_num_0, raw_line) in input.lines().enumerate() {
-        let line_num = line_num_0 + 1;
-
-        // Strip comments: find first `//`, take everything before it
-        let line = match raw_line.find("//") {
-            Some(pos) => &raw_line[..pos],
-            None => raw_line,
+        let source = SourceLine {
+            text: raw_line,
+            num: line_num_0 + 1,
         };
 
-        let line = line.trim();
+        let line = split_comment(raw_line).0.trim();
 
         if line.is_empty() {
             continue;
         }
 
-        // Section header
-        if let Some(after_hashes) = line.strip_prefix("##") {
-            // Must have a space after ##
-            if !after_hashes.starts_with(' ') {
-                return Err(DpkgError::ConfigParse {
-                    line: line_num,
-                    message: format!(
-                        "Invalid section header: `{line}`\n  Expected: ## * or ## @<hostname>\n  Hint: Section headers must have a space after ##"
-                    ),
-                });
-            }
+        if let Err(e) = parse_line(line, &source, &mut sections) {
+            errors.push(e);
+        }
+    }
 
-            let header_value = after_hashes[1..].trim();
-
-            let header = if header_value == "*" {
-                Header::All
-            } else if let Some(hostname) = header_value.strip_prefix('@') {
-                let hostname = hostname.trim();
-                if hostname.is_empty() {
-                    return Err(DpkgError::ConfigParse {
-                        line: line_num,
-                        message: "Empty hostname in section header".to_string(),
-                    });
-                }
-                // Validate hostname: alphanumeric and hyphens
-                if !hostname
-                    .chars()
-                    .all(|c| c.is_alphanumeric() || c == '-')
-                {
-                    return Err(DpkgError::ConfigParse {
-                        line: line_num,
-                        message: format!(
-                            "Invalid hostname `{hostname}`: only alphanumeric characters and hyphens are allowed"
-                        ),
-                    });
-                }
-                Header::Hostname(hostname.to_string())
-            } else {
-                return Err(DpkgError::ConfigParse {
-                    line: line_num,
-                    message: format!(
-                        "Invalid section header: `{line}`\n  Expected: ## * or ## @<hostname>"
-                    ),
-                });
-            };
-
-            sections.push(Section {
-                header,
-                packages: Vec::new(),
-            });
-            continue;
+    if errors.is_empty() {
+        Ok(Config { sections })
+    } else {
+        Err(DpkgError::ConfigParse(errors))
+    }
+}
+
+/// Parse one non-empty line (without its comment) into `sections`.
+fn parse_line(line: &str, source: &SourceLine, sections: &mut Vec<Section>) -> Result<(), Diagnostic> {
+    // Section header
+    if let Some(after_hashes) = line.strip_prefix("##") {
+        let header = parse_header(line, after_hashes, source);
+        // An invalid header still opens a section, so the lines below it are checked too
+        sections.push(Section {
+            line: source.num,
+            ..Section::new(header.clone().unwrap_or(Header::All))
+        });
+        return header.map(|_| ());
+    }
+
+    // Package line — must be inside a section
+    if sections.is_empty() {
+        // Reported once; the lines up to the first header are checked as if under `## *`
+        sections.push(Section::new(Header::All));
+        return Err(source
+            .error(line, "Package found before any section header")
+            .with_hint("Start the file with ## * or ## @<hostname>"));
+    }
+    let section = sections.last_mut().unwrap();
+
+    if let Some(hook_spec) = line.strip_prefix("hook:") {
+        section.hooks.push(parse_hook(hook_spec, source)?);
+        return Ok(());
+    }
+
+    if let Some((user, unit)) = line
+        .strip_prefix("service:")
+        .map(|u| (false, u))
+        .or_else(|| line.strip_prefix("user-service:").map(|u| (true, u)))
+    {
+        let unit = unit.trim();
+        let (enabled, unit) = match unit.strip_prefix('!') {
+            Some(disabled) => (false, disabled.trim()),
+            None => (true, unit),
+        };
+        if unit.is_empty() {
+            return Err(source.error(unit, "Empty unit name in service declaration"));
         }
+        section.services.push(Service {
+            unit: unit.to_string(),
+            enabled,
+            user,
+        });
+        return Ok(());
+    }
+
+    if let Some(spec) = line.strip_prefix("key:") {
+        section.keys.push(parse_key(spec, source)?);
+        return Ok(());
+    }
+
+    if let Some(method) = line.strip_prefix("escalate:") {
+        let method = Escalation::parse(method).ok_or_else(|| {
+            source
+                .error(line, "Empty escalation method")
+                .with_hint("Expected escalate:auto, none, sudo, doas, run0, pkexec or a command")
+        })?;
+        section.escalation.get_or_insert(method);
+        return Ok(());
+    }
 
-        // Package line — must be inside a section
-        if sections.is_empty() {
-            return Err(DpkgError::ConfigParse {
-                line: line_num,
-                message: "Package found before any section header".to_string(),
-            });
+    if let Some(user) = line.strip_prefix("aur-user:") {
+        let user = user.trim();
+        if !valid_user_name(user) {
+            return Err(source
+                .error(line, "Invalid AUR build user")
+                .with_hint("Expected aur-user:<name> with letters, digits, _, - or ."));
         }
+        section.aur_user.get_or_insert_with(|| user.to_string());
+        return Ok(());
+    }
 
-        let (name, source) = if let Some(aur_name) = line.strip_prefix("aur:") {
-            let aur_name = aur_name.trim();
-            if aur_name.is_empty() {
-                return Err(DpkgError::ConfigParse {
-                    line: line_num,
-                    message: "Empty AUR package name after `aur:` prefix".to_string(),
-                });
-            }
-            (aur_name.to_string(), PackageSource::Aur)
-        } else {
-            (line.to_string(), PackageSource::Official)
+    if let Some(spec) = line.strip_prefix("repo:") {
+        section.repos.push(parse_repo(spec, source)?);
+        return Ok(());
+    }
+
+    let (line, hold) = parse_attributes(line, source)?;
+
+    let (name, source_kind) = if let Some((user, spec)) = line
+        .strip_prefix("flatpak:")
+        .map(|s| (false, s))
+        .or_else(|| line.strip_prefix("flatpak-user:").map(|s| (true, s)))
+    {
+        let (remote, app_id) = match spec.trim().split_once('/') {
+            Some((remote, app_id)) => (Some(remote.trim()), app_id.trim()),
+            None => (None, spec.trim()),
         };
+        if app_id.is_empty() || remote == Some("") {
+            return Err(source
+                .error(spec.trim(), "Invalid Flatpak declaration")
+                .with_hint("Expected flatpak:[remote/]<app-id>"));
+        }
+        let remote = remote.map(str::to_string);
+        (app_id, PackageSource::Flatpak { remote, user })
+    } else if let Some((tool, tool_name)) = LangTool::ALL
+        .into_iter()
+        .find_map(|tool| Some((tool, line.strip_prefix(tool.name())?.strip_prefix(':')?)))
+    {
+        let tool_name = tool_name.trim();
+        if tool_name.is_empty() {
+            return Err(source.error(
+                tool_name,
+                format!("Empty package name after `{}:` prefix", tool.name()),
+            ));
+        }
+        (tool_name, PackageSource::Lang(tool))
+    } else if let Some(path) = line.strip_prefix("local:") {
+        let path = path.trim();
+        if path.is_empty() {
+            return Err(source
+                .error(path, "Empty path after `local:` prefix")
+                .with_hint("Expected local:<PKGBUILD directory>"));
+        }
+        (path, PackageSource::Local)
+    } else if let Some(file) = line.strip_prefix("file:") {
+        let file = file.trim();
+        if file.is_empty() {
+            return Err(source
+                .error(file, "Empty path after `file:` prefix")
+                .with_hint("Expected file:<package archive path or URL>"));
+        }
+        (file, PackageSource::File)
+    } else if let Some(aur_name) = line.strip_prefix("aur:") {
+        let aur_name = aur_name.trim();
+        if aur_name.is_empty() {
+            return Err(source.error(aur_name, "Empty AUR package name after `aur:` prefix"));
+        }
+        (aur_name, PackageSource::Aur)
+    } else {
+        (line, PackageSource::Official)
+    };
+
+    let pacman = matches!(source_kind, PackageSource::Official | PackageSource::Aur);
+    if let Some(attr) = hold
+        && !pacman
+    {
+        return Err(source.error(
+            attr,
+            format!("`[hold]` only applies to repository and AUR packages, not `{line}`"),
+        ));
+    }
+
+    let (name, optdeps, excludes) = if pacman {
+        split_modifiers(name, source_kind == PackageSource::Official, source)?
+    } else {
+        (name.to_string(), Vec::new(), Vec::new())
+    };
+
+    section.packages.push(Package {
+        name,
+        source: source_kind,
+        hold: hold.is_some(),
+        optdeps,
+        excludes,
+        line: source.num,
+    });
+    Ok(())
+}
 
-        sections.last_mut().unwrap().packages.push(Package { name, source });
+/// Parse a section header line; `after_hashes` is the part after `##`.
+fn parse_header(line: &str, after_hashes: &str, source: &SourceLine) -> Result<Header, Diagnostic> {
+    // Must have a space after ##
+    if !after_hashes.starts_with(' ') {
+        return Err(source
+            .error(line, format!("Invalid section header: `{line}`"))
+            .with_hint("Section headers must have a space after ##, e.g. ## * or ## @<hostname>"));
     }
 
-    Ok(Config { sections })
+    let header_value = after_hashes[1..].trim();
+
+    if header_value == "*" {
+        Ok(Header::All)
+    } else if let Some(hostname) = header_value.strip_prefix('@') {
+        let hostname = hostname.trim();
+        if hostname.is_empty() {
+            return Err(source.error(header_value, "Empty hostname in section header"));
+        }
+        // Validate hostname: alphanumeric and hyphens
+        if !hostname
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-')
+        {
+            return Err(source
+                .error(hostname, format!("Invalid hostname `{hostname}`"))
+                .with_hint("Only alphanumeric characters and hyphens are allowed"));
+        }
+        Ok(Header::Hostname(hostname.to_string()))
+    } else {
+        Err(source
+            .error(header_value, format!("Invalid section header: `{line}`"))
+            .with_hint("Expected ## * or ## @<hostname>"))
+    }
+}
+
+/// Parse the part of a repo line after `repo:`, e.g. `company file:///srv/repo Optional TrustAll`.
+fn parse_repo(spec: &str, source: &SourceLine) -> Result<PacmanRepo, Diagnostic> {
+    let mut parts = spec.split_whitespace();
+    let (Some(name), Some(server)) = (parts.next(), parts.next()) else {
+        return Err(source
+            .error(spec.trim(), "Incomplete repository declaration")
+            .with_hint("Expected repo:<name> <server> [<SigLevel>]"));
+    };
+    // `local` is the name of pacman's database of installed packages
+    if name.contains(['[', ']']) || name == "local" {
+        return Err(source.error(name, format!("Invalid repository name `{name}`")));
+    }
+    if !server.contains("://") {
+        return Err(source
+            .error(server, format!("Repository server `{server}` is not a URL"))
+            .with_hint("Local repositories use file:///path/to/repo"));
+    }
+    let sig_level = parts.collect::<Vec<_>>().join(" ");
+
+    Ok(PacmanRepo {
+        name: name.to_string(),
+        server: server.to_string(),
+        sig_level: (!sig_level.is_empty()).then_some(sig_level),
+    })
+}
+
+/// Split `mpv +yt-dlp +vapoursynth` or `gnome -epiphany` into the package, its
+/// optional dependencies and its excluded group members. Only repository groups
+/// (`allow_excludes`) can exclude members.
+fn split_modifiers(
+    spec: &str,
+    allow_excludes: bool,
+    source: &SourceLine,
+) -> Result<(String, Vec<String>, Vec<String>), Diagnostic> {
+    let mut parts = spec.split_whitespace();
+    let name = parts.next().unwrap_or_default().to_string();
+    let (mut optdeps, mut excludes) = (Vec::new(), Vec::new());
+    for part in parts {
+        match part.split_at_checked(1) {
+            Some(("+", optdep)) if !optdep.is_empty() => optdeps.push(optdep.to_string()),
+            Some(("-", _)) if !allow_excludes => {
+                return Err(source.error(part, "`-<member>` exclusions only apply to repository groups"));
+            }
+            Some(("-", member)) if !member.is_empty() => excludes.push(member.to_string()),
+            _ => {
+                return Err(source
+                    .error(part, format!("Unexpected `{part}` after package name"))
+                    .with_hint("Expected <package> [+<optional dependency> ...] [-<group member> ...] [hold]"));
+            }
+        }
+    }
+    Ok((name, optdeps, excludes))
+}
+
+/// Split trailing attributes off a package line, e.g. `linux-lts [hold]`.
+/// Returns the rest of the line and, if the package is held, the `hold` attribute.
+fn parse_attributes<'a>(
+    line: &'a str,
+    source: &SourceLine,
+) -> Result<(&'a str, Option<&'a str>), Diagnostic> {
+    // Attributes follow whitespace, so a `[` inside a name or URL is left alone
+    let Some(open) = line
+        .match_indices(" [")
+        .chain(line.match_indices("\t["))
+        .map(|(i, _)| i + 1)
+        .min()
+    else {
+        return Ok((line, None));
+    };
+    let Some(attrs) = line[open + 1..].trim_end().strip_suffix(']') else {
+        return Err(source
+            .error(&line[open..], "Unclosed `[` in package attributes")
+            .with_hint("Expected <package> [hold]"));
+    };
+
+    let mut hold = None;
+    for attr in attrs.split(',').map(str::trim) {
+        match attr {
+            "hold" => hold = Some(attr),
+            _ => {
+                return Err(source
+                    .error(attr, format!("Unknown package attribute `{attr}`"))
+                    .with_hint("Known attributes: hold"));
+            }
+        }
+    }
+    Ok((line[..open].trim_end(), hold))
+}
+
+/// Parse the part of a key line after `key:`, e.g. `0123…CDEF ./keys/company.asc`.
+fn parse_key(spec: &str, source: &SourceLine) -> Result<SigningKey, Diagnostic> {
+    let mut parts = spec.split_whitespace();
+    let fingerprint = parts.next().unwrap_or(spec.trim());
+    if fingerprint.len() != 40 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(source
+            .error(fingerprint, format!("Invalid key fingerprint `{fingerprint}`"))
+            .with_hint("Expected key:<40 hex digit fingerprint> [<key file>]"));
+    }
+    let file = parts.next().map(|f| f.to_string());
+    if let Some(extra) = parts.next() {
+        return Err(source.error(extra, format!("Unexpected `{extra}` after key file")));
+    }
+
+    Ok(SigningKey {
+        fingerprint: fingerprint.to_ascii_uppercase(),
+        file,
+    })
+}
+
+/// Parse the part of a hook line after `hook:`, e.g. `post-install:docker systemctl restart docker`.
+fn parse_hook(spec: &str, source: &SourceLine) -> Result<Hook, Diagnostic> {
+    let (event_str, command) = spec
+        .split_once(char::is_whitespace)
+        .map(|(e, c)| (e, c.trim()))
+        .unwrap_or((spec, ""));
+
+    if command.is_empty() {
+        return Err(source
+            .error(event_str, format!("Hook `{event_str}` has no command"))
+            .with_hint("Expected hook:<event> <command>"));
+    }
+
+    let event = match event_str.split_once(':') {
+        None if event_str == "pre-sync" => HookEvent::PreSync,
+        None if event_str == "post-sync" => HookEvent::PostSync,
+        Some(("post-install", pkg)) if !pkg.is_empty() => HookEvent::PostInstall(pkg.to_string()),
+        Some(("post-remove", pkg)) if !pkg.is_empty() => HookEvent::PostRemove(pkg.to_string()),
+        _ => {
+            return Err(source
+                .error(event_str, format!("Unknown hook event `{event_str}`"))
+                .with_hint("Expected pre-sync, post-sync, post-install:<package> or post-remove:<package>"));
+        }
+    };
+
+    Ok(Hook {
+        event,
+        command: command.to_string(),
+    })
 }
 
 #[cfg(test)]
@@ -190,11 +523,30 @@ mod tests {
         let result = parse_config_str(input);
         assert!(result.is_err());
         match result.unwrap_err() {
-            DpkgError::ConfigParse { line, .. } => assert_eq!(line, 1),
+            DpkgError::ConfigParse(errors) => assert_eq!(errors[0].span.line, 1),
             _ => panic!("Expected ConfigParse error"),
         }
     }
 
+    #[test]
+    fn test_parse_collects_all_errors() {
+        let input = "base\n## *\nlinux [pin]\naur:\n## @my_desktop\nmpv yt-dlp\ngit\n";
+        let DpkgError::ConfigParse(errors) = parse_config_str(input).unwrap_err() else {
+            panic!("Expected ConfigParse error");
+        };
+        let spans: Vec<_> = errors.iter().map(|e| (e.span.line, e.span.column, e.span.length)).collect();
+        assert_eq!(spans, vec![(1, 1, 4), (3, 8, 3), (4, 5, 1), (5, 5, 10), (6, 5, 6)]);
+    }
+
+    #[test]
+    fn test_parse_error_rendering() {
+        let err = parse_config_str("## *\n\tlinux [pin] // pinned\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Configuration error at line 2, column 9: Unknown package attribute `pin`\n   |\n 2 |  linux [pin] // pinned\n   |         ^^^\n  Hint: Known attributes: hold"
+        );
+    }
+
     #[test]
     fn test_parse_invalid_header_no_marker() {
         let input = "## something\nbase\n";
@@ -222,7 +574,7 @@ mod tests {
         let result = parse_config_str(input);
         assert!(result.is_err());
         match result.unwrap_err() {
-            DpkgError::ConfigParse { line, .. } => assert_eq!(line, 1),
+            DpkgError::ConfigParse(errors) => assert_eq!(errors[0].span.line, 1),
             _ => panic!("Expected ConfigParse error"),
         }
     }
@@ -256,6 +608,19 @@ mod tests {
         assert_eq!(config.sections[0].packages[0].name, "nvidia");
     }
 
+    #[test]
+    fn test_split_comment_keeps_urls_only_in_values() {
+        assert_eq!(split_comment("nvidia//driver"), ("nvidia", Some("driver")));
+        assert_eq!(split_comment("## *// common"), ("## *", Some(" common")));
+        let url = "file:https://example.com/x.pkg.tar.zst";
+        assert_eq!(split_comment(url), (url, None));
+        assert_eq!(
+            split_comment("repo:company file:///srv/repo//in-house"),
+            ("repo:company file:///srv/repo", Some("in-house"))
+        );
+        assert_eq!(split_comment("aur:foo https://bar"), ("aur:foo https:", Some("bar")));
+    }
+
     #[test]
     fn test_parse_hostname_with_hyphen() {
         let input = "## @my-desktop\nnvidia\n";
@@ -272,4 +637,210 @@ mod tests {
         let result = parse_config_str(input);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_parse_hooks() {
+        let input = "## *\nhook:pre-sync ./snapshot.sh pre\nhook:post-install:docker systemctl enable --now docker\nhook:post-remove:tlp rm -f /etc/tlp.d/local.conf\nhook:post-sync mkinitcpio -P\n";
+        let config = parse_config_str(input).unwrap();
+        let hooks = &config.sections[0].hooks;
+        assert_eq!(hooks.len(), 4);
+        assert_eq!(hooks[0].event, HookEvent::PreSync);
+        assert_eq!(hooks[0].command, "./snapshot.sh pre");
+        assert_eq!(hooks[1].event, HookEvent::PostInstall("docker".to_string()));
+        assert_eq!(hooks[2].event, HookEvent::PostRemove("tlp".to_string()));
+        assert_eq!(hooks[3].event, HookEvent::PostSync);
+        assert!(config.sections[0].packages.is_empty());
+    }
+
+    #[test]
+    fn test_parse_hook_without_command() {
+        let result = parse_config_str("## *\nhook:pre-sync\n");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_hook_unknown_event() {
+        assert!(parse_config_str("## *\nhook:pre-install ./x.sh\n").is_err());
+        assert!(parse_config_str("## *\nhook:post-install: ./x.sh\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_services() {
+        let input = "## *\nservice:docker.service\nservice:!bluetooth.service\nuser-service:pipewire.service\n";
+        let config = parse_config_str(input).unwrap();
+        let services = &config.sections[0].services;
+        assert_eq!(services.len(), 3);
+        assert_eq!(services[0], Service { unit: "docker.service".to_string(), enabled: true, user: false });
+        assert_eq!(services[1], Service { unit: "bluetooth.service".to_string(), enabled: false, user: false });
+        assert_eq!(services[2], Service { unit: "pipewire.service".to_string(), enabled: true, user: true });
+        assert!(config.sections[0].packages.is_empty());
+    }
+
+    #[test]
+    fn test_parse_escalation() {
+        let input = "## *\nescalate:doas\nescalate:sudo\n## @server\nescalate: sudo -A // askpass\n";
+        let config = parse_config_str(input).unwrap();
+        assert_eq!(config.sections[0].escalation, Some(Escalation::Doas));
+        assert_eq!(
+            config.sections[1].escalation,
+            Some(Escalation::Custom(vec!["sudo".to_string(), "-A".to_string()]))
+        );
+        assert!(config.sections[0].packages.is_empty());
+        assert!(parse_config_str("## *\nescalate:\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_aur_user() {
+        let config = parse_config_str("## *\naur-user: builder // builds yay packages\n").unwrap();
+        assert_eq!(config.sections[0].aur_user.as_deref(), Some("builder"));
+        assert!(parse_config_str("## *\naur-user:\n").is_err());
+        assert!(parse_config_str("## *\naur-user:bob ALL=(ALL) ALL\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_repos() {
+        let input = "## *\nrepo:company file:///srv/repo/$arch Optional TrustAll // in-house\nrepo:multilib https://geo.mirror.pkgbuild.com/$repo/os/$arch\n";
+        let config = parse_config_str(input).unwrap();
+        let repos = &config.sections[0].repos;
+        assert_eq!(
+            repos[0],
+            PacmanRepo {
+                name: "company".to_string(),
+                server: "file:///srv/repo/$arch".to_string(),
+                sig_level: Some("Optional TrustAll".to_string()),
+            }
+        );
+        assert_eq!(repos[1].name, "multilib");
+        assert_eq!(repos[1].sig_level, None);
+        assert!(config.sections[0].packages.is_empty());
+
+        assert!(parse_config_str("## *\nrepo:company\n").is_err());
+        assert!(parse_config_str("## *\nrepo:company /srv/repo\n").is_err());
+        assert!(parse_config_str("## *\nrepo:[core] file:///srv\n").is_err());
+        assert!(parse_config_str("## *\nrepo:local file:///srv\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_hold_attribute() {
+        let input = "## *\nlinux-lts [hold]\naur:yay-bin [ hold ] // pinned\ngit\nfile:http://[::1]/x.pkg.tar.zst\n";
+        let config = parse_config_str(input).unwrap();
+        let packages = &config.sections[0].packages;
+        assert_eq!(packages[0].name, "linux-lts");
+        assert!(packages[0].hold);
+        assert_eq!(packages[1].name, "yay-bin");
+        assert!(packages[1].hold);
+        assert!(!packages[2].hold);
+        assert_eq!(packages[3].name, "http://[::1]/x.pkg.tar.zst");
+
+        assert!(parse_config_str("## *\nlinux [hold\n").is_err());
+        assert!(parse_config_str("## *\nlinux [pin]\n").is_err());
+        assert!(parse_config_str("## *\nflatpak:org.gimp.GIMP [hold]\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_optdeps() {
+        let config = parse_config_str("## *\nmpv +yt-dlp +vapoursynth [hold]\ngit\n").unwrap();
+        let packages = &config.sections[0].packages;
+        assert_eq!(packages[0].name, "mpv");
+        assert_eq!(packages[0].optdeps, vec!["yt-dlp", "vapoursynth"]);
+        assert!(packages[0].hold);
+        assert!(packages[1].optdeps.is_empty());
+
+        assert!(parse_config_str("## *\nmpv yt-dlp\n").is_err());
+        assert!(parse_config_str("## *\nmpv +\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_group_exclusions() {
+        let config = parse_config_str("## *\ngnome -gnome-boxes -epiphany\n").unwrap();
+        let packages = &config.sections[0].packages;
+        assert_eq!(packages[0].name, "gnome");
+        assert_eq!(packages[0].excludes, vec!["gnome-boxes", "epiphany"]);
+
+        assert!(parse_config_str("## *\naur:foo -bar\n").is_err());
+        assert!(parse_config_str("## *\ngnome -\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        let input = "## *\nkey:0123456789abcdef0123456789ABCDEF01234567\nkey:89ABCDEF0123456789ABCDEF0123456789ABCDEF ./keys/company.asc\n";
+        let config = parse_config_str(input).unwrap();
+        let keys = &config.sections[0].keys;
+        assert_eq!(keys[0].fingerprint, "0123456789ABCDEF0123456789ABCDEF01234567");
+        assert_eq!(keys[0].file, None);
+        assert_eq!(keys[1].file.as_deref(), Some("./keys/company.asc"));
+
+        assert!(parse_config_str("## *\nkey:\n").is_err());
+        assert!(parse_config_str("## *\nkey:0123ABCD\n").is_err());
+        assert!(parse_config_str("## *\nkey:Z123456789ABCDEF0123456789ABCDEF01234567\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_empty_service() {
+        assert!(parse_config_str("## *\nservice:\n").is_err());
+        assert!(parse_config_str("## *\nuser-service:!\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_flatpaks() {
+        let input = "## *\nflatpak:org.mozilla.firefox\nflatpak-user:fedora/org.gnome.Maps\n";
+        let config = parse_config_str(input).unwrap();
+        let packages = &config.sections[0].packages;
+        assert_eq!(packages[0].name, "org.mozilla.firefox");
+        assert_eq!(
+            packages[0].source,
+            PackageSource::Flatpak { remote: None, user: false }
+        );
+        assert_eq!(packages[1].name, "org.gnome.Maps");
+        assert_eq!(
+            packages[1].source,
+            PackageSource::Flatpak { remote: Some("fedora".to_string()), user: true }
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_flatpak() {
+        assert!(parse_config_str("## *\nflatpak:\n").is_err());
+        assert!(parse_config_str("## *\nflatpak:/org.x.App\n").is_err());
+        assert!(parse_config_str("## *\nflatpak:flathub/\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_lang_packages() {
+        let input = "## *\ncargo:ripgrep\npipx: black\nnpm:@angular/cli\ncargo-audit\nnpm:\n";
+        assert!(parse_config_str(input).is_err());
+
+        let config = parse_config_str(&input.replace("npm:\n", "")).unwrap();
+        let packages = &config.sections[0].packages;
+        assert_eq!(packages[0].source, PackageSource::Lang(LangTool::Cargo));
+        assert_eq!(packages[1].name, "black");
+        assert_eq!(packages[1].source, PackageSource::Lang(LangTool::Pipx));
+        assert_eq!(packages[2].name, "@angular/cli");
+        assert_eq!(packages[2].source, PackageSource::Lang(LangTool::Npm));
+        assert_eq!(packages[3].source, PackageSource::Official);
+    }
+
+    #[test]
+    fn test_parse_local_packages() {
+        let config = parse_config_str("## *\nlocal:./pkgs/mytool\nlocal: pkgs/other \n").unwrap();
+        let packages = &config.sections[0].packages;
+        assert_eq!(packages[0].name, "./pkgs/mytool");
+        assert_eq!(packages[0].source, PackageSource::Local);
+        assert_eq!(packages[1].name, "pkgs/other");
+        assert!(parse_config_str("## *\nlocal:\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_file_packages() {
+        let input = "## *\nfile:/srv/pkgs/vendor-tool-1.2-1-x86_64.pkg.tar.zst\nfile:https://example.com/x-1-1-any.pkg.tar.zst\n";
+        let config = parse_config_str(input).unwrap();
+        let packages = &config.sections[0].packages;
+        assert_eq!(packages[0].name, "/srv/pkgs/vendor-tool-1.2-1-x86_64.pkg.tar.zst");
+        assert_eq!(packages[0].source, PackageSource::File);
+        assert_eq!(packages[1].name, "https://example.com/x-1-1-any.pkg.tar.zst");
+        assert!(parse_config_str("## *\nfile: \n").is_err());
+
+        let config = parse_config_str("## *\nfile:https://example.com/x.pkg.tar.zst // vendor\n").unwrap();
+        assert_eq!(config.sections[0].packages[0].name, "https://example.com/x.pkg.tar.zst");
+    }
 }
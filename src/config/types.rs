@@ -1,5 +1,7 @@
 use std::collections::HashSet;
 
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub sections: Vec<Section>,
@@ -9,6 +11,40 @@ pub struct Config {
 pub struct Section {
     pub header: Header,
     pub packages: Vec<Package>,
+    pub hooks: Vec<Hook>,
+    pub services: Vec<Service>,
+    pub repos: Vec<PacmanRepo>,
+    pub keys: Vec<SigningKey>,
+    /// `escalate:` line; the first one in the section counts
+    pub escalation: Option<Escalation>,
+    /// `aur-user:` line; the first one in the section counts
+    pub aur_user: Option<String>,
+    /// Line of the section header
+    pub line: usize,
+}
+
+impl Section {
+    pub fn new(header: Header) -> Self {
+        Section {
+            header,
+            packages: Vec::new(),
+            hooks: Vec::new(),
+            services: Vec::new(),
+            repos: Vec::new(),
+            keys: Vec::new(),
+            escalation: None,
+            aur_user: None,
+            line: 0,
+        }
+    }
+
+    /// Whether this section applies to `hostname`.
+    pub fn applies_to(&self, hostname: &str) -> bool {
+        match &self.header {
+            Header::All => true,
+            Header::Hostname(h) => h == hostname,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -21,12 +57,207 @@ pub enum Header {
 pub struct Package {
     pub name: String,
     pub source: PackageSource,
+    /// `[hold]`: kept at its installed version through IgnorePkg
+    pub hold: bool,
+    /// `+<name>` optional dependencies installed along with the package
+    pub optdeps: Vec<String>,
+    /// `-<name>` members left out when the package is a group
+    pub excludes: Vec<String>,
+    pub line: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum PackageSource {
     Official,
     Aur,
+    /// Flatpak app from `remote` (default: flathub), system-wide or per-user
+    Flatpak { remote: Option<String>, user: bool },
+    /// Tool installed through a language package manager (`cargo:`, `pipx:`, `npm:`)
+    Lang(LangTool),
+    /// PKGBUILD directory, relative to the config file; `name` holds the path
+    Local,
+    /// Prebuilt package archive (path relative to the config file, or URL); `name` holds it
+    File,
+}
+
+/// Language-level package managers whose globally installed tools are managed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LangTool {
+    Cargo,
+    Pipx,
+    Npm,
+}
+
+impl LangTool {
+    pub const ALL: [LangTool; 3] = [LangTool::Cargo, LangTool::Pipx, LangTool::Npm];
+
+    /// Config prefix and display name, e.g. `cargo`.
+    pub fn name(self) -> &'static str {
+        match self {
+            LangTool::Cargo => "cargo",
+            LangTool::Pipx => "pipx",
+            LangTool::Npm => "npm",
+        }
+    }
+}
+
+/// A tool installed with `cargo install`, `pipx install` or `npm install -g`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LangPackage {
+    pub tool: LangTool,
+    pub name: String,
+}
+
+impl std::fmt::Display for LangPackage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.tool.name(), self.name)
+    }
+}
+
+/// A Flatpak application, declared as `flatpak:[remote/]<app-id>` (system
+/// installation) or `flatpak-user:[remote/]<app-id>` (user installation).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FlatpakApp {
+    pub id: String,
+    pub remote: Option<String>,
+    pub user: bool,
+}
+
+impl std::fmt::Display for FlatpakApp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let prefix = if self.user { "flatpak-user" } else { "flatpak" };
+        match &self.remote {
+            Some(remote) => write!(f, "{prefix}:{remote}/{}", self.id),
+            None => write!(f, "{prefix}:{}", self.id),
+        }
+    }
+}
+
+/// How commands that need root are run, declared as `escalate:<method>` or set with
+/// `DPKG_ESCALATE`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Escalation {
+    /// Nothing when already root, otherwise the first of sudo, doas, run0 and pkexec
+    /// that is installed
+    Auto,
+    /// Run commands directly; dpkg already runs as root
+    None,
+    Sudo,
+    Doas,
+    Run0,
+    Pkexec,
+    /// Any other command and its arguments, e.g. `sudo -A`
+    Custom(Vec<String>),
+}
+
+impl Escalation {
+    /// Parse a method name or a custom command line. Empty text is no method.
+    pub fn parse(text: &str) -> Option<Self> {
+        let words: Vec<String> = text.split_whitespace().map(str::to_string).collect();
+        Some(match words.as_slice() {
+            [] => return None,
+            [word] if word == "auto" => Escalation::Auto,
+            [word] if word == "none" => Escalation::None,
+            [word] if word == "sudo" => Escalation::Sudo,
+            [word] if word == "doas" => Escalation::Doas,
+            [word] if word == "run0" => Escalation::Run0,
+            [word] if word == "pkexec" => Escalation::Pkexec,
+            _ => Escalation::Custom(words),
+        })
+    }
+}
+
+impl std::fmt::Display for Escalation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Escalation::Auto => write!(f, "auto"),
+            Escalation::None => write!(f, "none"),
+            Escalation::Sudo => write!(f, "sudo"),
+            Escalation::Doas => write!(f, "doas"),
+            Escalation::Run0 => write!(f, "run0"),
+            Escalation::Pkexec => write!(f, "pkexec"),
+            Escalation::Custom(words) => write!(f, "{}", words.join(" ")),
+        }
+    }
+}
+
+/// A systemd unit whose enablement is managed, declared as `service:<unit>`,
+/// `service:!<unit>` (keep disabled) or `user-service:<unit>` (user manager).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Service {
+    pub unit: String,
+    pub enabled: bool,
+    pub user: bool,
+}
+
+impl std::fmt::Display for Service {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let prefix = if self.user { "user-service" } else { "service" };
+        write!(f, "{prefix}:{}", self.unit)
+    }
+}
+
+/// A pacman repository, declared as `repo:<name> <server> [<SigLevel>]`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacmanRepo {
+    pub name: String,
+    /// `Server` URL; `file://` URLs point at local repositories
+    pub server: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sig_level: Option<String>,
+}
+
+impl std::fmt::Display for PacmanRepo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "repo:{}", self.name)
+    }
+}
+
+/// A signing key pacman must trust, declared as `key:<fingerprint> [<file>]`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigningKey {
+    /// Full 40-digit fingerprint, uppercase
+    pub fingerprint: String,
+    /// Key file to import instead of fetching from a keyserver, relative to the config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+impl std::fmt::Display for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "key:{}", self.fingerprint)
+    }
+}
+
+/// A command run around a sync, declared as `hook:<event> <command>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub event: HookEvent,
+    pub command: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookEvent {
+    /// Before any change is made; failure aborts the sync
+    PreSync,
+    /// After all steps succeeded
+    PostSync,
+    /// After the named package was installed
+    PostInstall(String),
+    /// After the named package was removed
+    PostRemove(String),
+}
+
+impl std::fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookEvent::PreSync => write!(f, "pre-sync"),
+            HookEvent::PostSync => write!(f, "post-sync"),
+            HookEvent::PostInstall(pkg) => write!(f, "post-install:{pkg}"),
+            HookEvent::PostRemove(pkg) => write!(f, "post-remove:{pkg}"),
+        }
+    }
 }
 
 impl std::fmt::Display for Header {
@@ -46,17 +277,19 @@ pub fn collect_packages(config: &Config, hostname: &str) -> (Vec<String>, Vec<St
     let mut seen = HashSet::new();
 
     for section in &config.sections {
-        let should_include = match &section.header {
-            Header::All => true,
-            Header::Hostname(h) => h == hostname,
-        };
-
-        if should_include {
+        if section.applies_to(hostname) {
             for package in &section.packages {
+                if !matches!(package.source, PackageSource::Official | PackageSource::Aur) {
+                    continue;
+                }
                 if seen.insert(package.name.clone()) {
                     match package.source {
                         PackageSource::Official => official.push(package.name.clone()),
                         PackageSource::Aur => aur.push(package.name.clone()),
+                        PackageSource::Flatpak { .. }
+                        | PackageSource::Lang(_)
+                        | PackageSource::Local
+                        | PackageSource::File => {}
                     }
                 }
             }
@@ -66,6 +299,230 @@ pub fn collect_packages(config: &Config, hostname: &str) -> (Vec<String>, Vec<St
     (official, aur)
 }
 
+/// An optional dependency declared as `<parent> +<name>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OptDep {
+    pub name: String,
+    pub parent: String,
+    /// Config line of the parent declaration
+    pub line: usize,
+}
+
+impl std::fmt::Display for OptDep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (optional for {})", self.name, self.parent)
+    }
+}
+
+/// Collect optional dependencies for `hostname`. The first parent to declare a name
+/// wins, and names that are also declared as packages are left to that declaration.
+pub fn collect_optdeps(config: &Config, hostname: &str) -> Vec<OptDep> {
+    let sections: Vec<&Section> = config.sections.iter().filter(|s| s.applies_to(hostname)).collect();
+    let declared: HashSet<&str> = sections
+        .iter()
+        .flat_map(|s| s.packages.iter())
+        .filter(|p| matches!(p.source, PackageSource::Official | PackageSource::Aur))
+        .map(|p| p.name.as_str())
+        .collect();
+    let mut seen = HashSet::new();
+    sections
+        .iter()
+        .flat_map(|s| s.packages.iter())
+        .flat_map(|p| {
+            p.optdeps.iter().map(|name| OptDep {
+                name: name.clone(),
+                parent: p.name.clone(),
+                line: p.line,
+            })
+        })
+        .filter(|dep| !declared.contains(dep.name.as_str()) && seen.insert(dep.name.clone()))
+        .collect()
+}
+
+/// Members excluded from a group on its line, e.g. `gnome -gnome-boxes -epiphany`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupExclusion {
+    pub group: String,
+    pub members: Vec<String>,
+    pub line: usize,
+}
+
+/// Collect group exclusions for `hostname` from the declaration of each name that
+/// wins deduplication, i.e. the first one.
+pub fn collect_exclusions(config: &Config, hostname: &str) -> Vec<GroupExclusion> {
+    let mut seen = HashSet::new();
+    config
+        .sections
+        .iter()
+        .filter(|s| s.applies_to(hostname))
+        .flat_map(|s| s.packages.iter())
+        .filter(|p| p.source == PackageSource::Official && seen.insert(p.name.clone()))
+        .filter(|p| !p.excludes.is_empty())
+        .map(|p| GroupExclusion {
+            group: p.name.clone(),
+            members: p.excludes.clone(),
+            line: p.line,
+        })
+        .collect()
+}
+
+/// Collect `[hold]` packages for `hostname`. A package is held if any applicable
+/// declaration of it holds it.
+pub fn collect_held(config: &Config, hostname: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    config
+        .sections
+        .iter()
+        .filter(|s| s.applies_to(hostname))
+        .flat_map(|s| s.packages.iter())
+        .filter(|p| p.hold && seen.insert(p.name.clone()))
+        .map(|p| p.name.clone())
+        .collect()
+}
+
+/// Collect Flatpak apps for `hostname`, first declaration of an app (per installation) wins.
+pub fn collect_flatpaks(config: &Config, hostname: &str) -> Vec<FlatpakApp> {
+    let mut apps = Vec::new();
+    let mut seen = HashSet::new();
+
+    for section in config.sections.iter().filter(|s| s.applies_to(hostname)) {
+        for package in &section.packages {
+            if let PackageSource::Flatpak { remote, user } = &package.source
+                && seen.insert((package.name.clone(), *user))
+            {
+                apps.push(FlatpakApp {
+                    id: package.name.clone(),
+                    remote: remote.clone(),
+                    user: *user,
+                });
+            }
+        }
+    }
+
+    apps
+}
+
+/// Collect cargo/pipx/npm tools for `hostname`, first declaration per tool wins.
+pub fn collect_lang_packages(config: &Config, hostname: &str) -> Vec<LangPackage> {
+    let mut packages = Vec::new();
+    let mut seen = HashSet::new();
+
+    for section in config.sections.iter().filter(|s| s.applies_to(hostname)) {
+        for package in &section.packages {
+            if let PackageSource::Lang(tool) = package.source
+                && seen.insert((tool, package.name.clone()))
+            {
+                packages.push(LangPackage {
+                    tool,
+                    name: package.name.clone(),
+                });
+            }
+        }
+    }
+
+    packages
+}
+
+/// Collect `local:` PKGBUILD directories for `hostname`, as written in the config.
+pub fn collect_local_packages(config: &Config, hostname: &str) -> Vec<String> {
+    collect_by_source(config, hostname, &PackageSource::Local)
+}
+
+/// Collect `file:` package archives (paths or URLs) for `hostname`, as written in the config.
+pub fn collect_file_packages(config: &Config, hostname: &str) -> Vec<String> {
+    collect_by_source(config, hostname, &PackageSource::File)
+}
+
+fn collect_by_source(config: &Config, hostname: &str, source: &PackageSource) -> Vec<String> {
+    let mut seen = HashSet::new();
+    config
+        .sections
+        .iter()
+        .filter(|s| s.applies_to(hostname))
+        .flat_map(|s| s.packages.iter())
+        .filter(|p| p.source == *source && seen.insert(p.name.clone()))
+        .map(|p| p.name.clone())
+        .collect()
+}
+
+/// Collect hooks declared in sections that apply to `hostname`, in config order.
+pub fn collect_hooks(config: &Config, hostname: &str) -> Vec<Hook> {
+    config
+        .sections
+        .iter()
+        .filter(|s| s.applies_to(hostname))
+        .flat_map(|s| s.hooks.iter().cloned())
+        .collect()
+}
+
+/// Collect repository declarations for `hostname`; the first declaration of a name wins.
+pub fn collect_repos(config: &Config, hostname: &str) -> Vec<PacmanRepo> {
+    let mut seen = HashSet::new();
+    config
+        .sections
+        .iter()
+        .filter(|s| s.applies_to(hostname))
+        .flat_map(|s| s.repos.iter())
+        .filter(|repo| seen.insert(repo.name.clone()))
+        .cloned()
+        .collect()
+}
+
+/// Collect key declarations for `hostname`; the first declaration of a fingerprint wins.
+pub fn collect_keys(config: &Config, hostname: &str) -> Vec<SigningKey> {
+    let mut seen = HashSet::new();
+    config
+        .sections
+        .iter()
+        .filter(|s| s.applies_to(hostname))
+        .flat_map(|s| s.keys.iter())
+        .filter(|key| seen.insert(key.fingerprint.clone()))
+        .cloned()
+        .collect()
+}
+
+/// Escalation method for `hostname`. A hostname section's `escalate:` line beats one
+/// under `## *`; otherwise the first one wins.
+pub fn collect_escalation(config: &Config, hostname: &str) -> Option<Escalation> {
+    host_setting(config, hostname, |s| s.escalation.clone())
+}
+
+/// User AUR packages are built as when dpkg runs as root, chosen like the escalation method.
+pub fn collect_aur_user(config: &Config, hostname: &str) -> Option<String> {
+    host_setting(config, hostname, |s| s.aur_user.clone())
+}
+
+/// A per-host setting: hostname sections first, then `## *`, each in file order.
+fn host_setting<T>(config: &Config, hostname: &str, get: impl Fn(&Section) -> Option<T>) -> Option<T> {
+    let applicable = || config.sections.iter().filter(|s| s.applies_to(hostname));
+    applicable()
+        .filter(|s| s.header != Header::All)
+        .chain(applicable().filter(|s| s.header == Header::All))
+        .find_map(get)
+}
+
+/// Whether `name` is usable as a user name: letters, digits, `_`, `-` and `.`, not
+/// starting with `-`, so it cannot be mistaken for an option of runuser.
+pub fn valid_user_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Collect service declarations for `hostname`. The first declaration of a unit
+/// (per system/user manager) wins, like package deduplication.
+pub fn collect_services(config: &Config, hostname: &str) -> Vec<Service> {
+    let mut seen = HashSet::new();
+    config
+        .sections
+        .iter()
+        .filter(|s| s.applies_to(hostname))
+        .flat_map(|s| s.services.iter())
+        .filter(|svc| seen.insert((svc.unit.clone(), svc.user)))
+        .cloned()
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -74,6 +531,10 @@ mod tests {
         Package {
             name: name.to_string(),
             source,
+            hold: false,
+            optdeps: Vec::new(),
+            excludes: Vec::new(),
+            line: 0,
         }
     }
 
@@ -81,12 +542,12 @@ mod tests {
     fn test_collect_wildcard_only() {
         let config = Config {
             sections: vec![Section {
-                header: Header::All,
                 packages: vec![
                     make_pkg("base", PackageSource::Official),
                     make_pkg("git", PackageSource::Official),
                     make_pkg("yay", PackageSource::Aur),
                 ],
+                ..Section::new(Header::All)
             }],
         };
         let (official, aur) = collect_packages(&config, "myhost");
@@ -99,16 +560,16 @@ mod tests {
         let config = Config {
             sections: vec![
                 Section {
-                    header: Header::All,
                     packages: vec![make_pkg("base", PackageSource::Official)],
+                    ..Section::new(Header::All)
                 },
                 Section {
-                    header: Header::Hostname("desktop".to_string()),
                     packages: vec![make_pkg("nvidia", PackageSource::Official)],
+                    ..Section::new(Header::Hostname("desktop".to_string()))
                 },
                 Section {
-                    header: Header::Hostname("laptop".to_string()),
                     packages: vec![make_pkg("tlp", PackageSource::Official)],
+                    ..Section::new(Header::Hostname("laptop".to_string()))
                 },
             ],
         };
@@ -122,12 +583,12 @@ mod tests {
         let config = Config {
             sections: vec![
                 Section {
-                    header: Header::All,
                     packages: vec![make_pkg("firefox", PackageSource::Official)],
+                    ..Section::new(Header::All)
                 },
                 Section {
-                    header: Header::Hostname("myhost".to_string()),
                     packages: vec![make_pkg("firefox", PackageSource::Official)],
+                    ..Section::new(Header::Hostname("myhost".to_string()))
                 },
             ],
         };
@@ -140,16 +601,16 @@ mod tests {
         let config = Config {
             sections: vec![
                 Section {
-                    header: Header::Hostname("desktop".to_string()),
                     packages: vec![make_pkg("nvidia", PackageSource::Official)],
+                    ..Section::new(Header::Hostname("desktop".to_string()))
                 },
                 Section {
-                    header: Header::All,
                     packages: vec![make_pkg("firefox", PackageSource::Official)],
+                    ..Section::new(Header::All)
                 },
                 Section {
-                    header: Header::Hostname("desktop".to_string()),
                     packages: vec![make_pkg("steam", PackageSource::Official)],
+                    ..Section::new(Header::Hostname("desktop".to_string()))
                 },
             ],
         };
@@ -161,12 +622,130 @@ mod tests {
     fn test_collect_no_matching_hostname() {
         let config = Config {
             sections: vec![Section {
-                header: Header::Hostname("other".to_string()),
                 packages: vec![make_pkg("nvidia", PackageSource::Official)],
+                ..Section::new(Header::Hostname("other".to_string()))
             }],
         };
         let (official, aur) = collect_packages(&config, "myhost");
         assert!(official.is_empty());
         assert!(aur.is_empty());
     }
+
+    #[test]
+    fn test_collect_services_first_wins() {
+        let svc = |unit: &str, enabled: bool, user: bool| Service {
+            unit: unit.to_string(),
+            enabled,
+            user,
+        };
+        let config = Config {
+            sections: vec![
+                Section {
+                    services: vec![svc("docker.service", true, false), svc("pipewire.service", true, true)],
+                    ..Section::new(Header::All)
+                },
+                Section {
+                    services: vec![svc("docker.service", false, false), svc("tlp.service", true, false)],
+                    ..Section::new(Header::Hostname("laptop".to_string()))
+                },
+            ],
+        };
+        let services = collect_services(&config, "laptop");
+        assert_eq!(
+            services,
+            vec![
+                svc("docker.service", true, false),
+                svc("pipewire.service", true, true),
+                svc("tlp.service", true, false),
+            ]
+        );
+        assert_eq!(collect_services(&config, "desktop").len(), 2);
+    }
+
+    #[test]
+    fn test_collect_flatpaks_separate_from_packages() {
+        let config = Config {
+            sections: vec![Section {
+                packages: vec![
+                    make_pkg("base", PackageSource::Official),
+                    make_pkg(
+                        "org.mozilla.firefox",
+                        PackageSource::Flatpak { remote: None, user: false },
+                    ),
+                    make_pkg(
+                        "org.mozilla.firefox",
+                        PackageSource::Flatpak { remote: None, user: false },
+                    ),
+                    make_pkg(
+                        "com.spotify.Client",
+                        PackageSource::Flatpak { remote: Some("flathub".to_string()), user: true },
+                    ),
+                ],
+                ..Section::new(Header::All)
+            }],
+        };
+        let (official, aur) = collect_packages(&config, "myhost");
+        assert_eq!(official, vec!["base"]);
+        assert!(aur.is_empty());
+
+        let flatpaks = collect_flatpaks(&config, "myhost");
+        assert_eq!(flatpaks.len(), 2);
+        assert_eq!(flatpaks[0].to_string(), "flatpak:org.mozilla.firefox");
+        assert_eq!(flatpaks[1].to_string(), "flatpak-user:flathub/com.spotify.Client");
+    }
+
+    #[test]
+    fn test_collect_lang_packages_per_tool() {
+        let config = Config {
+            sections: vec![Section {
+                packages: vec![
+                    make_pkg("ripgrep", PackageSource::Official),
+                    make_pkg("ripgrep", PackageSource::Lang(LangTool::Cargo)),
+                    make_pkg("black", PackageSource::Lang(LangTool::Pipx)),
+                    make_pkg("ripgrep", PackageSource::Lang(LangTool::Cargo)),
+                    make_pkg("prettier", PackageSource::Lang(LangTool::Npm)),
+                ],
+                ..Section::new(Header::All)
+            }],
+        };
+        let (official, _) = collect_packages(&config, "myhost");
+        assert_eq!(official, vec!["ripgrep"]);
+
+        let tools: Vec<String> = collect_lang_packages(&config, "myhost")
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(tools, vec!["cargo:ripgrep", "pipx:black", "npm:prettier"]);
+    }
+
+    #[test]
+    fn test_collect_local_packages() {
+        let config = Config {
+            sections: vec![
+                Section {
+                    packages: vec![
+                        make_pkg("./pkgs/mytool", PackageSource::Local),
+                        make_pkg("base", PackageSource::Official),
+                    ],
+                    ..Section::new(Header::All)
+                },
+                Section {
+                    packages: vec![
+                        make_pkg("./pkgs/mytool", PackageSource::Local),
+                        make_pkg("./pkgs/laptop-tweaks", PackageSource::Local),
+                    ],
+                    ..Section::new(Header::Hostname("laptop".to_string()))
+                },
+            ],
+        };
+        let (official, aur) = collect_packages(&config, "laptop");
+        assert_eq!(official, vec!["base"]);
+        assert!(aur.is_empty());
+        assert_eq!(
+            collect_local_packages(&config, "laptop"),
+            vec!["./pkgs/mytool", "./pkgs/laptop-tweaks"]
+        );
+        assert_eq!(collect_local_packages(&config, "desktop"), vec!["./pkgs/mytool"]);
+        assert!(collect_file_packages(&config, "laptop").is_empty());
+    }
 }
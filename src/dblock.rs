@@ -0,0 +1,197 @@
+use std::fs::{File, OpenOptions, Permissions, TryLockError};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::error::DpkgError;
+use crate::output;
+use crate::repos;
+use crate::system;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// pacman's database directory: `--dbpath`, else `DBPath` from the `[options]`
+/// section of pacman.conf, or `/var/lib/pacman/`, below the target root.
+pub fn db_path() -> PathBuf {
+    if let Some(dbpath) = &system::target().dbpath {
+        return dbpath.clone();
+    }
+    let configured = std::fs::read_to_string(repos::pacman_conf_path())
+        .ok()
+        .and_then(|conf| parse_db_path(&conf))
+        .unwrap_or_else(|| PathBuf::from("/var/lib/pacman/"));
+    system::in_root(&configured)
+}
+
+/// `DBPath` set in the `[options]` section of a pacman.conf.
+pub fn parse_db_path(conf: &str) -> Option<PathBuf> {
+    let mut in_options = false;
+    let mut db_path = None;
+    for line in conf.lines().map(str::trim) {
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_options = section == "options";
+        } else if in_options
+            && let Some((key, value)) = line.split_once('=')
+            && key.trim() == "DBPath"
+        {
+            db_path = Some(PathBuf::from(value.trim()));
+        }
+    }
+    db_path
+}
+
+/// Fail if another pacman holds the database lock, so nothing is changed halfway.
+/// With `wait`, poll until the lock is gone or the time runs out.
+pub fn wait_for_pacman(wait: Option<Duration>, quiet: bool) -> Result<(), DpkgError> {
+    let lock = db_path().join("db.lck");
+    wait_until(
+        wait,
+        quiet,
+        &format!("Waiting for pacman to release {}...", lock.display()),
+        || Ok(!lock.exists()),
+        |waited| DpkgError::DatabaseLocked(held_message(&format!("{} exists", lock.display()), waited)),
+    )
+}
+
+/// dpkg's own lock, held while a sync or rollback runs so two never overlap.
+/// Released when dropped.
+pub struct SyncLock {
+    _file: File,
+}
+
+/// dpkg's lock file, overridable with `DPKG_LOCK_FILE`. It is system-wide rather than
+/// in the state directory, so a user's sync and root's sync lock the same file.
+pub fn lock_path() -> PathBuf {
+    std::env::var("DPKG_LOCK_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/run/lock/dpkg-sync.lock"))
+}
+
+/// Take dpkg's lock (`lock_path`), waiting like `wait_for_pacman`.
+pub fn acquire(wait: Option<Duration>, quiet: bool) -> Result<SyncLock, DpkgError> {
+    acquire_at(&lock_path(), wait, quiet)
+}
+
+fn acquire_at(path: &Path, wait: Option<Duration>, quiet: bool) -> Result<SyncLock, DpkgError> {
+    let lock_error =
+        |e: std::io::Error| DpkgError::State(format!("Cannot lock {}: {e}", path.display()));
+    // Whoever creates the file lets every user write it; a file another user created
+    // without that can still be locked, just not labelled with our PID
+    let opened = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o666)
+        .open(path);
+    let (mut file, writable) = match opened {
+        Ok(file) => {
+            let _ = file.set_permissions(Permissions::from_mode(0o666));
+            (file, true)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            (File::open(path).map_err(lock_error)?, false)
+        }
+        Err(e) => return Err(lock_error(e)),
+    };
+
+    wait_until(
+        wait,
+        quiet,
+        "Waiting for another dpkg sync to finish...",
+        || match file.try_lock() {
+            Ok(()) => Ok(true),
+            Err(TryLockError::WouldBlock) => Ok(false),
+            Err(TryLockError::Error(e)) => Err(lock_error(e)),
+        },
+        |waited| {
+            let holder = std::fs::read_to_string(path).unwrap_or_default();
+            let holder = match holder.trim() {
+                "" => format!("{} is locked", path.display()),
+                pid => format!("process {pid} holds {}", path.display()),
+            };
+            DpkgError::SyncRunning(held_message(&holder, waited))
+        },
+    )?;
+
+    // Record who holds the lock for the error shown to the next run
+    if writable {
+        file.set_len(0)
+            .and_then(|_| writeln!(file, "{}", std::process::id()))
+            .map_err(lock_error)?;
+    }
+    Ok(SyncLock { _file: file })
+}
+
+/// Call `free` until it reports the lock free. Without `wait` it is asked once;
+/// `held` builds the error from the time waited (zero when not waiting).
+fn wait_until(
+    wait: Option<Duration>,
+    quiet: bool,
+    message: &str,
+    mut free: impl FnMut() -> Result<bool, DpkgError>,
+    held: impl FnOnce(Duration) -> DpkgError,
+) -> Result<(), DpkgError> {
+    let start = Instant::now();
+    let mut announced = false;
+    while !free()? {
+        let waited = start.elapsed();
+        match wait {
+            Some(limit) if waited < limit => {
+                if !announced && !quiet {
+                    output::info(message);
+                    announced = true;
+                }
+                std::thread::sleep(POLL_INTERVAL.min(limit - waited));
+            }
+            Some(_) => return Err(held(waited)),
+            None => return Err(held(Duration::ZERO)),
+        }
+    }
+    Ok(())
+}
+
+fn held_message(holder: &str, waited: Duration) -> String {
+    if waited.is_zero() {
+        holder.to_string()
+    } else {
+        format!("{holder} after waiting {}s", waited.as_secs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_db_path() {
+        let conf = "\
+[options]
+#DBPath      = /ignored/
+DBPath      = /mnt/var/lib/pacman/
+CacheDir    = /var/cache/pacman/pkg/
+
+[core]
+DBPath = /not/options/
+Include = /etc/pacman.d/mirrorlist
+";
+        assert_eq!(parse_db_path(conf), Some(PathBuf::from("/mnt/var/lib/pacman/")));
+        assert_eq!(parse_db_path("[options]\nHoldPkg = pacman\n"), None);
+    }
+
+    #[test]
+    fn test_sync_lock_excludes_second_holder() {
+        let dir = std::env::temp_dir().join(format!("dpkg-lock-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sync.lock");
+
+        let held = acquire_at(&path, None, true).unwrap();
+        let err = acquire_at(&path, Some(Duration::from_millis(50)), true).err().unwrap();
+        assert!(matches!(err, DpkgError::SyncRunning(ref m) if m.contains(&std::process::id().to_string())));
+
+        drop(held);
+        assert!(acquire_at(&path, None, true).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,41 +1,477 @@
+use std::fmt;
 use std::path::PathBuf;
 
+use serde::Serialize;
 use thiserror::Error;
 
+/// Where a config problem is: 1-based line and column, and its length in characters.
+/// Column 0 means the whole line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+impl Span {
+    /// A span covering all of `line`.
+    pub fn line(line: usize) -> Self {
+        Span {
+            line,
+            column: 0,
+            length: 0,
+        }
+    }
+}
+
+/// One problem in the config file, rendered with the offending line underlined.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    pub hint: Option<String>,
+    /// The config line the span points into, if known
+    pub source_line: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            span,
+            message: message.into(),
+            hint: None,
+            source_line: None,
+        }
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn with_source(mut self, source_line: &str) -> Self {
+        self.source_line = Some(source_line.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Span { line, column, length } = self.span;
+        if column > 0 {
+            write!(f, "Configuration error at line {line}, column {column}: {}", self.message)?;
+        } else {
+            write!(f, "Configuration error at line {line}: {}", self.message)?;
+        }
+        if let Some(source) = &self.source_line {
+            let gutter = " ".repeat(line.to_string().len());
+            // Tabs would throw the caret off, so they are shown as single spaces
+            let source = source.trim_end().replace('\t', " ");
+            write!(f, "\n {gutter} |\n {line} | {source}")?;
+            if column > 0 {
+                write!(
+                    f,
+                    "\n {gutter} | {}{}",
+                    " ".repeat(column - 1),
+                    "^".repeat(length.max(1))
+                )?;
+            }
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\n  Hint: {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+/// All problems found in one pass over the config.
+fn render_diagnostics(diagnostics: &[Diagnostic]) -> String {
+    let rendered: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+    match rendered.len() {
+        1 => rendered.into_iter().next().unwrap_or_default(),
+        n => format!("{n} configuration errors\n\n{}", rendered.join("\n\n")),
+    }
+}
+
+/// Outcome of installing one package of a batch.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PackageOutcome {
+    pub name: String,
+    /// Why it failed; `None` if it was installed
+    pub error: Option<String>,
+}
+
+fn render_partial(outcomes: &[PackageOutcome]) -> String {
+    let failed: Vec<String> = outcomes
+        .iter()
+        .filter_map(|o| Some(format!("\n  {}: {}", o.name, o.error.as_ref()?)))
+        .collect();
+    format!(
+        "{} of {} packages failed to install:{}",
+        failed.len(),
+        outcomes.len(),
+        failed.concat()
+    )
+}
+
 #[derive(Debug, Error)]
 pub enum DpkgError {
     #[error("Configuration file not found\n  Path: {path}\n  Hint: Create the file or specify a different path with --config")]
     ConfigNotFound { path: PathBuf },
 
-    #[error("Configuration error at line {line}: {message}")]
-    ConfigParse { line: usize, message: String },
+    #[error("Cannot read configuration file\n  Path: {path}\n  Reason: {reason}")]
+    ConfigUnreadable { path: PathBuf, reason: String },
+
+    /// Every problem the parser found; never empty
+    #[error("{}", render_diagnostics(.0))]
+    ConfigParse(Vec<Diagnostic>),
+
+    #[error("Lint failed: {0}")]
+    Lint(String),
 
-    #[error("Permission denied: {0}\n  Hint: Run with sudo or check your permissions")]
+    #[error("Cannot determine the hostname: {0}\n  Hint: Host sections are matched against the system hostname")]
+    HostnameUnavailable(String),
+
+    #[error("Permission denied: {0}\n  Hint: Check your permissions and that privilege escalation (sudo, doas, ...) works")]
     PermissionDenied(String),
 
-    #[error("Package installation failed: {0}")]
-    InstallFailed(String),
+    #[error("Cannot run `{program}`: {reason}\n  Hint: Install it, or point its environment variable (e.g. PACMAN, FLATPAK) at the binary")]
+    BackendMissing { program: String, reason: String },
 
     #[error("AUR packages found but yay is not installed\n  Hint: Install yay: git clone https://aur.archlinux.org/yay.git && cd yay && makepkg -si")]
     YayNotFound,
 
+    #[error("Package query failed: {0}")]
+    QueryFailed(String),
+
+    #[error("The pacman database is locked: {0}\n  Hint: Wait for the other package manager to finish or pass --wait-lock; if none is running, remove the lock file")]
+    DatabaseLocked(String),
+
+    #[error("Transaction conflict: {0}\n  Hint: Resolve the conflicting packages or files, then sync again")]
+    TransactionConflict(String),
+
+    #[error("Package installation failed: {0}")]
+    InstallFailed(String),
+
+    #[error("{}", render_partial(.0))]
+    PartialInstall(Vec<PackageOutcome>),
+
+    #[error("Package removal failed: {0}")]
+    RemoveFailed(String),
+
+    #[error("Package build failed: {0}")]
+    BuildFailed(String),
+
+    #[error("Service change failed: {0}")]
+    ServiceFailed(String),
+
     #[error("Network error: {0}")]
-    #[allow(dead_code)]
     NetworkError(String),
 
     #[error("User cancelled operation")]
     UserCancelled,
+
+    #[error("State error: {0}")]
+    State(String),
+
+    #[error("Lockfile error: {0}")]
+    Lock(String),
+
+    #[error("Hook failed: {0}")]
+    HookFailed(String),
+
+    #[error("Key import failed: {0}\n  Hint: Check the fingerprint, the key file and keyserver access")]
+    KeyImport(String),
+
+    #[error("Another dpkg sync is running: {0}\n  Hint: Wait for it to finish or pass --wait-lock")]
+    SyncRunning(String),
+
+    #[error("Not supported for an alternate root: {0}")]
+    UnsupportedTarget(String),
+}
+
+/// What an error code means, for `dpkg explain-error`. Codes and exit codes are stable:
+/// new errors get new codes, and existing ones are never renumbered.
+pub struct ErrorInfo {
+    pub code: &'static str,
+    pub exit_code: i32,
+    pub title: &'static str,
+    pub explanation: &'static str,
+}
+
+pub const ERRORS: &[ErrorInfo] = &[
+    ErrorInfo {
+        code: "DPKG-E001",
+        exit_code: 1,
+        title: "Configuration file not found",
+        explanation: "No file exists at the config path. The path comes from --config, then DPKG_CONFIG, then ~/.config/dpkg/pkg.conf.",
+    },
+    ErrorInfo {
+        code: "DPKG-E002",
+        exit_code: 1,
+        title: "Configuration file unreadable",
+        explanation: "The config file exists but could not be read, e.g. because it is a directory or not valid UTF-8.",
+    },
+    ErrorInfo {
+        code: "DPKG-E003",
+        exit_code: 1,
+        title: "Invalid configuration",
+        explanation: "The config has syntax errors, or declarations that do not match the system (an optional dependency the parent does not list, an excluded member that is not in the group). Every problem is listed with its line and column; `dpkg validate` reports them all at once.",
+    },
+    ErrorInfo {
+        code: "DPKG-E004",
+        exit_code: 1,
+        title: "Lint errors",
+        explanation: "`dpkg lint` found at least one error-severity finding. Fix the declaration, or lower the rule with a `// dpkg:warn(rule)` or `// dpkg:allow(rule)` comment.",
+    },
+    ErrorInfo {
+        code: "DPKG-E005",
+        exit_code: 11,
+        title: "Hostname unavailable",
+        explanation: "The system hostname could not be read or is not valid UTF-8, so `## @<hostname>` sections cannot be matched.",
+    },
+    ErrorInfo {
+        code: "DPKG-E006",
+        exit_code: 2,
+        title: "Permission denied",
+        explanation: "A file could not be read or written, or a privileged command was refused. Check that the escalation method (sudo by default, see `escalate:`) works for your user.",
+    },
+    ErrorInfo {
+        code: "DPKG-E007",
+        exit_code: 12,
+        title: "Backend missing",
+        explanation: "A program dpkg drives (pacman, sudo, systemctl, flatpak, makepkg, cargo, ...) could not be started. Install it, or set its environment variable (PACMAN, FLATPAK, ...) to the binary.",
+    },
+    ErrorInfo {
+        code: "DPKG-E008",
+        exit_code: 4,
+        title: "yay not installed",
+        explanation: "The config declares AUR packages, which are installed with yay, but yay is not installed.",
+    },
+    ErrorInfo {
+        code: "DPKG-E009",
+        exit_code: 13,
+        title: "Query failed",
+        explanation: "A read-only query (pacman -Q, -Si, -Sg, flatpak list, systemctl is-enabled, ...) exited with an error. Nothing was changed.",
+    },
+    ErrorInfo {
+        code: "DPKG-E010",
+        exit_code: 14,
+        title: "Database locked",
+        explanation: "Another pacman process holds db.lck in pacman's DBPath (/var/lib/pacman by default). Sync checks for it before changing anything; `--wait-lock` waits for it to go away. Remove the lock file only if no package manager is running.",
+    },
+    ErrorInfo {
+        code: "DPKG-E011",
+        exit_code: 15,
+        title: "Transaction conflict",
+        explanation: "pacman refused the transaction because of conflicting packages, dependencies or files already on disk.",
+    },
+    ErrorInfo {
+        code: "DPKG-E012",
+        exit_code: 3,
+        title: "Install failed",
+        explanation: "Installing packages failed. The message holds the output of the failing command.",
+    },
+    ErrorInfo {
+        code: "DPKG-E013",
+        exit_code: 16,
+        title: "Partial install",
+        explanation: "Some packages of a batch were installed and others failed. The JSON error lists the outcome of every package.",
+    },
+    ErrorInfo {
+        code: "DPKG-E014",
+        exit_code: 3,
+        title: "Removal failed",
+        explanation: "Removing packages or tools failed. The message holds the output of the failing command.",
+    },
+    ErrorInfo {
+        code: "DPKG-E015",
+        exit_code: 3,
+        title: "Build failed",
+        explanation: "makepkg failed to build a `local:` package or the dpkg-host meta-package, or produced no package.",
+    },
+    ErrorInfo {
+        code: "DPKG-E016",
+        exit_code: 3,
+        title: "Service change failed",
+        explanation: "systemctl could not enable or disable a declared unit.",
+    },
+    ErrorInfo {
+        code: "DPKG-E017",
+        exit_code: 5,
+        title: "Network error",
+        explanation: "Downloading a `file:` package failed.",
+    },
+    ErrorInfo {
+        code: "DPKG-E018",
+        exit_code: 6,
+        title: "Cancelled",
+        explanation: "A confirmation prompt was declined.",
+    },
+    ErrorInfo {
+        code: "DPKG-E019",
+        exit_code: 7,
+        title: "State error",
+        explanation: "The state directory (history journal, generations, group record) could not be read or written, or holds corrupt data.",
+    },
+    ErrorInfo {
+        code: "DPKG-E020",
+        exit_code: 8,
+        title: "Lockfile error",
+        explanation: "pkg.lock could not be read or written, lacks an entry for a desired package, or a locked version is not available in any cache or mirror.",
+    },
+    ErrorInfo {
+        code: "DPKG-E021",
+        exit_code: 9,
+        title: "Hook failed",
+        explanation: "A pre-sync hook failed, which stops the sync, or a post hook failed after the other steps ran.",
+    },
+    ErrorInfo {
+        code: "DPKG-E022",
+        exit_code: 10,
+        title: "Key import failed",
+        explanation: "pacman-key could not list, import or locally sign a declared signing key.",
+    },
+    ErrorInfo {
+        code: "DPKG-E023",
+        exit_code: 17,
+        title: "Sync already running",
+        explanation: "Another dpkg sync or rollback holds dpkg's lock (/run/lock/dpkg-sync.lock, shared by all users). `--wait-lock` waits for it to finish.",
+    },
+    ErrorInfo {
+        code: "DPKG-E024",
+        exit_code: 18,
+        title: "Not supported for an alternate root",
+        explanation: "With `--root`, pacman packages are installed into the target. AUR packages and `local:` PKGBUILDs are built on the host and installed with `pacman -U --root`, which needs a build user (`--aur-user` or `aur-user:`) when dpkg runs as root. Optional dependencies missing from the target's repositories, Flatpaks, language packages and user services would land on the host and are refused.",
+    },
+];
+
+/// Look up an error code, case-insensitively and with or without the `DPKG-` prefix.
+pub fn explain(code: &str) -> Option<&'static ErrorInfo> {
+    let code = code.trim().to_ascii_uppercase();
+    let code = code.strip_prefix("DPKG-").unwrap_or(&code);
+    ERRORS.iter().find(|info| info.code.strip_prefix("DPKG-") == Some(code))
 }
 
 impl DpkgError {
+    /// Error for a program that could not be started.
+    pub fn spawn(program: &str, e: std::io::Error) -> Self {
+        DpkgError::BackendMissing {
+            program: program.to_string(),
+            reason: e.to_string(),
+        }
+    }
+
+    /// Stable code identifying this kind of error, e.g. `DPKG-E012`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DpkgError::ConfigNotFound { .. } => "DPKG-E001",
+            DpkgError::ConfigUnreadable { .. } => "DPKG-E002",
+            DpkgError::ConfigParse(_) => "DPKG-E003",
+            DpkgError::Lint(_) => "DPKG-E004",
+            DpkgError::HostnameUnavailable(_) => "DPKG-E005",
+            DpkgError::PermissionDenied(_) => "DPKG-E006",
+            DpkgError::BackendMissing { .. } => "DPKG-E007",
+            DpkgError::YayNotFound => "DPKG-E008",
+            DpkgError::QueryFailed(_) => "DPKG-E009",
+            DpkgError::DatabaseLocked(_) => "DPKG-E010",
+            DpkgError::TransactionConflict(_) => "DPKG-E011",
+            DpkgError::InstallFailed(_) => "DPKG-E012",
+            DpkgError::PartialInstall(_) => "DPKG-E013",
+            DpkgError::RemoveFailed(_) => "DPKG-E014",
+            DpkgError::BuildFailed(_) => "DPKG-E015",
+            DpkgError::ServiceFailed(_) => "DPKG-E016",
+            DpkgError::NetworkError(_) => "DPKG-E017",
+            DpkgError::UserCancelled => "DPKG-E018",
+            DpkgError::State(_) => "DPKG-E019",
+            DpkgError::Lock(_) => "DPKG-E020",
+            DpkgError::HookFailed(_) => "DPKG-E021",
+            DpkgError::KeyImport(_) => "DPKG-E022",
+            DpkgError::SyncRunning(_) => "DPKG-E023",
+            DpkgError::UnsupportedTarget(_) => "DPKG-E024",
+        }
+    }
+
     pub fn exit_code(&self) -> i32 {
+        explain(self.code()).map_or(1, |info| info.exit_code)
+    }
+
+    /// Machine-readable form: code, exit code and message, plus the config diagnostics
+    /// or per-package outcomes where the error has them.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut value = serde_json::json!({
+            "code": self.code(),
+            "exit_code": self.exit_code(),
+            "message": self.to_string(),
+        });
         match self {
-            DpkgError::ConfigNotFound { .. } | DpkgError::ConfigParse { .. } => 1,
-            DpkgError::PermissionDenied(_) => 2,
-            DpkgError::InstallFailed(_) => 3,
-            DpkgError::YayNotFound => 4,
-            DpkgError::NetworkError(_) => 5,
-            DpkgError::UserCancelled => 6,
+            DpkgError::ConfigParse(diagnostics) => {
+                value["diagnostics"] = serde_json::json!(diagnostics);
+            }
+            DpkgError::PartialInstall(outcomes) => {
+                value["packages"] = serde_json::json!(outcomes);
+            }
+            _ => {}
+        }
+        value
+    }
+}
+
+impl From<Diagnostic> for DpkgError {
+    fn from(diagnostic: Diagnostic) -> Self {
+        DpkgError::ConfigParse(vec![diagnostic])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_are_unique_and_explained() {
+        let errors = [
+            DpkgError::ConfigNotFound { path: PathBuf::new() },
+            DpkgError::ConfigUnreadable { path: PathBuf::new(), reason: String::new() },
+            DpkgError::ConfigParse(Vec::new()),
+            DpkgError::Lint(String::new()),
+            DpkgError::HostnameUnavailable(String::new()),
+            DpkgError::PermissionDenied(String::new()),
+            DpkgError::BackendMissing { program: String::new(), reason: String::new() },
+            DpkgError::YayNotFound,
+            DpkgError::QueryFailed(String::new()),
+            DpkgError::DatabaseLocked(String::new()),
+            DpkgError::TransactionConflict(String::new()),
+            DpkgError::InstallFailed(String::new()),
+            DpkgError::PartialInstall(Vec::new()),
+            DpkgError::RemoveFailed(String::new()),
+            DpkgError::BuildFailed(String::new()),
+            DpkgError::ServiceFailed(String::new()),
+            DpkgError::NetworkError(String::new()),
+            DpkgError::UserCancelled,
+            DpkgError::State(String::new()),
+            DpkgError::Lock(String::new()),
+            DpkgError::HookFailed(String::new()),
+            DpkgError::KeyImport(String::new()),
+            DpkgError::SyncRunning(String::new()),
+            DpkgError::UnsupportedTarget(String::new()),
+        ];
+        assert_eq!(errors.len(), ERRORS.len());
+        for (error, info) in errors.iter().zip(ERRORS) {
+            assert_eq!(error.code(), info.code);
+            assert_eq!(error.exit_code(), info.exit_code);
         }
+        assert_eq!(explain("e010").map(|i| i.code), Some("DPKG-E010"));
+        assert_eq!(explain("dpkg-e013").map(|i| i.exit_code), Some(16));
+        assert!(explain("DPKG-E999").is_none());
+    }
+
+    #[test]
+    fn test_partial_install_message() {
+        let err = DpkgError::PartialInstall(vec![
+            PackageOutcome { name: "black".to_string(), error: None },
+            PackageOutcome { name: "httpie".to_string(), error: Some("no matching distribution".to_string()) },
+        ]);
+        assert_eq!(err.to_string(), "1 of 2 packages failed to install:\n  httpie: no matching distribution");
+        assert_eq!(err.to_json()["packages"][1]["name"], "httpie");
     }
 }
@@ -0,0 +1,148 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::DpkgError;
+use crate::repos;
+use crate::system;
+
+const HEADER: &str = "# Managed by dpkg; changes are overwritten on the next sync";
+
+/// The `IgnorePkg` include dpkg owns, overridable with `DPKG_HOLD_CONF`.
+pub fn include_path() -> PathBuf {
+    std::env::var("DPKG_HOLD_CONF")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/pacman.d/dpkg-hold.conf"))
+}
+
+/// How the held packages in the managed file differ from the declared ones.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HoldChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// pacman.conf does not include the managed file from `[options]` yet
+    #[serde(default)]
+    pub include_missing: bool,
+}
+
+impl HoldChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && !self.include_missing
+    }
+}
+
+/// Contents of the managed include for `held`. It is included from `[options]`, so it
+/// holds only the directive.
+pub fn render(held: &[String]) -> String {
+    if held.is_empty() {
+        format!("{HEADER}\n")
+    } else {
+        format!("{HEADER}\nIgnorePkg = {}\n", held.join(" "))
+    }
+}
+
+/// Held packages from a file written by `render`.
+pub fn parse(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| line.trim().split_once('='))
+        .filter(|(key, _)| key.trim() == "IgnorePkg")
+        .flat_map(|(_, value)| value.split_whitespace().map(str::to_string))
+        .collect()
+}
+
+/// pacman.conf with `Include = <path>` added right after `[options]`, since `IgnorePkg`
+/// is only read there. `None` if there is no `[options]` section.
+pub fn insert_include(pacman_conf: &str, path: &Path) -> Option<String> {
+    let mut result = String::with_capacity(pacman_conf.len() + 80);
+    let mut inserted = false;
+    for line in pacman_conf.lines() {
+        result.push_str(line);
+        result.push('\n');
+        if !inserted && line.trim() == "[options]" {
+            result.push_str("# Packages held in pkg.conf\n");
+            result.push_str(&format!("Include = {}\n", path.display()));
+            inserted = true;
+        }
+    }
+    inserted.then_some(result)
+}
+
+/// Compare declared holds with the managed file and pacman.conf. Without holds and
+/// without a managed file there is nothing to manage.
+pub fn plan(held: &[String]) -> Result<HoldChanges, DpkgError> {
+    let path = include_path();
+    let current = match std::fs::read_to_string(system::in_root(&path)) {
+        Ok(content) => parse(&content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if held.is_empty() {
+                return Ok(HoldChanges::default());
+            }
+            Vec::new()
+        }
+        Err(e) => {
+            return Err(DpkgError::QueryFailed(format!(
+                "Cannot read {}: {e}",
+                path.display()
+            )));
+        }
+    };
+
+    let include_missing = !held.is_empty() && !repos::is_included(&read_pacman_conf()?, &path);
+
+    Ok(HoldChanges {
+        added: held.iter().filter(|p| !current.contains(p)).cloned().collect(),
+        removed: current.iter().filter(|p| !held.contains(p)).cloned().collect(),
+        include_missing,
+    })
+}
+
+/// Write the managed file and include it from pacman.conf's `[options]` if needed.
+pub fn apply(held: &[String], changes: &HoldChanges, verbose: bool) -> Result<(), DpkgError> {
+    let path = include_path();
+    if verbose {
+        eprintln!("Writing {}...", system::in_root(&path).display());
+    }
+    repos::root_write(&system::in_root(&path), &render(held), false)?;
+
+    if changes.include_missing {
+        let conf_path = repos::pacman_conf_path();
+        let updated = insert_include(&read_pacman_conf()?, &path).ok_or_else(|| {
+            DpkgError::InstallFailed(format!("No [options] section in {}", conf_path.display()))
+        })?;
+        if verbose {
+            eprintln!("Adding Include = {} to [options] in {}...", path.display(), conf_path.display());
+        }
+        repos::root_write(&conf_path, &updated, false)?;
+    }
+    Ok(())
+}
+
+fn read_pacman_conf() -> Result<String, DpkgError> {
+    let conf_path = repos::pacman_conf_path();
+    std::fs::read_to_string(&conf_path).map_err(|e| {
+        DpkgError::QueryFailed(format!("Cannot read {}: {e}", conf_path.display()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_parse_roundtrip() {
+        let held = vec!["linux-lts".to_string(), "nvidia-lts".to_string()];
+        let content = render(&held);
+        assert!(content.ends_with("\nIgnorePkg = linux-lts nvidia-lts\n"));
+        assert_eq!(parse(&content), held);
+        assert!(parse(&render(&[])).is_empty());
+    }
+
+    #[test]
+    fn test_insert_include_after_options() {
+        let conf = "# pacman.conf\n[options]\nHoldPkg = pacman glibc\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n";
+        let updated = insert_include(conf, Path::new("/etc/pacman.d/dpkg-hold.conf")).unwrap();
+        assert!(updated.contains("[options]\n# Packages held in pkg.conf\nInclude = /etc/pacman.d/dpkg-hold.conf\nHoldPkg"));
+        assert!(insert_include("[core]\n", Path::new("/x")).is_none());
+    }
+}
@@ -0,0 +1,227 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde::Serialize;
+
+use crate::commands::sync::SyncPlan;
+use crate::config::types::Config;
+use crate::config::{collect_hooks, Hook, HookEvent};
+use crate::error::DpkgError;
+
+/// What a hook gets to see about the running sync.
+pub struct HookContext<'a> {
+    pub config_path: &'a Path,
+    pub hostname: &'a str,
+    pub plan: &'a SyncPlan,
+}
+
+/// JSON document written to a hook's stdin.
+#[derive(Serialize)]
+struct HookInput<'a> {
+    event: String,
+    hostname: &'a str,
+    config: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    package: Option<&'a str>,
+    plan: &'a SyncPlan,
+}
+
+/// Directory of drop-in hook scripts next to the config: `<config dir>/hooks.d`.
+pub fn hooks_dir(config_path: &Path) -> PathBuf {
+    config_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("hooks.d")
+}
+
+/// All hooks for `hostname`: those declared in the config first, then scripts from
+/// `hooks.d/{pre-sync,post-sync}/*` and `hooks.d/{post-install,post-remove}/<package>`.
+pub fn discover(config: &Config, config_path: &Path, hostname: &str) -> Vec<Hook> {
+    let mut hooks = collect_hooks(config, hostname);
+    let dir = hooks_dir(config_path);
+
+    for (subdir, event) in [
+        ("pre-sync", HookEvent::PreSync),
+        ("post-sync", HookEvent::PostSync),
+    ] {
+        for script in list_scripts(&dir.join(subdir)) {
+            hooks.push(Hook {
+                event: event.clone(),
+                command: shell_quote(&script),
+            });
+        }
+    }
+
+    for subdir in ["post-install", "post-remove"] {
+        for script in list_scripts(&dir.join(subdir)) {
+            let Some(package) = script.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let event = if subdir == "post-install" {
+                HookEvent::PostInstall(package.to_string())
+            } else {
+                HookEvent::PostRemove(package.to_string())
+            };
+            hooks.push(Hook {
+                event,
+                command: shell_quote(&script),
+            });
+        }
+    }
+
+    hooks
+}
+
+/// Hooks that the given plan would trigger, in execution order.
+pub fn planned<'a>(hooks: &'a [Hook], plan: &SyncPlan) -> Vec<&'a Hook> {
+    let order = |hook: &Hook| match hook.event {
+        HookEvent::PreSync => 0,
+        HookEvent::PostRemove(_) => 1,
+        HookEvent::PostInstall(_) => 2,
+        HookEvent::PostSync => 3,
+    };
+    let mut triggered: Vec<&Hook> = hooks
+        .iter()
+        .filter(|hook| match &hook.event {
+            HookEvent::PreSync | HookEvent::PostSync => true,
+            HookEvent::PostInstall(pkg) => {
+                plan.to_install_official.contains(pkg)
+                    || plan.to_install_aur.contains(pkg)
+                    || plan.local_install.iter().any(|b| b.names.contains(pkg))
+                    || plan.file_install.iter().any(|f| &f.name == pkg)
+            }
+            HookEvent::PostRemove(pkg) => plan.to_remove.contains(pkg),
+        })
+        .collect();
+    triggered.sort_by_key(|hook| order(hook));
+    triggered
+}
+
+/// Run one hook through `sh -c` from the config directory. The plan is passed as
+/// JSON on stdin and summarized in `DPKG_*` environment variables.
+pub fn run(hook: &Hook, ctx: &HookContext, verbose: bool) -> Result<(), DpkgError> {
+    if verbose {
+        eprintln!("Running {} hook: {}", hook.event, hook.command);
+    }
+
+    let package = match &hook.event {
+        HookEvent::PostInstall(pkg) | HookEvent::PostRemove(pkg) => Some(pkg.as_str()),
+        HookEvent::PreSync | HookEvent::PostSync => None,
+    };
+    let input = HookInput {
+        event: hook.event.to_string(),
+        hostname: ctx.hostname,
+        config: ctx.config_path.display().to_string(),
+        package,
+        plan: ctx.plan,
+    };
+    let json = serde_json::to_string(&input)
+        .map_err(|e| DpkgError::HookFailed(format!("Cannot serialize hook input: {e}")))?;
+
+    let installs: Vec<&str> = ctx
+        .plan
+        .to_install_official
+        .iter()
+        .chain(&ctx.plan.to_install_aur)
+        .chain(ctx.plan.local_install.iter().flat_map(|b| &b.names))
+        .chain(ctx.plan.file_install.iter().map(|f| &f.name))
+        .map(|s| s.as_str())
+        .collect();
+
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(&hook.command)
+        .current_dir(ctx.config_path.parent().unwrap_or(Path::new(".")))
+        .env("DPKG_HOOK_EVENT", hook.event.to_string())
+        .env("DPKG_HOSTNAME", ctx.hostname)
+        .env("DPKG_CONFIG", ctx.config_path)
+        .env("DPKG_INSTALL", installs.join(" "))
+        .env("DPKG_REMOVE", ctx.plan.to_remove.join(" "))
+        .stdin(Stdio::piped());
+    if let Some(pkg) = package {
+        command.env("DPKG_PACKAGE", pkg);
+    }
+
+    let mut child = command.spawn().map_err(|e| {
+        DpkgError::HookFailed(format!("{} hook `{}`: {e}", hook.event, hook.command))
+    })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Hooks are free to ignore their input; a closed pipe is not an error
+        stdin.write_all(json.as_bytes()).ok();
+    }
+    let status = child.wait().map_err(|e| {
+        DpkgError::HookFailed(format!("{} hook `{}`: {e}", hook.event, hook.command))
+    })?;
+
+    if !status.success() {
+        return Err(DpkgError::HookFailed(format!(
+            "{} hook `{}` exited with {status}",
+            hook.event, hook.command
+        )));
+    }
+    Ok(())
+}
+
+fn list_scripts(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut scripts: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+fn shell_quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(event: HookEvent) -> Hook {
+        Hook {
+            event,
+            command: "true".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_planned_filters_package_hooks_and_orders() {
+        let hooks = vec![
+            hook(HookEvent::PostSync),
+            hook(HookEvent::PostInstall("docker".to_string())),
+            hook(HookEvent::PostInstall("steam".to_string())),
+            hook(HookEvent::PostRemove("tlp".to_string())),
+            hook(HookEvent::PreSync),
+        ];
+        let plan = SyncPlan {
+            to_install_official: vec!["docker".to_string()],
+            to_install_aur: vec![],
+            to_remove: vec!["tlp".to_string()],
+            ..Default::default()
+        };
+        let events: Vec<String> = planned(&hooks, &plan)
+            .iter()
+            .map(|h| h.event.to_string())
+            .collect();
+        assert_eq!(
+            events,
+            vec!["pre-sync", "post-remove:tlp", "post-install:docker", "post-sync"]
+        );
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(
+            shell_quote(Path::new("/etc/dpkg/hooks.d/it's.sh")),
+            r"'/etc/dpkg/hooks.d/it'\''s.sh'"
+        );
+    }
+}
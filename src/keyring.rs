@@ -0,0 +1,257 @@
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::SigningKey;
+use crate::error::DpkgError;
+use crate::repos;
+use crate::system::{self, escalate};
+
+fn pacman_key_bin() -> String {
+    std::env::var("PACMAN_KEY").unwrap_or_else(|_| "pacman-key".to_string())
+}
+
+/// Under `--root`, pacman-key works on the target's keyring.
+fn target_args() -> Vec<OsString> {
+    match system::target().root {
+        Some(_) => vec![
+            "--gpgdir".into(),
+            system::in_root(Path::new("/etc/pacman.d/gnupg")).into(),
+            "--config".into(),
+            repos::pacman_conf_path().into(),
+        ],
+        None => Vec::new(),
+    }
+}
+
+/// A key in pacman's keyring as listed by `pacman-key --list-keys`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyringEntry {
+    pub fingerprint: String,
+    /// Locally signed (or otherwise valid), so pacman accepts its signatures
+    pub trusted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyState {
+    /// Not in the keyring yet
+    Missing,
+    /// Imported but not locally signed
+    Untrusted,
+}
+
+/// A declared key that still needs importing or signing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingKey {
+    pub key: SigningKey,
+    /// Key file resolved against the config directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    pub state: KeyState,
+}
+
+impl std::fmt::Display for PendingKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.key.fmt(f)
+    }
+}
+
+/// Keys in pacman's keyring. Listing does not need root.
+pub fn list_keys() -> Result<Vec<KeyringEntry>, DpkgError> {
+    let output = Command::new(pacman_key_bin())
+        .args(target_args())
+        .arg("--list-keys")
+        .output()
+        .map_err(|e| DpkgError::spawn(&pacman_key_bin(), e))?;
+    if !output.status.success() {
+        return Err(DpkgError::KeyImport(format!(
+            "pacman-key --list-keys failed: {}\n  Hint: Initialize the keyring with: sudo pacman-key --init",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(parse_list_keys(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse gpg's key listing. The fingerprint follows the `pub` line, either bare or as
+/// `Key fingerprint = XXXX XXXX …` from older gpg; uid lines carry the validity.
+pub fn parse_list_keys(text: &str) -> Vec<KeyringEntry> {
+    let mut keys: Vec<KeyringEntry> = Vec::new();
+    let mut in_key = false;
+    for line in text.lines() {
+        if line.starts_with("pub ") {
+            in_key = true;
+            keys.push(KeyringEntry {
+                fingerprint: String::new(),
+                trusted: false,
+            });
+            continue;
+        }
+        if !in_key {
+            continue;
+        }
+        let Some(key) = keys.last_mut() else {
+            continue;
+        };
+
+        if let Some(uid) = line.strip_prefix("uid") {
+            let validity = uid
+                .trim()
+                .strip_prefix('[')
+                .and_then(|v| v.split_once(']'))
+                .map(|(v, _)| v.trim());
+            if matches!(validity, Some("full" | "ultimate")) {
+                key.trusted = true;
+            }
+        } else if key.fingerprint.is_empty() {
+            let candidate: String = line
+                .trim()
+                .trim_start_matches("Key fingerprint =")
+                .split_whitespace()
+                .collect();
+            if candidate.len() == 40 && candidate.chars().all(|c| c.is_ascii_hexdigit()) {
+                key.fingerprint = candidate.to_ascii_uppercase();
+            }
+        } else if line.trim().is_empty() {
+            in_key = false;
+        }
+    }
+    keys.retain(|k| !k.fingerprint.is_empty());
+    keys
+}
+
+/// Pure computation: declared keys that are missing from the keyring or not trusted.
+pub fn compute_pending(
+    config_path: &Path,
+    desired: &[SigningKey],
+    keyring: &[KeyringEntry],
+) -> Vec<PendingKey> {
+    desired
+        .iter()
+        .filter_map(|key| {
+            let state = match keyring.iter().find(|k| k.fingerprint == key.fingerprint) {
+                None => KeyState::Missing,
+                Some(entry) if !entry.trusted => KeyState::Untrusted,
+                Some(_) => return None,
+            };
+            Some(PendingKey {
+                key: key.clone(),
+                file: key
+                    .file
+                    .as_ref()
+                    .map(|f| config_path.parent().unwrap_or(Path::new(".")).join(f)),
+                state,
+            })
+        })
+        .collect()
+}
+
+/// Declared keys that still need work. The keyring is only listed when keys are declared.
+pub fn plan(config_path: &Path, desired: &[SigningKey]) -> Result<Vec<PendingKey>, DpkgError> {
+    if desired.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(compute_pending(config_path, desired, &list_keys()?))
+}
+
+/// Import missing keys (from their file, or the keyserver) and locally sign them.
+pub fn apply(pending: &[PendingKey], verbose: bool) -> Result<(), DpkgError> {
+    for key in pending {
+        if key.state == KeyState::Missing {
+            match &key.file {
+                Some(file) => {
+                    if verbose {
+                        eprintln!("Importing {key} from {}...", file.display());
+                    }
+                    run_pacman_key(&["--add".as_ref(), file.as_os_str()], key)?;
+                }
+                None => {
+                    if verbose {
+                        eprintln!("Receiving {key} from the keyserver...");
+                    }
+                    run_pacman_key(&["--recv-keys".as_ref(), key.key.fingerprint.as_ref()], key)?;
+                }
+            }
+        }
+        if verbose {
+            eprintln!("Locally signing {key}...");
+        }
+        run_pacman_key(&["--lsign-key".as_ref(), key.key.fingerprint.as_ref()], key)?;
+    }
+    Ok(())
+}
+
+fn run_pacman_key(args: &[&OsStr], key: &PendingKey) -> Result<(), DpkgError> {
+    let mut command = escalate::command(pacman_key_bin());
+    command.args(target_args());
+    command.args(args);
+    let output = command.output().map_err(|e| escalate::spawn_error(&command, e))?;
+    if !output.status.success() {
+        return Err(DpkgError::KeyImport(format!(
+            "pacman-key {} failed for {key}: {}",
+            args[0].to_string_lossy(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTING: &str = "\
+/etc/pacman.d/gnupg/pubring.gpg
+-------------------------------
+pub   rsa4096 2011-09-23 [SC]
+      647F28654894E3BD457199BE38DBBDC86092693E
+uid           [  full  ] Jan Alexander Steffens (heftig) <heftig@archlinux.org>
+sub   rsa4096 2011-09-23 [E]
+
+pub   ed25519 2023-01-01 [SC]
+      Key fingerprint = 0123 4567 89AB CDEF 0123  4567 89ab cdef 0123 4567
+uid           [ unknown] Company Packaging <pkg@example.com>
+
+";
+
+    #[test]
+    fn test_parse_list_keys() {
+        assert_eq!(
+            parse_list_keys(LISTING),
+            vec![
+                KeyringEntry {
+                    fingerprint: "647F28654894E3BD457199BE38DBBDC86092693E".to_string(),
+                    trusted: true,
+                },
+                KeyringEntry {
+                    fingerprint: "0123456789ABCDEF0123456789ABCDEF01234567".to_string(),
+                    trusted: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compute_pending() {
+        let key = |fingerprint: &str, file: Option<&str>| SigningKey {
+            fingerprint: fingerprint.to_string(),
+            file: file.map(|f| f.to_string()),
+        };
+        let desired = vec![
+            key("647F28654894E3BD457199BE38DBBDC86092693E", None),
+            key("0123456789ABCDEF0123456789ABCDEF01234567", None),
+            key("89ABCDEF0123456789ABCDEF0123456789ABCDEF", Some("keys/company.asc")),
+        ];
+        let pending = compute_pending(
+            Path::new("/etc/dpkg/pkg.conf"),
+            &desired,
+            &parse_list_keys(LISTING),
+        );
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].state, KeyState::Untrusted);
+        assert_eq!(pending[1].state, KeyState::Missing);
+        assert_eq!(pending[1].file, Some(PathBuf::from("/etc/dpkg/keys/company.asc")));
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use crate::config::parser::split_comment;
+use crate::config::{Config, Header, Package, PackageSource, Section};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+pub struct Rule {
+    pub id: &'static str,
+    /// Severity unless a `// dpkg:warn(...)` or `// dpkg:deny(...)` directive changes it
+    pub severity: Severity,
+    pub description: &'static str,
+}
+
+pub const RULES: &[Rule] = &[
+    Rule {
+        id: "duplicate-package",
+        severity: Severity::Warning,
+        description: "Package declared again in a section that already applies to the same hosts",
+    },
+    Rule {
+        id: "redundant-dependency",
+        severity: Severity::Note,
+        description: "Package is already a hard dependency of another declared package",
+    },
+    Rule {
+        id: "aur-official-clash",
+        severity: Severity::Error,
+        description: "AUR package that is in the official repositories or also declared as official",
+    },
+    Rule {
+        id: "unknown-host",
+        severity: Severity::Warning,
+        description: "Section for a host missing from the `// dpkg:hosts(...)` list",
+    },
+    Rule {
+        id: "invalid-directive",
+        severity: Severity::Warning,
+        description: "`// dpkg:` comment with an unknown directive or rule",
+    },
+];
+
+fn rule(id: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|r| r.id == id)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub line: usize,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(rule_id: &'static str, line: usize, message: String) -> Self {
+        let severity = rule(rule_id).map_or(Severity::Warning, |r| r.severity);
+        Finding {
+            rule: rule_id,
+            severity,
+            line,
+            message,
+        }
+    }
+}
+
+/// What a `// dpkg:allow|warn|deny(rule, ...)` directive does with a rule's findings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// Lint directives from the config's comments.
+#[derive(Debug, Default)]
+pub struct Directives {
+    /// Directives before the first section, applying to the whole file
+    global: HashMap<String, Level>,
+    /// Directives for one line: inline, or on their own line right above it
+    lines: HashMap<usize, HashMap<String, Level>>,
+    /// Hosts from `// dpkg:hosts(...)`, if any
+    hosts: Option<Vec<String>>,
+    /// Malformed directives, reported as `invalid-directive`
+    invalid: Vec<Finding>,
+}
+
+/// Collect `// dpkg:` directives. A directive on its own line applies to the whole
+/// file before the first section and to the next declaration inside one; an inline
+/// directive applies to its own line. `hosts` is always file-wide.
+pub fn parse_directives(input: &str) -> Directives {
+    let mut directives = Directives::default();
+    let mut in_section = false;
+    let mut pending: Vec<(String, Level)> = Vec::new();
+
+    for (line_num_0, raw_line) in input.lines().enumerate() {
+        let line_num = line_num_0 + 1;
+        let (code, comment) = split_comment(raw_line);
+        let code = code.trim();
+
+        if let Some(directive) = comment.and_then(|c| c.trim().strip_prefix("dpkg:")) {
+            for (rule_id, level) in parse_directive(directive, line_num, &mut directives) {
+                if !code.is_empty() {
+                    directives.lines.entry(line_num).or_default().insert(rule_id, level);
+                } else if in_section {
+                    pending.push((rule_id, level));
+                } else {
+                    directives.global.insert(rule_id, level);
+                }
+            }
+        }
+
+        if code.is_empty() {
+            continue;
+        }
+        if code.starts_with("##") {
+            in_section = true;
+        }
+        if !pending.is_empty() {
+            directives.lines.entry(line_num).or_default().extend(pending.drain(..));
+        }
+    }
+    directives
+}
+
+/// Parse one directive after `dpkg:`, returning the rule levels it sets. `hosts` and
+/// malformed directives are recorded on `directives` directly.
+fn parse_directive(
+    directive: &str,
+    line_num: usize,
+    directives: &mut Directives,
+) -> Vec<(String, Level)> {
+    let parsed = directive
+        .trim()
+        .strip_suffix(')')
+        .and_then(|d| d.split_once('('))
+        .map(|(name, args)| {
+            let args: Vec<String> = args
+                .split(',')
+                .map(|a| a.trim().to_string())
+                .filter(|a| !a.is_empty())
+                .collect();
+            (name.trim(), args)
+        });
+    let Some((name, args)) = parsed else {
+        directives.invalid.push(Finding::new(
+            "invalid-directive",
+            line_num,
+            format!("Malformed directive `dpkg:{}`; expected `dpkg:<directive>(...)`", directive.trim()),
+        ));
+        return Vec::new();
+    };
+
+    let level = match name {
+        "allow" => Level::Allow,
+        "warn" => Level::Warn,
+        "deny" => Level::Deny,
+        "hosts" => {
+            directives.hosts.get_or_insert_with(Vec::new).extend(args);
+            return Vec::new();
+        }
+        _ => {
+            directives.invalid.push(Finding::new(
+                "invalid-directive",
+                line_num,
+                format!("Unknown directive `dpkg:{name}`; expected allow, warn, deny or hosts"),
+            ));
+            return Vec::new();
+        }
+    };
+    args.into_iter()
+        .filter(|id| {
+            let known = rule(id).is_some();
+            if !known {
+                directives.invalid.push(Finding::new(
+                    "invalid-directive",
+                    line_num,
+                    format!("Unknown lint rule `{id}`"),
+                ));
+            }
+            known
+        })
+        .map(|id| (id, level))
+        .collect()
+}
+
+/// System facts the rules check declarations against.
+#[derive(Debug, Default)]
+pub struct LintContext {
+    pub hostname: String,
+    /// Declared names found in the sync repositories
+    pub repo_packages: HashSet<String>,
+    /// Hard dependencies of declared packages, without version constraints
+    pub depends: HashMap<String, Vec<String>>,
+    /// Declared names that are package groups
+    pub groups: HashSet<String>,
+}
+
+/// Pure computation: findings for `config`, after applying `directives`, by line.
+pub fn lint(config: &Config, directives: &Directives, context: &LintContext) -> Vec<Finding> {
+    let declared: Vec<(&Section, &Package)> = config
+        .sections
+        .iter()
+        .flat_map(|s| s.packages.iter().map(move |p| (s, p)))
+        .collect();
+
+    let mut findings = Vec::new();
+    findings.extend(duplicate_packages(&declared));
+    findings.extend(aur_official_clashes(&declared, context));
+    findings.extend(redundant_dependencies(&declared, context));
+    findings.extend(unknown_hosts(config, directives, context));
+    findings.extend(directives.invalid.iter().cloned());
+
+    let mut findings: Vec<Finding> = findings
+        .into_iter()
+        .filter_map(|mut finding| {
+            let level = directives
+                .lines
+                .get(&finding.line)
+                .and_then(|levels| levels.get(finding.rule))
+                .or_else(|| directives.global.get(finding.rule));
+            match level {
+                Some(Level::Allow) => return None,
+                Some(Level::Warn) => finding.severity = Severity::Warning,
+                Some(Level::Deny) => finding.severity = Severity::Error,
+                None => {}
+            }
+            Some(finding)
+        })
+        .collect();
+    findings.sort_by(|a, b| a.line.cmp(&b.line).then(a.rule.cmp(b.rule)));
+    findings
+}
+
+/// Whether a section with header `outer` applies everywhere `inner` does.
+fn covers(outer: &Header, inner: &Header) -> bool {
+    *outer == Header::All || outer == inner
+}
+
+/// Source and name a declaration installs; the same identity twice is a duplicate.
+fn identity(package: &Package) -> (String, &str) {
+    let kind = match &package.source {
+        PackageSource::Official => "official".to_string(),
+        PackageSource::Aur => "aur".to_string(),
+        PackageSource::Flatpak { user, .. } => format!("flatpak:{user}"),
+        PackageSource::Lang(tool) => tool.name().to_string(),
+        PackageSource::Local => "local".to_string(),
+        PackageSource::File => "file".to_string(),
+    };
+    (kind, &package.name)
+}
+
+fn duplicate_packages(declared: &[(&Section, &Package)]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut reported = HashSet::new();
+    for (i, (section, package)) in declared.iter().enumerate() {
+        for (earlier_section, earlier) in &declared[..i] {
+            if identity(earlier) != identity(package) {
+                continue;
+            }
+            // Report the declaration whose hosts are already covered by the other one
+            let (redundant, other) = if covers(&earlier_section.header, &section.header) {
+                (package, earlier)
+            } else if covers(&section.header, &earlier_section.header) {
+                (earlier, package)
+            } else {
+                continue;
+            };
+            if reported.insert(redundant.line) {
+                findings.push(Finding::new(
+                    "duplicate-package",
+                    redundant.line,
+                    format!("`{}` is also declared at line {}", redundant.name, other.line),
+                ));
+            }
+        }
+    }
+    findings
+}
+
+fn aur_official_clashes(declared: &[(&Section, &Package)], context: &LintContext) -> Vec<Finding> {
+    declared
+        .iter()
+        .filter(|(_, p)| p.source == PackageSource::Aur)
+        .filter_map(|(_, package)| {
+            let message = if context.repo_packages.contains(&package.name) {
+                format!(
+                    "`aur:{0}` is in the official repositories; declare it as `{0}`",
+                    package.name
+                )
+            } else {
+                let (_, official) = declared.iter().find(|(_, p)| {
+                    p.source == PackageSource::Official && p.name == package.name
+                })?;
+                format!(
+                    "`{}` is declared from the AUR and from the official repositories at line {}",
+                    package.name, official.line
+                )
+            };
+            Some(Finding::new("aur-official-clash", package.line, message))
+        })
+        .collect()
+}
+
+fn redundant_dependencies(declared: &[(&Section, &Package)], context: &LintContext) -> Vec<Finding> {
+    let pacman = |p: &Package| matches!(p.source, PackageSource::Official | PackageSource::Aur);
+    declared
+        .iter()
+        .filter(|(_, p)| pacman(p) && !p.hold && !context.groups.contains(&p.name))
+        .filter_map(|(section, package)| {
+            let (_, parent) = declared.iter().find(|(parent_section, parent)| {
+                pacman(parent)
+                    && parent.name != package.name
+                    && covers(&parent_section.header, &section.header)
+                    && context
+                        .depends
+                        .get(&parent.name)
+                        .is_some_and(|deps| deps.contains(&package.name))
+            })?;
+            Some(Finding::new(
+                "redundant-dependency",
+                package.line,
+                format!(
+                    "`{}` is already a dependency of `{}` (line {})",
+                    package.name, parent.name, parent.line
+                ),
+            ))
+        })
+        .collect()
+}
+
+fn unknown_hosts(config: &Config, directives: &Directives, context: &LintContext) -> Vec<Finding> {
+    let Some(hosts) = &directives.hosts else {
+        return Vec::new();
+    };
+    config
+        .sections
+        .iter()
+        .filter_map(|section| match &section.header {
+            Header::Hostname(host) if *host != context.hostname && !hosts.contains(host) => {
+                Some(Finding::new(
+                    "unknown-host",
+                    section.line,
+                    format!("Section for `{host}`, which is not in `dpkg:hosts`"),
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config_str;
+
+    fn run(input: &str, context: &LintContext) -> Vec<(usize, &'static str, Severity)> {
+        let config = parse_config_str(input).unwrap();
+        lint(&config, &parse_directives(input), context)
+            .into_iter()
+            .map(|f| (f.line, f.rule, f.severity))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint_rules() {
+        let input = "\
+// dpkg:hosts(desktop)
+## *
+git
+base-devel
+aur:yay
+aur:neovim
+## @desktop
+git
+steam
+## @laptop
+steam
+## @old-server
+neovim
+";
+        let context = LintContext {
+            hostname: "desktop".to_string(),
+            repo_packages: ["git", "neovim", "base-devel", "steam"].map(String::from).into(),
+            depends: [("base-devel".to_string(), vec!["make".to_string(), "git".to_string()])].into(),
+            groups: HashSet::new(),
+        };
+        assert_eq!(
+            run(input, &context),
+            vec![
+                (3, "redundant-dependency", Severity::Note),
+                (6, "aur-official-clash", Severity::Error),
+                (8, "duplicate-package", Severity::Warning),
+                (8, "redundant-dependency", Severity::Note),
+                (10, "unknown-host", Severity::Warning),
+                (12, "unknown-host", Severity::Warning),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_directive_scope() {
+        let input = "\
+// dpkg:warn(aur-official-clash)
+## *
+git
+git // dpkg:allow(duplicate-package)
+// dpkg:deny(duplicate-package)
+git
+git
+aur:git // dpkg:allow(no-such-rule)
+";
+        assert_eq!(
+            run(input, &LintContext::default()),
+            vec![
+                (6, "duplicate-package", Severity::Error),
+                (7, "duplicate-package", Severity::Warning),
+                (8, "aur-official-clash", Severity::Warning),
+                (8, "invalid-directive", Severity::Warning),
+            ]
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::DpkgError;
+use crate::state;
+use crate::system::{self, escalate, progress};
+
+pub fn makepkg_bin() -> String {
+    std::env::var("MAKEPKG").unwrap_or_else(|_| "makepkg".to_string())
+}
+
+/// A `local:` PKGBUILD directory and what it currently builds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocalBuild {
+    /// Path as written in the config, e.g. `./pkgs/mytool`
+    pub source: String,
+    pub dir: PathBuf,
+    /// Every `pkgname` of the PKGBUILD (split packages build several)
+    pub names: Vec<String>,
+    /// `[epoch:]pkgver-pkgrel`
+    pub version: String,
+    pub pkgbuild_hash: String,
+}
+
+impl std::fmt::Display for LocalBuild {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "local:{}", self.source)
+    }
+}
+
+/// The last successful build of a PKGBUILD directory, kept in `local-builds.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildRecord {
+    pub pkgbuild_hash: String,
+    pub version: String,
+    pub files: Vec<PathBuf>,
+}
+
+/// `local:` paths are relative to the directory holding the config file.
+pub fn resolve_dir(config_path: &Path, source: &str) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new(".")).join(source)
+}
+
+/// Read the PKGBUILD in `source` and ask makepkg for its package names and version.
+pub fn inspect(config_path: &Path, source: &str) -> Result<LocalBuild, DpkgError> {
+    let dir = resolve_dir(config_path, source);
+    let pkgbuild = std::fs::read(dir.join("PKGBUILD")).map_err(|e| {
+        DpkgError::BuildFailed(format!("Cannot read {}/PKGBUILD: {e}", dir.display()))
+    })?;
+
+    let (names, version) = parse_srcinfo(&srcinfo(&dir)?)
+        .ok_or_else(|| {
+            DpkgError::BuildFailed(format!(
+                "PKGBUILD in {} has no pkgname, pkgver or pkgrel",
+                dir.display()
+            ))
+        })?;
+
+    Ok(LocalBuild {
+        source: source.to_string(),
+        dir,
+        names,
+        version,
+        pkgbuild_hash: state::sha256_hex(&pkgbuild),
+    })
+}
+
+/// `.SRCINFO` of the PKGBUILD in `dir`, from `makepkg --printsrcinfo`.
+pub fn srcinfo(dir: &Path) -> Result<String, DpkgError> {
+    let output = makepkg_command(dir)?
+        .arg("--printsrcinfo")
+        .output()
+        .map_err(|e| DpkgError::spawn(&makepkg_bin(), e))?;
+    if !output.status.success() {
+        return Err(DpkgError::BuildFailed(format!(
+            "makepkg --printsrcinfo failed in {}: {}",
+            dir.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Inspect every declared `local:` directory.
+pub fn inspect_all(config_path: &Path, sources: &[String]) -> Result<Vec<LocalBuild>, DpkgError> {
+    sources.iter().map(|s| inspect(config_path, s)).collect()
+}
+
+/// Package names and `[epoch:]pkgver-pkgrel` from `.SRCINFO` text.
+pub fn parse_srcinfo(text: &str) -> Option<(Vec<String>, String)> {
+    let mut names = Vec::new();
+    let (mut pkgver, mut pkgrel, mut epoch) = (None, None, None);
+
+    for line in text.lines() {
+        let Some((key, value)) = line.trim().split_once(" = ") else {
+            continue;
+        };
+        match key {
+            "pkgname" => names.push(value.to_string()),
+            "pkgver" => pkgver = Some(value),
+            "pkgrel" => pkgrel = Some(value),
+            "epoch" => epoch = Some(value),
+            _ => {}
+        }
+    }
+
+    if names.is_empty() {
+        return None;
+    }
+    let version = format!("{}-{}", pkgver?, pkgrel?);
+    let version = match epoch {
+        Some(epoch) if epoch != "0" => format!("{epoch}:{version}"),
+        _ => version,
+    };
+    Some((names, version))
+}
+
+/// Dependencies needed to build from `.SRCINFO` text: `depends`, `makedepends` and
+/// `checkdepends`, including the ones for this architecture, with their constraints.
+pub fn parse_srcinfo_depends(text: &str) -> Vec<String> {
+    let mut depends: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let Some((key, value)) = line.trim().split_once(" = ") else {
+            continue;
+        };
+        let kind = match key.split_once('_') {
+            Some((kind, arch)) if arch == std::env::consts::ARCH => kind,
+            Some(_) => continue,
+            None => key,
+        };
+        if matches!(kind, "depends" | "makedepends" | "checkdepends") && !depends.iter().any(|d| d == value) {
+            depends.push(value.to_string());
+        }
+    }
+    depends
+}
+
+/// Install the repository packages among the missing build dependencies `depends`
+/// as root, on the system the build runs on (never a `--root` target). Returns the
+/// missing ones no repository provides.
+pub fn install_build_deps(depends: &[String], verbose: bool) -> Result<Vec<String>, DpkgError> {
+    if depends.is_empty() {
+        return Ok(Vec::new());
+    }
+    // pacman -T prints the dependencies that are not satisfied, versions and provides included
+    let output = Command::new(system::pacman_bin())
+        .arg("-T")
+        .args(depends)
+        .output()
+        .map_err(|e| DpkgError::spawn(&system::pacman_bin(), e))?;
+    let mut from_repos = Vec::new();
+    let mut elsewhere = Vec::new();
+    for dep in String::from_utf8_lossy(&output.stdout).lines().map(str::trim).filter(|d| !d.is_empty()) {
+        let in_repos = Command::new(system::pacman_bin())
+            .args(["-Sp", "--print-format", "%n", dep])
+            .output()
+            .is_ok_and(|output| output.status.success());
+        if in_repos {
+            from_repos.push(dep.to_string());
+        } else {
+            elsewhere.push(dep.to_string());
+        }
+    }
+
+    if !from_repos.is_empty() {
+        if verbose {
+            eprintln!("Installing {} build dependencies...", from_repos.len());
+        }
+        let mut command = escalate::command(system::pacman_bin());
+        command.args(["-S", "--needed", "--noconfirm", "--asdeps"]).args(&from_repos);
+        let output = progress::run(&mut command).map_err(|e| escalate::spawn_error(&command, e))?;
+        if !output.status.success() {
+            return Err(system::pacman_error(&output.stderr, DpkgError::InstallFailed));
+        }
+    }
+    Ok(elsewhere)
+}
+
+/// Whether `build` is installed: every package at the PKGBUILD's version, and the
+/// PKGBUILD unchanged since dpkg last built it.
+pub fn is_current(
+    build: &LocalBuild,
+    installed_versions: &HashMap<String, String>,
+    records: &BTreeMap<String, BuildRecord>,
+) -> bool {
+    let versions_match = build
+        .names
+        .iter()
+        .all(|name| installed_versions.get(name) == Some(&build.version));
+    let hash_matches = records
+        .get(&record_key(build))
+        .is_none_or(|record| record.pkgbuild_hash == build.pkgbuild_hash);
+    versions_match && hash_matches
+}
+
+/// Builds that are missing, outdated or whose PKGBUILD changed.
+pub fn pending(
+    builds: &[LocalBuild],
+    installed_versions: &HashMap<String, String>,
+) -> Result<Vec<LocalBuild>, DpkgError> {
+    let records = load_records()?;
+    Ok(builds
+        .iter()
+        .filter(|b| !is_current(b, installed_versions, &records))
+        .cloned()
+        .collect())
+}
+
+/// Build `build` with makepkg and return its package files. The previous build is
+/// reused when the PKGBUILD has not changed and its files are still there.
+pub fn build(build: &LocalBuild, verbose: bool) -> Result<Vec<PathBuf>, DpkgError> {
+    let mut records = load_records()?;
+    let key = record_key(build);
+    if let Some(record) = records.get(&key)
+        && record.pkgbuild_hash == build.pkgbuild_hash
+        && !record.files.is_empty()
+        && record.files.iter().all(|f| f.exists())
+    {
+        if verbose {
+            eprintln!("Reusing previous build of {build}");
+        }
+        return Ok(record.files.clone());
+    }
+
+    let missing = install_build_deps(&parse_srcinfo_depends(&srcinfo(&build.dir)?), verbose)?;
+    if !missing.is_empty() {
+        return Err(DpkgError::BuildFailed(format!(
+            "{build} needs {}, which no repository provides\n  Hint: Declare them as aur: or local: packages",
+            missing.join(", ")
+        )));
+    }
+    if verbose {
+        eprintln!("Building {build} in {}...", build.dir.display());
+    }
+    let files = make(&build.dir, &build.names, &build.version, &build.to_string())?;
+
+    records.insert(
+        key,
+        BuildRecord {
+            pkgbuild_hash: build.pkgbuild_hash.clone(),
+            version: build.version.clone(),
+            files: files.clone(),
+        },
+    );
+    save_records(&records)?;
+    Ok(files)
+}
+
+/// Run makepkg in `dir` and return the archives of `names` at `version`. makepkg only
+/// builds; dependencies are installed beforehand and the archives by the caller, both
+/// as root, so the build user never needs root.
+pub fn make(dir: &Path, names: &[String], version: &str, label: &str) -> Result<Vec<PathBuf>, DpkgError> {
+    let output = progress::run(makepkg_command(dir)?.args(["--force", "--noconfirm"]))
+        .map_err(|e| DpkgError::spawn(&makepkg_bin(), e))?;
+    if !output.status.success() {
+        return Err(DpkgError::BuildFailed(format!(
+            "makepkg failed for {label}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let output = makepkg_command(dir)?
+        .arg("--packagelist")
+        .output()
+        .map_err(|e| DpkgError::spawn(&makepkg_bin(), e))?;
+    let listed: Vec<PathBuf> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(PathBuf::from)
+        .filter(|p| p.exists())
+        .collect();
+    // Skip debug and other extra archives: only the wanted pkgnames get installed
+    let files: Vec<PathBuf> = names
+        .iter()
+        .filter_map(|name| system::find_package_file(&listed, name, version).cloned())
+        .collect();
+    if files.len() != names.len() {
+        return Err(DpkgError::BuildFailed(format!(
+            "makepkg did not produce packages for every pkgname of {label}"
+        )));
+    }
+    Ok(files)
+}
+
+/// makepkg refuses to run as root, so under sudo or doas it runs as the invoking user.
+pub fn makepkg_command(dir: &Path) -> Result<Command, DpkgError> {
+    let mut command = escalate::unprivileged(makepkg_bin())?;
+    command.current_dir(dir);
+    Ok(command)
+}
+
+fn record_key(build: &LocalBuild) -> String {
+    build.dir.display().to_string()
+}
+
+fn records_path() -> PathBuf {
+    state::state_dir().join("local-builds.json")
+}
+
+/// Previous builds keyed by PKGBUILD directory. A missing file means no builds yet.
+pub fn load_records() -> Result<BTreeMap<String, BuildRecord>, DpkgError> {
+    let path = records_path();
+    match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| {
+            DpkgError::State(format!("Corrupt build records {}: {e}", path.display()))
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(DpkgError::State(format!(
+            "Cannot read build records {}: {e}",
+            path.display()
+        ))),
+    }
+}
+
+fn save_records(records: &BTreeMap<String, BuildRecord>) -> Result<(), DpkgError> {
+    state::ensure_state_dir()?;
+    let path = records_path();
+    let json = serde_json::to_string_pretty(records)
+        .map_err(|e| DpkgError::State(format!("Cannot serialize build records: {e}")))?;
+    std::fs::write(&path, format!("{json}\n")).map_err(|e| {
+        DpkgError::State(format!("Cannot write build records {}: {e}", path.display()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_build(hash: &str) -> LocalBuild {
+        LocalBuild {
+            source: "./pkgs/mytool".to_string(),
+            dir: PathBuf::from("/etc/dpkg/pkgs/mytool"),
+            names: vec!["mytool".to_string(), "mytool-docs".to_string()],
+            version: "1.2-3".to_string(),
+            pkgbuild_hash: hash.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_srcinfo() {
+        let text = "pkgbase = mytool\n\tpkgdesc = In-house tool\n\tpkgver = 1.2\n\tpkgrel = 3\n\tepoch = 1\n\tarch = x86_64\n\npkgname = mytool\n\npkgname = mytool-docs\n";
+        let (names, version) = parse_srcinfo(text).unwrap();
+        assert_eq!(names, vec!["mytool", "mytool-docs"]);
+        assert_eq!(version, "1:1.2-3");
+
+        let (_, version) = parse_srcinfo("pkgver = 2.0\npkgrel = 1\nepoch = 0\npkgname = x\n").unwrap();
+        assert_eq!(version, "2.0-1");
+        assert!(parse_srcinfo("pkgname = x\npkgver = 1\n").is_none());
+    }
+
+    #[test]
+    fn test_parse_srcinfo_depends() {
+        let text = format!(
+            "pkgbase = mytool\n\tmakedepends = rust\n\tdepends = glibc>=2.38\n\tcheckdepends = python\n\tdepends_{} = lib-native\n\tdepends_nonexistent = lib-other\n\toptdepends = git: vcs\n\npkgname = mytool\n\tdepends = glibc>=2.38\n",
+            std::env::consts::ARCH
+        );
+        assert_eq!(parse_srcinfo_depends(&text), vec!["rust", "glibc>=2.38", "python", "lib-native"]);
+    }
+
+    #[test]
+    fn test_is_current() {
+        let build = local_build("abc");
+        let installed: HashMap<String, String> = [
+            ("mytool".to_string(), "1.2-3".to_string()),
+            ("mytool-docs".to_string(), "1.2-3".to_string()),
+        ]
+        .into();
+        let record = |hash: &str| BuildRecord {
+            pkgbuild_hash: hash.to_string(),
+            version: "1.2-3".to_string(),
+            files: vec![],
+        };
+
+        // Installed at the PKGBUILD version, never built by dpkg
+        assert!(is_current(&build, &installed, &BTreeMap::new()));
+        let same: BTreeMap<String, BuildRecord> = [(record_key(&build), record("abc"))].into();
+        assert!(is_current(&build, &installed, &same));
+        // PKGBUILD edited without a version bump
+        let edited: BTreeMap<String, BuildRecord> = [(record_key(&build), record("old"))].into();
+        assert!(!is_current(&build, &installed, &edited));
+        // One split package missing
+        let partial: HashMap<String, String> =
+            [("mytool".to_string(), "1.2-3".to_string())].into();
+        assert!(!is_current(&build, &partial, &same));
+    }
+}
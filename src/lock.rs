@@ -0,0 +1,219 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::DpkgError;
+use crate::system;
+
+const LOCK_FORMAT_VERSION: u32 = 1;
+
+/// Contents of `pkg.lock`: the exact package versions per host.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockFile {
+    pub version: u32,
+    pub hosts: BTreeMap<String, Vec<LockedPackage>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    /// Repository the package comes from (`core`, `extra`, ... or `aur`).
+    pub repo: String,
+    /// Git commit of the AUR package's PKGBUILD repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+}
+
+impl LockFile {
+    pub fn new() -> Self {
+        LockFile {
+            version: LOCK_FORMAT_VERSION,
+            hosts: BTreeMap::new(),
+        }
+    }
+
+    /// Locked packages for `hostname`, keyed by package name.
+    pub fn for_host(&self, hostname: &str) -> Option<HashMap<&str, &LockedPackage>> {
+        self.hosts
+            .get(hostname)
+            .map(|pkgs| pkgs.iter().map(|p| (p.name.as_str(), p)).collect())
+    }
+}
+
+/// The lockfile lives next to the config: `pkg.conf` → `pkg.lock`.
+pub fn lock_path(config_path: &Path) -> PathBuf {
+    config_path.with_extension("lock")
+}
+
+/// Load the lockfile, returning `None` if it does not exist.
+pub fn load(path: &Path) -> Result<Option<LockFile>, DpkgError> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(DpkgError::Lock(format!(
+                "Cannot read lockfile {}: {e}",
+                path.display()
+            )));
+        }
+    };
+    let lock: LockFile = serde_json::from_str(&content)
+        .map_err(|e| DpkgError::Lock(format!("Invalid lockfile {}: {e}", path.display())))?;
+    if lock.version != LOCK_FORMAT_VERSION {
+        return Err(DpkgError::Lock(format!(
+            "Unsupported lockfile format version {} in {}",
+            lock.version,
+            path.display()
+        )));
+    }
+    Ok(Some(lock))
+}
+
+pub fn save(path: &Path, lock: &LockFile) -> Result<(), DpkgError> {
+    let json = serde_json::to_string_pretty(lock)
+        .map_err(|e| DpkgError::Lock(format!("Cannot serialize lockfile: {e}")))?;
+    std::fs::write(path, format!("{json}\n"))
+        .map_err(|e| DpkgError::Lock(format!("Cannot write lockfile {}: {e}", path.display())))
+}
+
+/// Locate archives for every locked package that is missing or installed at a
+/// different version. `candidates` lists the archive files to consider for a
+/// package name, in order of preference.
+/// Fails listing every package whose locked version cannot be found.
+pub fn resolve_locked_files(
+    desired: &[String],
+    locked: &HashMap<&str, &LockedPackage>,
+    installed_versions: &HashMap<String, String>,
+    candidates: &dyn Fn(&str) -> Vec<PathBuf>,
+) -> Result<Vec<PathBuf>, DpkgError> {
+    let mut files = Vec::new();
+    let mut unlocked = Vec::new();
+    let mut unavailable = Vec::new();
+
+    for name in desired {
+        let Some(pkg) = locked.get(name.as_str()) else {
+            unlocked.push(name.clone());
+            continue;
+        };
+        if installed_versions.get(name) == Some(&pkg.version) {
+            continue;
+        }
+        match system::find_package_file(&candidates(&pkg.name), &pkg.name, &pkg.version) {
+            Some(file) => files.push(file.clone()),
+            None => unavailable.push(format!("{} {}", pkg.name, pkg.version)),
+        }
+    }
+
+    if !unlocked.is_empty() {
+        return Err(DpkgError::Lock(format!(
+            "Packages not in lockfile: {}\n  Hint: Run `dpkg lock` to update the lockfile",
+            unlocked.join(", ")
+        )));
+    }
+    if !unavailable.is_empty() {
+        return Err(DpkgError::Lock(format!(
+            "Locked versions not available in cache or mirror: {}",
+            unavailable.join(", ")
+        )));
+    }
+    Ok(files)
+}
+
+/// Archive files for `name` in an Arch Linux Archive style mirror
+/// (`<mirror>/packages/<first letter>/<name>/`), falling back to a flat directory.
+pub fn mirror_candidates(mirror: &Path, name: &str) -> Vec<PathBuf> {
+    let first = name.chars().next().map(|c| c.to_string()).unwrap_or_default();
+    let mut files = system::list_cached_packages(&mirror.join("packages").join(first).join(name));
+    files.extend(system::list_cached_packages(mirror));
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locked(name: &str, version: &str) -> LockedPackage {
+        LockedPackage {
+            name: name.to_string(),
+            version: version.to_string(),
+            repo: "extra".to_string(),
+            commit: None,
+        }
+    }
+
+    #[test]
+    fn test_lock_path() {
+        assert_eq!(
+            lock_path(Path::new("/home/u/.config/dpkg/pkg.conf")),
+            PathBuf::from("/home/u/.config/dpkg/pkg.lock")
+        );
+    }
+
+    #[test]
+    fn test_resolve_skips_matching_versions() {
+        let git = locked("git", "2.44.0-1");
+        let map: HashMap<&str, &LockedPackage> = [("git", &git)].into();
+        let installed: HashMap<String, String> =
+            [("git".to_string(), "2.44.0-1".to_string())].into();
+        let files =
+            resolve_locked_files(&["git".to_string()], &map, &installed, &|_| Vec::new()).unwrap();
+        assert!(files.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_finds_matching_version() {
+        let git = locked("git", "2.44.0-1");
+        let map: HashMap<&str, &LockedPackage> = [("git", &git)].into();
+        let candidates = |_: &str| {
+            vec![
+                PathBuf::from("/cache/git-2.45.0-1-x86_64.pkg.tar.zst"),
+                PathBuf::from("/mirror/git-2.44.0-1-x86_64.pkg.tar.zst"),
+            ]
+        };
+        let files =
+            resolve_locked_files(&["git".to_string()], &map, &HashMap::new(), &candidates)
+                .unwrap();
+        assert_eq!(files, vec![PathBuf::from("/mirror/git-2.44.0-1-x86_64.pkg.tar.zst")]);
+    }
+
+    #[test]
+    fn test_resolve_fails_when_unavailable() {
+        let git = locked("git", "2.44.0-1");
+        let map: HashMap<&str, &LockedPackage> = [("git", &git)].into();
+        let result =
+            resolve_locked_files(&["git".to_string()], &map, &HashMap::new(), &|_| Vec::new());
+        assert!(matches!(result, Err(DpkgError::Lock(msg)) if msg.contains("git 2.44.0-1")));
+    }
+
+    #[test]
+    fn test_resolve_fails_for_unlocked_package() {
+        let result = resolve_locked_files(
+            &["steam".to_string()],
+            &HashMap::new(),
+            &HashMap::new(),
+            &|_| Vec::new(),
+        );
+        assert!(matches!(result, Err(DpkgError::Lock(msg)) if msg.contains("steam")));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut lock = LockFile::new();
+        lock.hosts.insert(
+            "desktop".to_string(),
+            vec![LockedPackage {
+                name: "yay".to_string(),
+                version: "12.3.5-1".to_string(),
+                repo: "aur".to_string(),
+                commit: Some("0123abc".to_string()),
+            }],
+        );
+        let json = serde_json::to_string(&lock).unwrap();
+        let parsed: LockFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, lock);
+        assert!(parsed.for_host("desktop").unwrap().contains_key("yay"));
+        assert!(parsed.for_host("laptop").is_none());
+    }
+}
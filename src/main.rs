@@ -1,25 +1,57 @@
+mod archive;
+mod aur;
 mod cli;
 mod commands;
 mod config;
+mod dblock;
 mod error;
+mod hold;
+mod hooks;
+mod keyring;
+mod lint;
+mod local;
+mod lock;
+mod metapackage;
 mod output;
+mod repos;
+mod state;
 mod system;
 
 use std::process;
 
 use clap::Parser;
 
-use cli::{Cli, Command};
+use cli::{Cli, Command, ErrorFormat, Progress};
 
 fn main() {
     let cli = Cli::parse();
+    cli.check_combinations();
     let config_path = cli.config_path();
+    system::progress::set_mode(if cli.quiet && cli.progress == Progress::Compact {
+        Progress::Off
+    } else {
+        cli.progress
+    });
+    system::set_target(system::Target {
+        root: cli.root.clone(),
+        dbpath: cli.dbpath.clone(),
+        cachedir: cli.cachedir.clone(),
+        host: cli.host.clone(),
+    });
 
     let result = match cli.command {
         Some(Command::Sync {
             no_confirm,
             only_install,
             only_remove,
+            locked,
+            mirror,
+            only_source,
+            strategy,
+            keep_going,
+            wait_lock,
+            aur_user,
+            format,
         }) => commands::sync::run(
             &config_path,
             &commands::sync::SyncOptions {
@@ -29,11 +61,48 @@ fn main() {
                 no_confirm,
                 only_install,
                 only_remove,
+                locked,
+                mirror,
+                only_source,
+                strategy,
+                keep_going,
+                wait_lock: cli::lock_wait(wait_lock),
+                aur_user,
+                format,
             },
         ),
         Some(Command::Status) => commands::status::run(&config_path, cli.quiet),
         Some(Command::Validate) => commands::validate::run(&config_path, cli.quiet),
-        Some(Command::Diff) => commands::diff::run(&config_path, cli.quiet),
+        Some(Command::Diff { only_source }) => {
+            commands::diff::run(&config_path, &only_source, cli.quiet)
+        }
+        Some(Command::Why { package }) => commands::why::run(&config_path, &package, cli.quiet),
+        Some(Command::Lint { format }) => commands::lint::run(&config_path, format, cli.quiet),
+        Some(Command::ExplainError { code }) => commands::explain_error::run(code.as_deref()),
+        Some(Command::Lock) => commands::lock::run(&config_path, cli.dry_run, cli.quiet),
+        Some(Command::History { last, show }) => {
+            commands::history::run(last, show, cli.quiet)
+        }
+        Some(Command::Bootstrap { dir }) => {
+            commands::bootstrap::run(&config_path, &dir, cli.dry_run, cli.verbose, cli.quiet)
+        }
+        Some(Command::Rollback {
+            generation,
+            list,
+            no_confirm,
+            wait_lock,
+        }) => commands::rollback::run(
+            &config_path,
+            generation,
+            list,
+            &commands::rollback::RollbackOptions {
+                dry_run: cli.dry_run,
+                verbose: cli.verbose,
+                quiet: cli.quiet,
+                no_confirm,
+                wait_lock: cli::lock_wait(wait_lock),
+            },
+        ),
         // Default: sync with no extra options
         None => commands::sync::run(
             &config_path,
@@ -44,12 +113,24 @@ fn main() {
                 no_confirm: false,
                 only_install: false,
                 only_remove: false,
+                locked: false,
+                mirror: None,
+                only_source: Vec::new(),
+                strategy: cli::SyncStrategy::Mark,
+                keep_going: false,
+                wait_lock: None,
+                aur_user: None,
+                format: cli::PlanFormat::Text,
             },
         ),
     };
+    repos::remove_pacman_config();
 
     if let Err(e) = result {
-        output::error(&format!("Error: {e}"));
+        match cli.error_format {
+            ErrorFormat::Text => output::error(&format!("Error [{}]: {e}", e.code())),
+            ErrorFormat::Json => eprintln!("{}", e.to_json()),
+        }
         process::exit(e.exit_code());
     }
 }
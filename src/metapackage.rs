@@ -0,0 +1,144 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::DpkgError;
+use crate::local;
+use crate::state;
+use crate::system::{self, escalate, progress};
+
+/// The per-host meta-package whose `depends` are the declared official packages.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetaPackage {
+    pub name: String,
+    pub depends: Vec<String>,
+}
+
+/// `dpkg-host-<hostname>`, lowercased since pacman package names are.
+pub fn package_name(hostname: &str) -> String {
+    format!("dpkg-host-{}", hostname.to_lowercase())
+}
+
+/// Directory the PKGBUILD is generated and built in. makepkg runs as the build user,
+/// so it lives in that user's cache rather than the state directory.
+pub fn build_dir(name: &str) -> PathBuf {
+    system::build_cache_dir().join("dpkg/metapackage").join(name)
+}
+
+pub fn render_pkgbuild(meta: &MetaPackage, hostname: &str, pkgver: u64) -> String {
+    let mut pkgbuild = format!(
+        "# Generated by dpkg for {hostname}; changes are overwritten on the next sync\n\
+         pkgname={}\n\
+         pkgver={pkgver}\n\
+         pkgrel=1\n\
+         pkgdesc=\"Packages declared for {hostname} in pkg.conf\"\n\
+         arch=(any)\n\
+         depends=(\n",
+        meta.name
+    );
+    for dep in &meta.depends {
+        pkgbuild.push_str(&format!("  '{dep}'\n"));
+    }
+    pkgbuild.push_str(")\n");
+    pkgbuild
+}
+
+/// The meta-package to build, or `None` when the installed one already depends on
+/// exactly `desired_official`.
+pub fn plan(
+    hostname: &str,
+    desired_official: &[String],
+    installed: Option<&[String]>,
+) -> Option<MetaPackage> {
+    let desired: BTreeSet<&String> = desired_official.iter().collect();
+    if installed.is_some_and(|deps| deps.iter().collect::<BTreeSet<_>>() == desired) {
+        return None;
+    }
+    Some(MetaPackage {
+        name: package_name(hostname),
+        depends: desired.into_iter().cloned().collect(),
+    })
+}
+
+/// Generate, build and install the meta-package. `pacman -U` pulls in missing
+/// dependencies from the repositories, installed as dependencies.
+pub fn build_and_install(meta: &MetaPackage, hostname: &str, verbose: bool) -> Result<(), DpkgError> {
+    let dir = build_dir(&meta.name);
+    // Created as the build user, who has to write the build into it
+    let mut mkdir = escalate::unprivileged("mkdir")?;
+    mkdir.arg("-p").arg(&dir);
+    let output = mkdir.output().map_err(|e| escalate::spawn_error(&mkdir, e))?;
+    if !output.status.success() {
+        return Err(DpkgError::BuildFailed(format!(
+            "Cannot create {}: {}",
+            dir.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // A timestamp keeps every rebuild newer than the installed meta-package
+    let pkgver = state::now_unix();
+    std::fs::write(dir.join("PKGBUILD"), render_pkgbuild(meta, hostname, pkgver)).map_err(|e| {
+        DpkgError::State(format!("Cannot write {}/PKGBUILD: {e}", dir.display()))
+    })?;
+
+    if verbose {
+        eprintln!("Building {} with {} dependencies...", meta.name, meta.depends.len());
+    }
+    // Dependencies are resolved by pacman -U, not at build time
+    let output = progress::run(local::makepkg_command(&dir)?.args(["--force", "--nodeps"]))
+        .map_err(|e| DpkgError::spawn(&local::makepkg_bin(), e))?;
+    if !output.status.success() {
+        return Err(DpkgError::BuildFailed(format!(
+            "makepkg failed for {}: {}",
+            meta.name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let output = local::makepkg_command(&dir)?
+        .arg("--packagelist")
+        .output()
+        .map_err(|e| DpkgError::spawn(&local::makepkg_bin(), e))?;
+    let listed: Vec<PathBuf> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(PathBuf::from)
+        .collect();
+    let file = system::find_package_file(&listed, &meta.name, &format!("{pkgver}-1"))
+        .ok_or_else(|| {
+            DpkgError::BuildFailed(format!("makepkg did not produce {}", meta.name))
+        })?;
+
+    system::install_files(std::slice::from_ref(file), verbose)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sv(vals: &[&str]) -> Vec<String> {
+        vals.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_render_pkgbuild() {
+        let meta = MetaPackage {
+            name: package_name("MyDesktop"),
+            depends: sv(&["base", "git"]),
+        };
+        let pkgbuild = render_pkgbuild(&meta, "MyDesktop", 1_700_000_000);
+        assert!(pkgbuild.contains("pkgname=dpkg-host-mydesktop\n"));
+        assert!(pkgbuild.contains("pkgver=1700000000\n"));
+        assert!(pkgbuild.contains("depends=(\n  'base'\n  'git'\n)\n"));
+    }
+
+    #[test]
+    fn test_plan_only_when_depends_differ() {
+        let desired = sv(&["git", "base"]);
+        assert_eq!(plan("host", &desired, Some(&sv(&["base", "git"]))), None);
+
+        let meta = plan("host", &desired, Some(&sv(&["base"]))).unwrap();
+        assert_eq!(meta.depends, sv(&["base", "git"]));
+        assert!(plan("host", &desired, None).is_some());
+    }
+}
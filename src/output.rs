@@ -56,6 +56,14 @@ pub fn added(name: &str, detail: &str) {
     }
 }
 
+pub fn changed(name: &str, detail: &str) {
+    if color_enabled() {
+        println!("{} {:<30} {}", "~".yellow(), name.yellow(), detail);
+    } else {
+        println!("~ {name:<30} {detail}");
+    }
+}
+
 pub fn removed(name: &str, detail: &str) {
     if color_enabled() {
         println!("{} {:<30} {}", "-".red(), name.red(), detail);
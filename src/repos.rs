@@ -0,0 +1,328 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::PacmanRepo;
+use crate::error::DpkgError;
+use crate::hold;
+use crate::system::{self, escalate};
+
+const HEADER: &str = "# Managed by dpkg; changes are overwritten on the next sync";
+
+/// pacman.conf, overridable with `PACMAN_CONF`; under `--root` the target's.
+pub fn pacman_conf_path() -> PathBuf {
+    std::env::var("PACMAN_CONF")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| system::in_root(Path::new("/etc/pacman.conf")))
+}
+
+/// The `Include` file dpkg owns, overridable with `DPKG_REPOS_CONF`. This is the path
+/// as the target system sees it; under `--root` the file is written below the root.
+pub fn include_path() -> PathBuf {
+    std::env::var("DPKG_REPOS_CONF")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/pacman.d/dpkg-repos.conf"))
+}
+
+/// Pure computation: `pacman_conf` with the `Include` lines naming one of `managed`
+/// pointed below `root`. pacman resolves `Include` lines on the host even with `--root`,
+/// so a `--root` run hands pacman this copy instead of the target's own pacman.conf.
+pub fn host_config(pacman_conf: &str, root: &Path, managed: &[PathBuf]) -> String {
+    pacman_conf
+        .lines()
+        .map(|line| match line.trim().split_once('=') {
+            Some((key, value)) if key.trim() == "Include" && managed.iter().any(|p| p == Path::new(value.trim())) => {
+                let path = system::below_root(Some(root), Path::new(value.trim()));
+                format!("Include = {}", path.display())
+            }
+            _ => line.to_string(),
+        })
+        .map(|line| line + "\n")
+        .collect()
+}
+
+/// Directory holding the generated host-side pacman.conf of a `--root` run, if created.
+static HOST_CONFIG_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// The pacman.conf pacman reads: the target's, or under `--root` a copy from
+/// `host_config`, regenerated on each call since a sync may add `Include` lines. Falls
+/// back to the target's pacman.conf if the copy cannot be written; pacman then reports
+/// what is missing.
+pub fn pacman_config() -> PathBuf {
+    let conf_path = pacman_conf_path();
+    let Some(root) = &system::target().root else {
+        return conf_path;
+    };
+    let Ok(pacman_conf) = std::fs::read_to_string(&conf_path) else {
+        return conf_path;
+    };
+    let dir = HOST_CONFIG_DIR.get_or_init(|| {
+        // A fresh directory, so nothing else can have planted the file
+        let dir = std::env::temp_dir().join(format!("dpkg-{}", std::process::id()));
+        std::fs::create_dir(&dir).ok().map(|_| dir)
+    });
+    let Some(dir) = dir else {
+        return conf_path;
+    };
+    let managed = [include_path(), hold::include_path()];
+    let path = dir.join("pacman.conf");
+    match std::fs::write(&path, host_config(&pacman_conf, root, &managed)) {
+        Ok(()) => path,
+        Err(_) => conf_path,
+    }
+}
+
+/// Remove the generated host-side pacman.conf, if any.
+pub fn remove_pacman_config() {
+    if let Some(Some(dir)) = HOST_CONFIG_DIR.get() {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+/// The lines appended to pacman.conf to include the managed file at `path`.
+pub fn include_entry(path: &Path) -> String {
+    format!("\n# Repositories declared in pkg.conf\nInclude = {}\n", path.display())
+}
+
+/// How the managed repositories differ from the declared ones.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RepoChanges {
+    pub added: Vec<PacmanRepo>,
+    pub changed: Vec<PacmanRepo>,
+    pub removed: Vec<String>,
+    /// pacman.conf does not include the managed file yet
+    #[serde(default)]
+    pub include_missing: bool,
+}
+
+impl RepoChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.changed.is_empty()
+            && self.removed.is_empty()
+            && !self.include_missing
+    }
+}
+
+/// Contents of the managed `Include` file for `repos`, in declaration order.
+pub fn render(repos: &[PacmanRepo]) -> String {
+    let mut content = format!("{HEADER}\n");
+    for repo in repos {
+        content.push_str(&format!("\n[{}]\n", repo.name));
+        if let Some(sig_level) = &repo.sig_level {
+            content.push_str(&format!("SigLevel = {sig_level}\n"));
+        }
+        content.push_str(&format!("Server = {}\n", repo.server));
+    }
+    content
+}
+
+/// Read repositories back from a file written by `render`.
+pub fn parse(content: &str) -> Vec<PacmanRepo> {
+    let mut repos: Vec<PacmanRepo> = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            repos.push(PacmanRepo {
+                name: name.to_string(),
+                server: String::new(),
+                sig_level: None,
+            });
+        } else if let Some((key, value)) = line.split_once('=')
+            && let Some(repo) = repos.last_mut()
+        {
+            match key.trim() {
+                "Server" => repo.server = value.trim().to_string(),
+                "SigLevel" => repo.sig_level = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+    }
+    repos
+}
+
+/// Pure computation: differences between the managed and the declared repositories.
+pub fn compute_changes(
+    desired: &[PacmanRepo],
+    current: &[PacmanRepo],
+    include_missing: bool,
+) -> RepoChanges {
+    let find = |name: &str| current.iter().find(|r| r.name == name);
+    RepoChanges {
+        added: desired
+            .iter()
+            .filter(|r| find(&r.name).is_none())
+            .cloned()
+            .collect(),
+        changed: desired
+            .iter()
+            .filter(|r| find(&r.name).is_some_and(|c| c != *r))
+            .cloned()
+            .collect(),
+        removed: current
+            .iter()
+            .filter(|c| !desired.iter().any(|r| r.name == c.name))
+            .map(|c| c.name.clone())
+            .collect(),
+        include_missing,
+    }
+}
+
+/// Whether pacman.conf has an active `Include = <path>` line.
+pub fn is_included(pacman_conf: &str, path: &Path) -> bool {
+    pacman_conf.lines().any(|line| {
+        line.trim()
+            .split_once('=')
+            .is_some_and(|(key, value)| key.trim() == "Include" && Path::new(value.trim()) == path)
+    })
+}
+
+/// Compare declared repositories with the managed file and pacman.conf. Without
+/// declarations and without a managed file there is nothing to manage.
+pub fn plan(desired: &[PacmanRepo]) -> Result<RepoChanges, DpkgError> {
+    let path = include_path();
+    let current = match std::fs::read_to_string(system::in_root(&path)) {
+        Ok(content) => parse(&content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if desired.is_empty() {
+                return Ok(RepoChanges::default());
+            }
+            Vec::new()
+        }
+        Err(e) => {
+            return Err(DpkgError::QueryFailed(format!(
+                "Cannot read {}: {e}",
+                path.display()
+            )));
+        }
+    };
+
+    let include_missing = if desired.is_empty() {
+        false
+    } else {
+        let conf_path = pacman_conf_path();
+        let pacman_conf = std::fs::read_to_string(&conf_path).map_err(|e| {
+            DpkgError::QueryFailed(format!("Cannot read {}: {e}", conf_path.display()))
+        })?;
+        !is_included(&pacman_conf, &path)
+    };
+
+    Ok(compute_changes(desired, &current, include_missing))
+}
+
+/// Write the managed file, reference it from pacman.conf if needed, and refresh the
+/// sync databases with `pacman -Sy`.
+pub fn apply(desired: &[PacmanRepo], changes: &RepoChanges, verbose: bool) -> Result<(), DpkgError> {
+    let path = include_path();
+    if verbose {
+        eprintln!("Writing {}...", system::in_root(&path).display());
+    }
+    root_write(&system::in_root(&path), &render(desired), false)?;
+
+    if changes.include_missing {
+        let conf_path = pacman_conf_path();
+        if verbose {
+            eprintln!("Adding Include = {} to {}...", path.display(), conf_path.display());
+        }
+        root_write(&conf_path, &include_entry(&path), true)?;
+    }
+
+    system::refresh_databases(verbose)
+}
+
+/// Write (or append to) a root-owned file through `tee` run as root.
+pub fn root_write(path: &Path, content: &str, append: bool) -> Result<(), DpkgError> {
+    let mut command = escalate::command("tee");
+    if append {
+        command.arg("-a");
+    }
+    let mut child = command
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| escalate::spawn_error(&command, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes()).map_err(|e| {
+            DpkgError::PermissionDenied(format!("Cannot write {}: {e}", path.display()))
+        })?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| DpkgError::PermissionDenied(format!("Failed to run tee: {e}")))?;
+    if !output.status.success() {
+        return Err(DpkgError::PermissionDenied(format!(
+            "Cannot write {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo(name: &str, server: &str, sig_level: Option<&str>) -> PacmanRepo {
+        PacmanRepo {
+            name: name.to_string(),
+            server: server.to_string(),
+            sig_level: sig_level.map(|s| s.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_render_parse_roundtrip() {
+        let repos = vec![
+            repo("company", "file:///srv/repo/$arch", Some("Optional TrustAll")),
+            repo("multilib", "https://geo.mirror.pkgbuild.com/$repo/os/$arch", None),
+        ];
+        let content = render(&repos);
+        assert!(content.contains("[company]\nSigLevel = Optional TrustAll\nServer = file:///srv/repo/$arch\n"));
+        assert_eq!(parse(&content), repos);
+    }
+
+    #[test]
+    fn test_compute_changes() {
+        let current = vec![
+            repo("company", "file:///srv/old", None),
+            repo("multilib", "https://mirror/$repo", None),
+            repo("legacy", "file:///srv/legacy", None),
+        ];
+        let desired = vec![
+            repo("company", "file:///srv/repo", None),
+            repo("multilib", "https://mirror/$repo", None),
+            repo("testing", "file:///srv/testing", Some("Never")),
+        ];
+        let changes = compute_changes(&desired, &current, false);
+        assert_eq!(changes.added, vec![repo("testing", "file:///srv/testing", Some("Never"))]);
+        assert_eq!(changes.changed, vec![repo("company", "file:///srv/repo", None)]);
+        assert_eq!(changes.removed, vec!["legacy".to_string()]);
+        assert!(compute_changes(&desired, &desired, false).is_empty());
+    }
+
+    #[test]
+    fn test_is_included() {
+        let path = Path::new("/etc/pacman.d/dpkg-repos.conf");
+        assert!(is_included("[options]\nInclude = /etc/pacman.d/dpkg-repos.conf\n", path));
+        assert!(!is_included("#Include = /etc/pacman.d/dpkg-repos.conf\n", path));
+        assert!(!is_included("Include = /etc/pacman.d/mirrorlist\n", path));
+    }
+
+    #[test]
+    fn test_host_config_below_root() {
+        let managed = [PathBuf::from("/etc/pacman.d/dpkg-repos.conf"), PathBuf::from("/etc/pacman.d/dpkg-hold.conf")];
+        // The target's pacman.conf names the files as the booted image sees them
+        let conf = format!("[options]\nInclude = /etc/pacman.d/dpkg-hold.conf\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n{}", include_entry(&managed[0]));
+        assert!(is_included(&conf, &managed[0]));
+
+        let host = host_config(&conf, Path::new("/mnt"), &managed);
+        assert!(host.contains("[options]\nInclude = /mnt/etc/pacman.d/dpkg-hold.conf\n"));
+        assert!(host.contains("[core]\nInclude = /etc/pacman.d/mirrorlist\n"));
+        assert!(host.ends_with("\nInclude = /mnt/etc/pacman.d/dpkg-repos.conf\n"));
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::DpkgError;
+use crate::state;
+use crate::system::InstalledPackage;
+
+/// Snapshot of the installed package set after a successful sync.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Generation {
+    pub id: u64,
+    pub timestamp: u64,
+    pub hostname: String,
+    pub packages: Vec<InstalledPackage>,
+}
+
+pub fn generations_dir() -> PathBuf {
+    state::state_dir().join("generations")
+}
+
+fn generation_path(id: u64) -> PathBuf {
+    generations_dir().join(format!("{id}.json"))
+}
+
+/// IDs of all stored generations, ascending.
+pub fn list_ids() -> Result<Vec<u64>, DpkgError> {
+    let dir = generations_dir();
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(DpkgError::State(format!(
+                "Cannot read generations directory {}: {e}",
+                dir.display()
+            )));
+        }
+    };
+
+    let mut ids: Vec<u64> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            e.file_name()
+                .to_str()
+                .and_then(|n| n.strip_suffix(".json"))
+                .and_then(|n| n.parse().ok())
+        })
+        .collect();
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+pub fn load(id: u64) -> Result<Generation, DpkgError> {
+    let path = generation_path(id);
+    let content = std::fs::read_to_string(&path).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            DpkgError::State(format!("Generation {id} does not exist"))
+        } else {
+            DpkgError::State(format!("Cannot read generation {}: {e}", path.display()))
+        }
+    })?;
+    serde_json::from_str(&content)
+        .map_err(|e| DpkgError::State(format!("Corrupt generation file {}: {e}", path.display())))
+}
+
+/// Store `packages` as a new generation, unless it is identical to the latest one.
+/// Returns the ID of the new generation, or `None` if nothing changed.
+pub fn record(hostname: &str, packages: Vec<InstalledPackage>) -> Result<Option<u64>, DpkgError> {
+    let ids = list_ids()?;
+    if let Some(&latest) = ids.last()
+        && load(latest)?.packages == packages
+    {
+        return Ok(None);
+    }
+
+    let generation = Generation {
+        id: ids.last().map_or(1, |id| id + 1),
+        timestamp: state::now_unix(),
+        hostname: hostname.to_string(),
+        packages,
+    };
+
+    std::fs::create_dir_all(generations_dir()).map_err(|e| {
+        DpkgError::State(format!("Cannot create generations directory: {e}"))
+    })?;
+    let path = generation_path(generation.id);
+    let json = serde_json::to_string_pretty(&generation)
+        .map_err(|e| DpkgError::State(format!("Cannot serialize generation: {e}")))?;
+    std::fs::write(&path, json)
+        .map_err(|e| DpkgError::State(format!("Cannot write {}: {e}", path.display())))?;
+
+    Ok(Some(generation.id))
+}
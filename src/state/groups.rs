@@ -0,0 +1,137 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::GroupExclusion;
+use crate::error::DpkgError;
+use crate::state;
+
+/// Members of each declared group as of the last sync, keyed by group name.
+pub type GroupMembership = BTreeMap<String, Vec<String>>;
+
+/// How a group's membership changed since the last sync.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupDrift {
+    pub group: String,
+    /// New members, without the excluded ones
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+fn groups_path() -> PathBuf {
+    state::state_dir().join("groups.json")
+}
+
+/// Recorded membership. A missing file means no sync has recorded groups yet.
+pub fn load() -> Result<GroupMembership, DpkgError> {
+    let path = groups_path();
+    match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| {
+            DpkgError::State(format!("Corrupt group membership {}: {e}", path.display()))
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(GroupMembership::new()),
+        Err(e) => Err(DpkgError::State(format!(
+            "Cannot read group membership {}: {e}",
+            path.display()
+        ))),
+    }
+}
+
+/// Record `groups` as seen now, leaving `declined` new members out so they are
+/// offered again next time. Groups not in `groups` keep their old record.
+pub fn record(
+    groups: &HashMap<String, Vec<String>>,
+    declined: &[String],
+) -> Result<(), DpkgError> {
+    if groups.is_empty() {
+        return Ok(());
+    }
+    let mut membership = load()?;
+    for (group, members) in groups {
+        let mut members: Vec<String> =
+            members.iter().filter(|m| !declined.contains(m)).cloned().collect();
+        members.sort();
+        membership.insert(group.clone(), members);
+    }
+
+    state::ensure_state_dir()?;
+    let path = groups_path();
+    let json = serde_json::to_string_pretty(&membership)
+        .map_err(|e| DpkgError::State(format!("Cannot serialize group membership: {e}")))?;
+    std::fs::write(&path, format!("{json}\n")).map_err(|e| {
+        DpkgError::State(format!("Cannot write group membership {}: {e}", path.display()))
+    })
+}
+
+/// Pure computation: membership changes of the groups in `current` that have a record.
+pub fn compute_drift(
+    recorded: &GroupMembership,
+    current: &HashMap<String, Vec<String>>,
+    exclusions: &[GroupExclusion],
+) -> Vec<GroupDrift> {
+    let mut drift: Vec<GroupDrift> = current
+        .iter()
+        .filter_map(|(group, members)| {
+            let before = recorded.get(group)?;
+            let excluded = exclusions
+                .iter()
+                .find(|e| e.group == *group)
+                .map_or(&[][..], |e| e.members.as_slice());
+            let added: Vec<String> = members
+                .iter()
+                .filter(|m| !before.contains(m) && !excluded.contains(m))
+                .cloned()
+                .collect();
+            let removed: Vec<String> =
+                before.iter().filter(|m| !members.contains(m)).cloned().collect();
+            (!added.is_empty() || !removed.is_empty()).then(|| GroupDrift {
+                group: group.clone(),
+                added,
+                removed,
+            })
+        })
+        .collect();
+    drift.sort_by(|a, b| a.group.cmp(&b.group));
+    drift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sv(vals: &[&str]) -> Vec<String> {
+        vals.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_compute_drift() {
+        let recorded: GroupMembership = [
+            ("gnome".to_string(), sv(&["nautilus", "gnome-maps"])),
+            ("kde".to_string(), sv(&["dolphin"])),
+        ]
+        .into();
+        let current: HashMap<String, Vec<String>> = [
+            ("gnome".to_string(), sv(&["nautilus", "epiphany", "gnome-weather", "loupe"])),
+            ("kde".to_string(), sv(&["dolphin"])),
+            ("xfce4".to_string(), sv(&["thunar"])),
+        ]
+        .into();
+        let exclusions = vec![GroupExclusion {
+            group: "gnome".to_string(),
+            members: sv(&["epiphany"]),
+            line: 2,
+        }];
+
+        assert_eq!(
+            compute_drift(&recorded, &current, &exclusions),
+            vec![GroupDrift {
+                group: "gnome".to_string(),
+                added: sv(&["gnome-weather", "loupe"]),
+                removed: sv(&["gnome-maps"]),
+            }]
+        );
+        // Without a record there is nothing to compare against
+        assert!(compute_drift(&GroupMembership::new(), &current, &[]).is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands::sync::SyncPlan;
+use crate::error::DpkgError;
+use crate::state;
+
+/// One sync run, stored as a single line of JSON in `history.jsonl`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    pub config_path: String,
+    pub config_hash: String,
+    pub hostname: String,
+    pub plan: SyncPlan,
+    pub steps: Vec<StepRecord>,
+    pub versions: Vec<VersionChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepRecord {
+    pub name: String,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Stable code of the error, e.g. `DPKG-E012`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+/// A package whose installed version differs between before and after the run.
+/// `None` means the package was not installed at that point.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VersionChange {
+    pub name: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl JournalEntry {
+    pub fn succeeded(&self) -> bool {
+        self.steps.iter().all(|s| s.ok)
+    }
+}
+
+pub fn journal_path() -> PathBuf {
+    state::state_dir().join("history.jsonl")
+}
+
+/// Read every entry in the journal, oldest first. A missing journal is empty.
+pub fn read_entries() -> Result<Vec<JournalEntry>, DpkgError> {
+    let path = journal_path();
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(DpkgError::State(format!(
+                "Cannot read history journal {}: {e}",
+                path.display()
+            )));
+        }
+    };
+    parse_entries(&content)
+}
+
+fn parse_entries(content: &str) -> Result<Vec<JournalEntry>, DpkgError> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                DpkgError::State(format!("Corrupt history journal entry at line {}: {e}", i + 1))
+            })
+        })
+        .collect()
+}
+
+/// Next free journal ID (one past the highest recorded ID).
+pub fn next_id() -> Result<u64, DpkgError> {
+    Ok(read_entries()?.iter().map(|e| e.id).max().unwrap_or(0) + 1)
+}
+
+/// Append an entry to the journal, creating the state directory if needed.
+pub fn append(entry: &JournalEntry) -> Result<(), DpkgError> {
+    state::ensure_state_dir()?;
+    let path = journal_path();
+    let line = serde_json::to_string(entry)
+        .map_err(|e| DpkgError::State(format!("Cannot serialize history entry: {e}")))?;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| {
+            DpkgError::State(format!("Cannot open history journal {}: {e}", path.display()))
+        })?;
+    writeln!(file, "{line}").map_err(|e| {
+        DpkgError::State(format!("Cannot write history journal {}: {e}", path.display()))
+    })
+}
+
+/// Compute the per-package version delta between two `name → version` snapshots.
+pub fn version_changes(
+    before: &HashMap<String, String>,
+    after: &HashMap<String, String>,
+) -> Vec<VersionChange> {
+    let names: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    names
+        .into_iter()
+        .filter(|name| before.get(*name) != after.get(*name))
+        .map(|name| VersionChange {
+            name: name.clone(),
+            before: before.get(name).cloned(),
+            after: after.get(name).cloned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_version_changes() {
+        let before = versions(&[("base", "3-2"), ("git", "2.44.0-1"), ("tlp", "1.6-1")]);
+        let after = versions(&[("base", "3-2"), ("git", "2.45.0-1"), ("steam", "1.0-1")]);
+        let changes = version_changes(&before, &after);
+        assert_eq!(
+            changes,
+            vec![
+                VersionChange {
+                    name: "git".to_string(),
+                    before: Some("2.44.0-1".to_string()),
+                    after: Some("2.45.0-1".to_string()),
+                },
+                VersionChange {
+                    name: "steam".to_string(),
+                    before: None,
+                    after: Some("1.0-1".to_string()),
+                },
+                VersionChange {
+                    name: "tlp".to_string(),
+                    before: Some("1.6-1".to_string()),
+                    after: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_entry_roundtrip() {
+        let entry = JournalEntry {
+            id: 3,
+            timestamp: 1_700_000_000,
+            config_path: "/home/u/.config/dpkg/pkg.conf".to_string(),
+            config_hash: "abc".to_string(),
+            hostname: "desktop".to_string(),
+            plan: SyncPlan {
+                to_install_official: vec!["git".to_string()],
+                to_install_aur: vec![],
+                to_remove: vec!["tlp".to_string()],
+                ..Default::default()
+            },
+            steps: vec![StepRecord {
+                name: "install_official".to_string(),
+                ok: false,
+                error: Some("boom".to_string()),
+                code: Some("DPKG-E012".to_string()),
+            }],
+            versions: vec![],
+        };
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(!line.contains('\n'));
+        let parsed = parse_entries(&format!("{line}\n\n")).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].id, 3);
+        assert_eq!(parsed[0].plan, entry.plan);
+        assert!(!parsed[0].succeeded());
+    }
+
+    #[test]
+    fn test_parse_corrupt_entry() {
+        assert!(parse_entries("{not json}\n").is_err());
+    }
+}
@@ -0,0 +1,95 @@
+pub mod generations;
+pub mod groups;
+pub mod history;
+
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::error::DpkgError;
+use crate::system;
+
+/// Directory holding dpkg's persistent state (history journal, etc.).
+/// Resolution order: `$DPKG_STATE_DIR`, `/var/lib/dpkg` in the `--root` target,
+/// `$XDG_STATE_HOME/dpkg`, `$HOME/.local/state/dpkg`.
+pub fn state_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var("DPKG_STATE_DIR") {
+        return PathBuf::from(dir);
+    }
+    if system::target().root.is_some() {
+        return system::in_root(Path::new("/var/lib/dpkg"));
+    }
+    if let Ok(dir) = std::env::var("XDG_STATE_HOME") {
+        return PathBuf::from(dir).join("dpkg");
+    }
+    let home = std::env::var("HOME").unwrap_or_else(|_| "/root".to_string());
+    PathBuf::from(home).join(".local/state/dpkg")
+}
+
+pub fn ensure_state_dir() -> Result<PathBuf, DpkgError> {
+    let dir = state_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| {
+        DpkgError::State(format!("Cannot create state directory {}: {e}", dir.display()))
+    })?;
+    Ok(dir)
+}
+
+/// Hex-encoded SHA-256 of `data`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Current time as seconds since the Unix epoch.
+pub fn now_unix() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Format Unix seconds as `YYYY-MM-DD HH:MM:SS` (UTC).
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let (hour, minute, second) = (rem / 3600, (rem % 3600) / 60, rem % 60);
+
+    // Civil-from-days (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp_epoch() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+    }
+
+    #[test]
+    fn test_format_timestamp_leap_day() {
+        // 2024-02-29 12:34:56 UTC
+        assert_eq!(format_timestamp(1_709_210_096), "2024-02-29 12:34:56");
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
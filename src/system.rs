@@ -1,38 +1,181 @@
+pub mod escalate;
+pub mod flatpak;
+pub mod lang;
+pub mod progress;
+pub mod simulate;
+pub mod systemctl;
+
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::OnceLock;
 
+use serde::{Deserialize, Serialize};
+
+use crate::aur;
+use crate::config::GroupExclusion;
+use crate::dblock;
 use crate::error::DpkgError;
+use crate::repos;
+
+/// An installed package with its version and install reason.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub version: String,
+    pub explicit: bool,
+}
+
+/// The system dpkg manages: the running one, or an alternate root given with `--root`
+/// for chroot and image builds.
+#[derive(Debug, Clone, Default)]
+pub struct Target {
+    pub root: Option<PathBuf>,
+    pub dbpath: Option<PathBuf>,
+    pub cachedir: Option<PathBuf>,
+    /// Hostname selecting the config's host sections, instead of this machine's
+    pub host: Option<String>,
+}
 
-fn pacman_bin() -> String {
+static TARGET: OnceLock<Target> = OnceLock::new();
+
+/// Set the target from the global options. Only the first call has an effect.
+pub fn set_target(target: Target) {
+    let _ = TARGET.set(target);
+}
+
+pub fn target() -> &'static Target {
+    TARGET.get_or_init(Target::default)
+}
+
+/// `path` as seen from the host: below the target root, if there is one.
+pub fn in_root(path: &Path) -> PathBuf {
+    below_root(target().root.as_deref(), path)
+}
+
+/// Pure computation: `path` below `root`, if there is one.
+pub fn below_root(root: Option<&Path>, path: &Path) -> PathBuf {
+    match root {
+        Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+        None => path.to_path_buf(),
+    }
+}
+
+pub fn pacman_bin() -> String {
     std::env::var("PACMAN").unwrap_or_else(|_| "pacman".to_string())
 }
 
-fn yay_bin() -> String {
+/// pacman, pointed at the target.
+pub fn pacman() -> Command {
+    let mut command = Command::new(pacman_bin());
+    command.args(target_args());
+    command
+}
+
+/// pacman run as root, pointed at the target.
+pub fn root_pacman() -> Command {
+    let mut command = escalate::command(pacman_bin());
+    command.args(target_args());
+    command
+}
+
+fn target_args() -> Vec<OsString> {
+    let target = target();
+    let db_path = (target.root.is_some() || target.dbpath.is_some()).then(dblock::db_path);
+    pacman_target_args(target, &repos::pacman_config(), db_path.as_deref())
+}
+
+/// Pure computation: the pacman options selecting `target`, with the target's
+/// pacman.conf and database directory.
+pub fn pacman_target_args(target: &Target, conf: &Path, db_path: Option<&Path>) -> Vec<OsString> {
+    let mut args = Vec::new();
+    if let Some(root) = &target.root {
+        args.extend(["--root".into(), root.into(), "--config".into(), conf.into()]);
+    }
+    if let Some(db_path) = db_path {
+        args.extend(["--dbpath".into(), db_path.into()]);
+    }
+    if let Some(cachedir) = &target.cachedir {
+        args.extend(["--cachedir".into(), cachedir.into()]);
+    }
+    args
+}
+
+pub fn yay_bin() -> String {
     std::env::var("YAY").unwrap_or_else(|_| "yay".to_string())
 }
 
+/// Repository metadata of a sync-database package.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepoInfo {
+    pub repo: String,
+    pub version: String,
+}
+
+/// Directory pacman keeps downloaded package files in: `--cachedir`, else
+/// `DPKG_CACHE_DIR`, else the target's.
+pub fn pacman_cache_dir() -> PathBuf {
+    if let Some(cachedir) = &target().cachedir {
+        return cachedir.clone();
+    }
+    std::env::var("DPKG_CACHE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| in_root(Path::new("/var/cache/pacman/pkg")))
+}
+
+/// Error for a failed pacman (or yay) transaction: a locked database and conflicts are
+/// told apart from other failures, which become `otherwise`.
+pub fn pacman_error(stderr: &[u8], otherwise: impl FnOnce(String) -> DpkgError) -> DpkgError {
+    let stderr = String::from_utf8_lossy(stderr).trim().to_string();
+    if stderr.contains("unable to lock database") {
+        DpkgError::DatabaseLocked(stderr)
+    } else if stderr.contains("conflicting files")
+        || stderr.contains("are in conflict")
+        || stderr.contains("conflicting dependencies")
+    {
+        DpkgError::TransactionConflict(stderr)
+    } else {
+        otherwise(stderr)
+    }
+}
+
+/// The hostname host sections are selected by: `--host`, else the target's
+/// /etc/hostname under `--root`, else this machine's.
 pub fn get_hostname() -> Result<String, DpkgError> {
+    let target = target();
+    if let Some(host) = &target.host {
+        return Ok(host.clone());
+    }
+    if target.root.is_some() {
+        let path = in_root(Path::new("/etc/hostname"));
+        return std::fs::read_to_string(&path)
+            .ok()
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| {
+                DpkgError::HostnameUnavailable(format!(
+                    "{} is missing or empty; pass the target's hostname with --host",
+                    path.display()
+                ))
+            });
+    }
     hostname::get()
-        .map_err(|e| DpkgError::ConfigParse {
-            line: 0,
-            message: format!("Failed to get hostname: {e}"),
-        })?
+        .map_err(|e| DpkgError::HostnameUnavailable(e.to_string()))?
         .into_string()
-        .map_err(|_| DpkgError::ConfigParse {
-            line: 0,
-            message: "Hostname contains invalid UTF-8".to_string(),
-        })
+        .map_err(|_| DpkgError::HostnameUnavailable("hostname contains invalid UTF-8".to_string()))
 }
 
 pub fn get_explicitly_installed() -> Result<Vec<String>, DpkgError> {
-    let output = Command::new(pacman_bin())
+    let output = pacman()
         .args(["-Qqe"])
         .output()
-        .map_err(|e| DpkgError::InstallFailed(format!("Failed to run pacman: {e}")))?;
+        .map_err(|e| DpkgError::spawn(&pacman_bin(), e))?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(DpkgError::InstallFailed(format!(
+        return Err(DpkgError::QueryFailed(format!(
             "pacman -Qqe failed: {stderr}"
         )));
     }
@@ -44,14 +187,14 @@ pub fn get_explicitly_installed() -> Result<Vec<String>, DpkgError> {
 }
 
 pub fn get_all_installed() -> Result<Vec<String>, DpkgError> {
-    let output = Command::new(pacman_bin())
+    let output = pacman()
         .args(["-Qq"])
         .output()
-        .map_err(|e| DpkgError::InstallFailed(format!("Failed to run pacman: {e}")))?;
+        .map_err(|e| DpkgError::spawn(&pacman_bin(), e))?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(DpkgError::InstallFailed(format!(
+        return Err(DpkgError::QueryFailed(format!(
             "pacman -Qq failed: {stderr}"
         )));
     }
@@ -62,11 +205,47 @@ pub fn get_all_installed() -> Result<Vec<String>, DpkgError> {
         .collect())
 }
 
+/// Map of installed package name → version, from `pacman -Q`.
+pub fn get_installed_versions() -> Result<HashMap<String, String>, DpkgError> {
+    let output = pacman()
+        .args(["-Q"])
+        .output()
+        .map_err(|e| DpkgError::spawn(&pacman_bin(), e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(DpkgError::QueryFailed(format!(
+            "pacman -Q failed: {stderr}"
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, version)| (name.to_string(), version.to_string()))
+        .collect())
+}
+
+/// Every installed package with version and install reason.
+pub fn get_installed_packages() -> Result<Vec<InstalledPackage>, DpkgError> {
+    let explicit: HashSet<String> = get_explicitly_installed()?.into_iter().collect();
+    let mut packages: Vec<InstalledPackage> = get_installed_versions()?
+        .into_iter()
+        .map(|(name, version)| InstalledPackage {
+            explicit: explicit.contains(&name),
+            name,
+            version,
+        })
+        .collect();
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(packages)
+}
+
 pub fn get_orphans() -> Result<Vec<String>, DpkgError> {
-    let output = Command::new(pacman_bin())
+    let output = pacman()
         .args(["-Qqdt"])
         .output()
-        .map_err(|e| DpkgError::InstallFailed(format!("Failed to run pacman: {e}")))?;
+        .map_err(|e| DpkgError::spawn(&pacman_bin(), e))?;
 
     // pacman -Qqdt returns exit code 1 when there are no orphans
     if !output.status.success() {
@@ -74,7 +253,7 @@ pub fn get_orphans() -> Result<Vec<String>, DpkgError> {
         if stderr.is_empty() || output.status.code() == Some(1) {
             return Ok(Vec::new());
         }
-        return Err(DpkgError::InstallFailed(format!(
+        return Err(DpkgError::QueryFailed(format!(
             "pacman -Qqdt failed: {stderr}"
         )));
     }
@@ -87,27 +266,26 @@ pub fn get_orphans() -> Result<Vec<String>, DpkgError> {
 
 pub fn mark_all_as_deps(verbose: bool) -> Result<(), DpkgError> {
     let explicitly = get_explicitly_installed()?;
-    if explicitly.is_empty() {
+    mark_as_deps(&explicitly, verbose)
+}
+
+pub fn mark_as_deps(packages: &[String], verbose: bool) -> Result<(), DpkgError> {
+    if packages.is_empty() {
         return Ok(());
     }
 
     if verbose {
-        eprintln!("Marking {} packages as dependencies...", explicitly.len());
+        eprintln!("Marking {} packages as dependencies...", packages.len());
     }
 
-    let output = Command::new("sudo")
-        .arg(pacman_bin())
-        .arg("-D")
-        .arg("--asdeps")
-        .args(&explicitly)
-        .output()
-        .map_err(|e| DpkgError::PermissionDenied(format!("Failed to run sudo pacman: {e}")))?;
+    let mut command = root_pacman();
+    command.args(["-D", "--asdeps"]).args(packages);
+    let output = command.output().map_err(|e| escalate::spawn_error(&command, e))?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(DpkgError::PermissionDenied(format!(
-            "Failed to mark packages as dependencies: {}", stderr.trim()
-        )));
+        return Err(pacman_error(&output.stderr, |stderr| {
+            DpkgError::PermissionDenied(format!("Failed to mark packages as dependencies: {stderr}"))
+        }));
     }
 
     Ok(())
@@ -125,19 +303,14 @@ pub fn mark_as_explicit(packages: &[String], verbose: bool) -> Result<(), DpkgEr
         );
     }
 
-    let output = Command::new("sudo")
-        .arg(pacman_bin())
-        .arg("-D")
-        .arg("--asexplicit")
-        .args(packages)
-        .output()
-        .map_err(|e| DpkgError::PermissionDenied(format!("Failed to run sudo pacman: {e}")))?;
+    let mut command = root_pacman();
+    command.args(["-D", "--asexplicit"]).args(packages);
+    let output = command.output().map_err(|e| escalate::spawn_error(&command, e))?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(DpkgError::PermissionDenied(format!(
-            "Failed to mark packages as explicit: {}", stderr.trim()
-        )));
+        return Err(pacman_error(&output.stderr, |stderr| {
+            DpkgError::PermissionDenied(format!("Failed to mark packages as explicit: {stderr}"))
+        }));
     }
 
     Ok(())
@@ -149,10 +322,10 @@ pub fn remove_orphans(verbose: bool) -> Result<(), DpkgError> {
     }
 
     // Get true orphans (unrequired deps) and remove them
-    let orphans_output = Command::new(pacman_bin())
+    let orphans_output = pacman()
         .args(["-Qqdt"])
         .output()
-        .map_err(|e| DpkgError::InstallFailed(format!("Failed to run pacman: {e}")))?;
+        .map_err(|e| DpkgError::spawn(&pacman_bin(), e))?;
 
     if !orphans_output.status.success() || orphans_output.stdout.is_empty() {
         return Ok(());
@@ -167,22 +340,119 @@ pub fn remove_orphans(verbose: bool) -> Result<(), DpkgError> {
         return Ok(());
     }
 
-    let output = Command::new("sudo")
-        .arg(pacman_bin())
-        .args(["-Rns", "--noconfirm"])
-        .args(&orphan_list)
-        .output()
-        .map_err(|e| DpkgError::InstallFailed(format!("Failed to remove orphans: {e}")))?;
+    let mut command = root_pacman();
+    command.args(["-Rns", "--noconfirm"]).args(&orphan_list);
+    let output = progress::run(&mut command).map_err(|e| escalate::spawn_error(&command, e))?;
 
     if !output.status.success() {
-        return Err(DpkgError::InstallFailed(
-            String::from_utf8_lossy(&output.stderr).trim().to_string(),
-        ));
+        return Err(pacman_error(&output.stderr, DpkgError::RemoveFailed));
     }
 
     Ok(())
 }
 
+/// Remove exactly the given packages (no recursive dependency removal).
+pub fn remove_packages(packages: &[String], verbose: bool) -> Result<(), DpkgError> {
+    if packages.is_empty() {
+        return Ok(());
+    }
+
+    if verbose {
+        eprintln!("Removing {} packages...", packages.len());
+    }
+
+    let mut command = root_pacman();
+    command.args(["-Rn", "--noconfirm"]).args(packages);
+    let output = progress::run(&mut command).map_err(|e| escalate::spawn_error(&command, e))?;
+
+    if !output.status.success() {
+        return Err(pacman_error(&output.stderr, DpkgError::RemoveFailed));
+    }
+
+    Ok(())
+}
+
+/// Refresh the sync databases with `pacman -Sy`.
+pub fn refresh_databases(verbose: bool) -> Result<(), DpkgError> {
+    if verbose {
+        eprintln!("Refreshing package databases...");
+    }
+
+    let mut command = root_pacman();
+    command.arg("-Sy");
+    let output = progress::run(&mut command).map_err(|e| escalate::spawn_error(&command, e))?;
+
+    if !output.status.success() {
+        return Err(pacman_error(&output.stderr, |stderr| {
+            DpkgError::NetworkError(format!("pacman -Sy failed: {stderr}"))
+        }));
+    }
+
+    Ok(())
+}
+
+/// Install package archives with `pacman -U`.
+pub fn install_files(files: &[PathBuf], verbose: bool) -> Result<(), DpkgError> {
+    install_package_files(files, false, verbose)
+}
+
+/// Install package archives with `pacman -U`, marked as dependencies with `asdeps`.
+pub fn install_package_files(files: &[PathBuf], asdeps: bool, verbose: bool) -> Result<(), DpkgError> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    if verbose {
+        eprintln!("Installing {} package files...", files.len());
+    }
+
+    let mut command = root_pacman();
+    command.args(["-U", "--noconfirm"]);
+    if asdeps {
+        command.arg("--asdeps");
+    }
+    command.args(files);
+    let output = progress::run(&mut command).map_err(|e| escalate::spawn_error(&command, e))?;
+
+    if !output.status.success() {
+        return Err(pacman_error(&output.stderr, DpkgError::InstallFailed));
+    }
+
+    Ok(())
+}
+
+/// List package archives in a cache directory (signatures and partial downloads excluded).
+/// A missing directory yields an empty list.
+pub fn list_cached_packages(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.contains(".pkg.tar") && !n.ends_with(".sig") && !n.ends_with(".part"))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Find the archive for `name` at exactly `version` among `files`.
+/// Archive names follow `<name>-<pkgver>-<pkgrel>-<arch>.pkg.tar.<ext>`.
+pub fn find_package_file<'a>(files: &'a [PathBuf], name: &str, version: &str) -> Option<&'a PathBuf> {
+    let prefix = format!("{name}-{version}-");
+    files.iter().find(|path| {
+        path.file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix(&prefix))
+            .and_then(|rest| rest.split_once(".pkg.tar"))
+            .is_some_and(|(arch, _)| !arch.is_empty() && !arch.contains('-'))
+    })
+}
+
 pub fn install_official(packages: &[String], verbose: bool) -> Result<(), DpkgError> {
     if packages.is_empty() {
         return Ok(());
@@ -192,17 +462,12 @@ pub fn install_official(packages: &[String], verbose: bool) -> Result<(), DpkgEr
         eprintln!("Installing {} official packages...", packages.len());
     }
 
-    let output = Command::new("sudo")
-        .arg(pacman_bin())
-        .args(["-S", "--needed", "--noconfirm"])
-        .args(packages)
-        .output()
-        .map_err(|e| DpkgError::InstallFailed(format!("Failed to run pacman -S: {e}")))?;
+    let mut command = root_pacman();
+    command.args(["-S", "--needed", "--noconfirm"]).args(packages);
+    let output = progress::run(&mut command).map_err(|e| escalate::spawn_error(&command, e))?;
 
     if !output.status.success() {
-        return Err(DpkgError::InstallFailed(
-            String::from_utf8_lossy(&output.stderr).trim().to_string(),
-        ));
+        return Err(pacman_error(&output.stderr, DpkgError::InstallFailed));
     }
 
     Ok(())
@@ -217,31 +482,64 @@ pub fn install_aur(packages: &[String], verbose: bool) -> Result<(), DpkgError>
         eprintln!("Installing {} AUR packages...", packages.len());
     }
 
-    let output = Command::new(yay_bin())
+    // As root, the build user only builds; root installs what was built. yay would
+    // install into the host, so a target gets the built archives through pacman -U
+    if escalate::is_root() || target().root.is_some() {
+        return aur::install(packages, false, verbose);
+    }
+    let mut command = escalate::unprivileged(yay_bin())?;
+    command
+        .args(escalate::yay_args())
         .args(["-S", "--needed", "--noconfirm"])
-        .args(packages)
-        .output()
-        .map_err(|e| DpkgError::InstallFailed(format!("Failed to run yay -S: {e}")))?;
+        .args(packages);
+    let output = progress::run(&mut command).map_err(|e| escalate::spawn_error(&command, e))?;
 
     if !output.status.success() {
-        return Err(DpkgError::InstallFailed(
-            String::from_utf8_lossy(&output.stderr).trim().to_string(),
-        ));
+        return Err(pacman_error(&output.stderr, DpkgError::InstallFailed));
     }
 
     Ok(())
 }
 
+/// Install optional dependencies with `--asdeps`, from the repositories or the AUR.
+pub fn install_optdeps(repo: &[String], aur: &[String], verbose: bool) -> Result<(), DpkgError> {
+    if verbose {
+        eprintln!("Installing {} optional dependencies...", repo.len() + aur.len());
+    }
+    if !repo.is_empty() {
+        let mut command = root_pacman();
+        command.args(["-S", "--needed", "--noconfirm", "--asdeps"]).args(repo);
+        let output = progress::run(&mut command).map_err(|e| escalate::spawn_error(&command, e))?;
+        if !output.status.success() {
+            return Err(pacman_error(&output.stderr, DpkgError::InstallFailed));
+        }
+    }
+    if !aur.is_empty() && escalate::is_root() {
+        aur::install(aur, true, verbose)?;
+    } else if !aur.is_empty() {
+        let mut command = escalate::unprivileged(yay_bin())?;
+        command
+            .args(escalate::yay_args())
+            .args(["-S", "--needed", "--noconfirm", "--asdeps"])
+            .args(aur);
+        let output = progress::run(&mut command).map_err(|e| escalate::spawn_error(&command, e))?;
+        if !output.status.success() {
+            return Err(pacman_error(&output.stderr, DpkgError::InstallFailed));
+        }
+    }
+    Ok(())
+}
+
 /// Get all known pacman group names.
 fn get_group_names() -> Result<HashSet<String>, DpkgError> {
-    let output = Command::new(pacman_bin())
+    let output = pacman()
         .args(["-Sg"])
         .output()
-        .map_err(|e| DpkgError::InstallFailed(format!("Failed to run pacman -Sg: {e}")))?;
+        .map_err(|e| DpkgError::spawn(&pacman_bin(), e))?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(DpkgError::InstallFailed(format!(
+        return Err(DpkgError::QueryFailed(format!(
             "pacman -Sg failed: {stderr}"
         )));
     }
@@ -254,10 +552,10 @@ fn get_group_names() -> Result<HashSet<String>, DpkgError> {
 
 /// Get member packages of a specific group.
 fn get_group_member_packages(group: &str) -> Result<Vec<String>, DpkgError> {
-    let output = Command::new(pacman_bin())
+    let output = pacman()
         .args(["-Sgq", group])
         .output()
-        .map_err(|e| DpkgError::InstallFailed(format!("Failed to run pacman -Sgq: {e}")))?;
+        .map_err(|e| DpkgError::spawn(&pacman_bin(), e))?;
 
     if !output.status.success() {
         return Ok(Vec::new());
@@ -286,16 +584,21 @@ pub fn get_group_members(packages: &[String]) -> Result<HashMap<String, Vec<Stri
     Ok(result)
 }
 
-/// Expand group names in a package list to their member packages.
-/// Non-group names pass through unchanged.
+/// Expand group names in a package list to their member packages, leaving out
+/// excluded members. Non-group names pass through unchanged.
 pub fn expand_package_groups(
     packages: &[String],
     groups: &HashMap<String, Vec<String>>,
+    exclusions: &[GroupExclusion],
 ) -> Vec<String> {
     let mut result = Vec::new();
     for pkg in packages {
         if let Some(members) = groups.get(pkg.as_str()) {
-            result.extend(members.iter().cloned());
+            let excluded = exclusions
+                .iter()
+                .find(|e| e.group == *pkg)
+                .map_or(&[][..], |e| e.members.as_slice());
+            result.extend(members.iter().filter(|m| !excluded.contains(m)).cloned());
         } else {
             result.push(pkg.clone());
         }
@@ -303,6 +606,211 @@ pub fn expand_package_groups(
     result
 }
 
+/// Repository and version for each of `packages` found in the sync databases.
+/// Packages unknown to pacman are simply absent from the result.
+pub fn get_repo_info(packages: &[String]) -> Result<HashMap<String, RepoInfo>, DpkgError> {
+    if packages.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    // pacman -Si exits non-zero if any target is missing but still prints the rest
+    let output = pacman()
+        .arg("-Si")
+        .args(packages)
+        .output()
+        .map_err(|e| DpkgError::spawn(&pacman_bin(), e))?;
+
+    Ok(parse_package_info(&String::from_utf8_lossy(&output.stdout))
+        .into_iter()
+        .filter_map(|fields| {
+            Some((
+                fields.get("Name")?.clone(),
+                RepoInfo {
+                    repo: fields.get("Repository")?.clone(),
+                    version: fields.get("Version")?.clone(),
+                },
+            ))
+        })
+        .collect())
+}
+
+/// `pacman -Qi` fields of an installed package, or `None` if it is not installed.
+pub fn get_installed_info(package: &str) -> Result<Option<HashMap<String, String>>, DpkgError> {
+    let output = pacman()
+        .args(["-Qi", package])
+        .output()
+        .map_err(|e| DpkgError::spawn(&pacman_bin(), e))?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(parse_package_info(&String::from_utf8_lossy(&output.stdout))
+        .into_iter()
+        .next())
+}
+
+/// `Depends On` of an installed package, or `None` if it is not installed.
+pub fn get_installed_depends(package: &str) -> Result<Option<Vec<String>>, DpkgError> {
+    Ok(get_installed_info(package)?
+        .map(|fields| parse_depends(fields.get("Depends On").map_or("None", |s| s.as_str()))))
+}
+
+/// `pacman -Si` records for `packages`, falling back to `pacman -Qi` for packages only
+/// installed locally (e.g. from the AUR). Packages pacman knows nothing about are skipped.
+fn query_package_info(packages: &[String]) -> Result<Vec<HashMap<String, String>>, DpkgError> {
+    let mut records: Vec<HashMap<String, String>> = Vec::new();
+    for flag in ["-Si", "-Qi"] {
+        let missing: Vec<&String> = packages
+            .iter()
+            .filter(|p| !records.iter().any(|r| r.get("Name") == Some(*p)))
+            .collect();
+        if missing.is_empty() {
+            break;
+        }
+        // Exits non-zero if any target is unknown but still prints the rest
+        let output = pacman()
+            .arg(flag)
+            .args(missing)
+            .output()
+            .map_err(|e| DpkgError::spawn(&pacman_bin(), e))?;
+        records.extend(parse_package_info(&String::from_utf8_lossy(&output.stdout)));
+    }
+    Ok(records)
+}
+
+/// Optional dependency names of each of `packages`.
+pub fn get_optdepends(packages: &[String]) -> Result<HashMap<String, Vec<String>>, DpkgError> {
+    Ok(package_field(query_package_info(packages)?, "Optional Deps", parse_optdepends))
+}
+
+/// Hard dependency names of each of `packages`, without version constraints.
+pub fn get_depends(packages: &[String]) -> Result<HashMap<String, Vec<String>>, DpkgError> {
+    Ok(package_field(query_package_info(packages)?, "Depends On", parse_depends))
+}
+
+/// Hard dependencies of AUR packages from `yay -Si`, which queries the AUR for packages
+/// not in the repositories.
+pub fn get_aur_depends(packages: &[String]) -> Result<HashMap<String, Vec<String>>, DpkgError> {
+    if packages.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let output = Command::new(yay_bin())
+        .arg("-Si")
+        .args(packages)
+        .output()
+        .map_err(|e| DpkgError::spawn(&yay_bin(), e))?;
+    let records = parse_package_info(&String::from_utf8_lossy(&output.stdout));
+    Ok(package_field(records, "Depends On", parse_depends))
+}
+
+/// One parsed field per package name; the first record of a name wins.
+fn package_field(
+    records: Vec<HashMap<String, String>>,
+    field: &str,
+    parse: fn(&str) -> Vec<String>,
+) -> HashMap<String, Vec<String>> {
+    let mut result = HashMap::new();
+    for fields in records {
+        if let Some(name) = fields.get("Name") {
+            let values = parse(fields.get(field).map_or("None", |s| s.as_str()));
+            result.entry(name.clone()).or_insert(values);
+        }
+    }
+    result
+}
+
+/// Names from an `Optional Deps` field: one `name[<constraint>]: description [installed]`
+/// per line, or `None`.
+fn parse_optdepends(field: &str) -> Vec<String> {
+    field
+        .lines()
+        .filter_map(|line| {
+            let name = line
+                .split([':', '<', '>', '='])
+                .next()?
+                .split_whitespace()
+                .next()?;
+            (name != "None").then(|| name.to_string())
+        })
+        .collect()
+}
+
+/// Split a `Depends On` field, dropping version constraints; pacman prints `None` for
+/// no dependencies.
+pub fn parse_depends(field: &str) -> Vec<String> {
+    field
+        .split_whitespace()
+        .filter(|dep| *dep != "None")
+        .filter_map(|dep| dep.split(['<', '>', '=']).next())
+        .map(|dep| dep.to_string())
+        .collect()
+}
+
+/// Parse `pacman -Si`/`-Qi` output into one field map per package.
+/// Continuation lines (indented values) are appended to the previous field.
+pub fn parse_package_info(text: &str) -> Vec<HashMap<String, String>> {
+    let mut records = Vec::new();
+    let mut current: HashMap<String, String> = HashMap::new();
+    let mut last_key: Option<String> = None;
+
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            if !current.is_empty() {
+                records.push(std::mem::take(&mut current));
+            }
+            last_key = None;
+            continue;
+        }
+        match line.split_once(" : ") {
+            Some((key, value)) if !line.starts_with(' ') => {
+                let key = key.trim().to_string();
+                current.insert(key.clone(), value.trim().to_string());
+                last_key = Some(key);
+            }
+            _ => {
+                if let Some(value) = last_key.as_ref().and_then(|k| current.get_mut(k)) {
+                    value.push('\n');
+                    value.push_str(line.trim());
+                }
+            }
+        }
+    }
+    if !current.is_empty() {
+        records.push(current);
+    }
+    records
+}
+
+/// Cache directory of the user packages are built as: the build user's when dpkg
+/// runs as root, else the caller's.
+pub fn build_cache_dir() -> PathBuf {
+    if let Some(home) = escalate::build_home() {
+        return home.join(".cache");
+    }
+    std::env::var("XDG_CACHE_HOME").map(PathBuf::from).unwrap_or_else(|_| {
+        let home = std::env::var("HOME").unwrap_or_else(|_| "/root".to_string());
+        PathBuf::from(home).join(".cache")
+    })
+}
+
+/// Directory yay clones an AUR package's PKGBUILD repository into.
+pub fn aur_clone_dir(package: &str) -> PathBuf {
+    build_cache_dir().join("yay").join(package)
+}
+
+/// HEAD commit of the local clone of an AUR package, if yay has built it before.
+pub fn aur_commit(package: &str) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(aur_clone_dir(package))
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
 pub fn check_yay_installed() -> Result<(), DpkgError> {
     let yay = yay_bin();
     let result = Command::new("which").arg(&yay).output();
@@ -312,3 +820,198 @@ pub fn check_yay_installed() -> Result<(), DpkgError> {
         _ => Err(DpkgError::YayNotFound),
     }
 }
+
+/// Compare two `[epoch:]pkgver[-pkgrel]` versions the way pacman's `vercmp` does.
+pub fn vercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    fn split(v: &str) -> (u64, &str, Option<&str>) {
+        let (epoch, rest) = match v.split_once(':') {
+            Some((epoch, rest)) if epoch.bytes().all(|c| c.is_ascii_digit()) => (epoch, rest),
+            _ => ("0", v),
+        };
+        let (version, release) = match rest.rsplit_once('-') {
+            Some((version, release)) => (version, Some(release)),
+            None => (rest, None),
+        };
+        (epoch.parse::<u64>().unwrap_or(0), version, release)
+    }
+    let (epoch_a, version_a, release_a) = split(a);
+    let (epoch_b, version_b, release_b) = split(b);
+
+    epoch_a
+        .cmp(&epoch_b)
+        .then_with(|| rpmvercmp(version_a, version_b))
+        .then_with(|| match (release_a, release_b) {
+            (Some(ra), Some(rb)) => rpmvercmp(ra, rb),
+            _ => Ordering::Equal,
+        })
+}
+
+/// Segment-wise comparison of one version component: digit runs compare numerically,
+/// letter runs lexically, and a digit run is newer than a letter run.
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (mut one, mut two) = (a.as_bytes(), b.as_bytes());
+
+    while !one.is_empty() && !two.is_empty() {
+        let sep_one = one.iter().take_while(|c| !c.is_ascii_alphanumeric()).count();
+        let sep_two = two.iter().take_while(|c| !c.is_ascii_alphanumeric()).count();
+        (one, two) = (&one[sep_one..], &two[sep_two..]);
+        if one.is_empty() || two.is_empty() {
+            break;
+        }
+        if sep_one != sep_two {
+            return sep_one.cmp(&sep_two);
+        }
+
+        let numeric = one[0].is_ascii_digit();
+        let in_segment = |c: &u8| if numeric { c.is_ascii_digit() } else { c.is_ascii_alphabetic() };
+        let len_one = one.iter().take_while(|c| in_segment(c)).count();
+        let len_two = two.iter().take_while(|c| in_segment(c)).count();
+        if len_two == 0 {
+            // Segments of different types: numbers are newer
+            return if numeric { Ordering::Greater } else { Ordering::Less };
+        }
+
+        let (seg_one, seg_two) = (&one[..len_one], &two[..len_two]);
+        let order = if numeric {
+            let trim = |s: &[u8]| -> usize { s.iter().take_while(|c| **c == b'0').count() };
+            let (seg_one, seg_two) = (&seg_one[trim(seg_one)..], &seg_two[trim(seg_two)..]);
+            seg_one.len().cmp(&seg_two.len()).then_with(|| seg_one.cmp(seg_two))
+        } else {
+            seg_one.cmp(seg_two)
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+        (one, two) = (&one[len_one..], &two[len_two..]);
+    }
+
+    if one.is_empty() && two.is_empty() {
+        return Ordering::Equal;
+    }
+    // A trailing letter segment (e.g. `1.0a` vs `1.0`) is older, anything else newer
+    if (one.is_empty() && !two.first().is_some_and(u8::is_ascii_alphabetic))
+        || one.first().is_some_and(u8::is_ascii_alphabetic)
+    {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pacman_error_classification() {
+        let err = pacman_error(b"error: failed to init transaction (unable to lock database)\n", DpkgError::InstallFailed);
+        assert!(matches!(err, DpkgError::DatabaseLocked(_)));
+        let err = pacman_error(b"error: failed to commit transaction (conflicting files)\n", DpkgError::InstallFailed);
+        assert!(matches!(err, DpkgError::TransactionConflict(_)));
+        let err = pacman_error(b"error: target not found: nope\n", DpkgError::RemoveFailed);
+        assert!(matches!(err, DpkgError::RemoveFailed(ref m) if m == "error: target not found: nope"));
+    }
+
+    #[test]
+    fn test_pacman_target_args() {
+        let args = |target: &Target, db_path: Option<&str>| -> Vec<String> {
+            pacman_target_args(target, Path::new("/mnt/etc/pacman.conf"), db_path.map(Path::new))
+                .into_iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect()
+        };
+        assert!(args(&Target::default(), None).is_empty());
+        let target = Target {
+            root: Some(PathBuf::from("/mnt")),
+            cachedir: Some(PathBuf::from("/srv/cache")),
+            ..Target::default()
+        };
+        assert_eq!(
+            args(&target, Some("/mnt/var/lib/pacman/")),
+            [
+                "--root", "/mnt", "--config", "/mnt/etc/pacman.conf",
+                "--dbpath", "/mnt/var/lib/pacman/", "--cachedir", "/srv/cache"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vercmp() {
+        assert_eq!(vercmp("1.0-1", "1.0-1"), Ordering::Equal);
+        assert_eq!(vercmp("1.0-1", "1.0-2"), Ordering::Less);
+        assert_eq!(vercmp("1.10-1", "1.9-1"), Ordering::Greater);
+        assert_eq!(vercmp("1:0.1-1", "2.0-1"), Ordering::Greater);
+        assert_eq!(vercmp("1.0a-1", "1.0-1"), Ordering::Less);
+        assert_eq!(vercmp("1.0.1-1", "1.0-1"), Ordering::Greater);
+        assert_eq!(vercmp("6.6.30-1", "6.6.30-1.1"), Ordering::Less);
+        assert_eq!(vercmp("2.45.0-1", "1.0-1"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_parse_optdepends() {
+        let field = "yt-dlp: for video-sharing websites playback [installed]\nvapoursynth>=60: for VapourSynth filters\nlua52";
+        assert_eq!(parse_optdepends(field), vec!["yt-dlp", "vapoursynth", "lua52"]);
+        assert!(parse_optdepends("None").is_empty());
+    }
+
+    #[test]
+    fn test_parse_depends() {
+        assert_eq!(parse_depends("base  git\nsteam"), vec!["base", "git", "steam"]);
+        assert_eq!(parse_depends("glibc>=2.39 zlib"), vec!["glibc", "zlib"]);
+        assert!(parse_depends("None").is_empty());
+    }
+
+    #[test]
+    fn test_parse_package_info_multiple_records() {
+        let text = "\
+Repository      : core
+Name            : base
+Version         : 3-2
+Depends On      : filesystem  gcc-libs
+
+Repository      : extra
+Name            : mpv
+Version         : 1:0.38.0-1
+Optional Deps   : yt-dlp: for video-sharing websites playback
+                  vapoursynth: for VapourSynth filters
+";
+        let records = parse_package_info(text);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["Name"], "base");
+        assert_eq!(records[1]["Version"], "1:0.38.0-1");
+        assert_eq!(
+            records[1]["Optional Deps"],
+            "yt-dlp: for video-sharing websites playback\nvapoursynth: for VapourSynth filters"
+        );
+    }
+
+    #[test]
+    fn test_find_package_file_exact_version() {
+        let files: Vec<PathBuf> = [
+            "/c/git-2.44.0-1-x86_64.pkg.tar.zst",
+            "/c/git-lfs-3.5.1-1-x86_64.pkg.tar.zst",
+            "/c/mpv-1:0.38.0-1-x86_64.pkg.tar.zst",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+        assert_eq!(
+            find_package_file(&files, "git", "2.44.0-1"),
+            Some(&files[0])
+        );
+        assert_eq!(
+            find_package_file(&files, "mpv", "1:0.38.0-1"),
+            Some(&files[2])
+        );
+        assert_eq!(find_package_file(&files, "git", "2.45.0-1"), None);
+        // `git-lfs-3.5.1-1` must not match package `git` at version `lfs-3.5.1`
+        assert_eq!(find_package_file(&files, "git", "lfs-3.5.1"), None);
+    }
+}
@@ -0,0 +1,213 @@
+use std::ffi::OsStr;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::config::Escalation;
+use crate::error::DpkgError;
+
+/// The method in use, never `Auto`.
+static METHOD: OnceLock<Escalation> = OnceLock::new();
+
+/// `--aur-user` or `aur-user:`, used when dpkg runs as root.
+static BUILD_USER: OnceLock<Option<String>> = OnceLock::new();
+
+/// Tried in this order when the method is `auto`.
+const CANDIDATES: [Escalation; 4] = [
+    Escalation::Sudo,
+    Escalation::Doas,
+    Escalation::Run0,
+    Escalation::Pkexec,
+];
+
+/// Choose the escalation method: `DPKG_ESCALATE` beats the config's `escalate:` line,
+/// and without either it is detected. Only the first call has an effect.
+pub fn init(configured: Option<Escalation>) {
+    let _ = METHOD.set(resolve(env_method().or(configured).unwrap_or(Escalation::Auto)));
+}
+
+/// Set the user AUR packages and local PKGBUILDs are built as when dpkg runs as root.
+/// Only the first call has an effect.
+pub fn set_build_user(user: Option<String>) {
+    let _ = BUILD_USER.set(user);
+}
+
+/// The configured build user, else the user who called sudo or doas.
+fn build_user() -> Option<String> {
+    BUILD_USER.get().cloned().flatten().or_else(|| {
+        ["SUDO_USER", "DOAS_USER"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|user| !user.is_empty() && user != "root")
+    })
+}
+
+/// Whether makepkg and yay can run: always without root, else only as a build user.
+pub fn can_build() -> bool {
+    !is_root() || build_user().is_some()
+}
+
+fn method() -> &'static Escalation {
+    METHOD.get_or_init(|| resolve(env_method().unwrap_or(Escalation::Auto)))
+}
+
+fn env_method() -> Option<Escalation> {
+    std::env::var("DPKG_ESCALATE").ok().and_then(|v| Escalation::parse(&v))
+}
+
+fn resolve(method: Escalation) -> Escalation {
+    match method {
+        Escalation::Auto => detect(is_root(), |program| find_in_path(program).is_some()),
+        method => method,
+    }
+}
+
+/// Pure computation: nothing as root, otherwise the first installed candidate. Falls
+/// back to sudo so the error names the usual tool.
+pub fn detect(root: bool, installed: impl Fn(&str) -> bool) -> Escalation {
+    if root {
+        return Escalation::None;
+    }
+    CANDIDATES
+        .into_iter()
+        .find(|candidate| installed(&candidate.to_string()))
+        .unwrap_or(Escalation::Sudo)
+}
+
+pub fn is_root() -> bool {
+    std::fs::metadata("/proc/self").is_ok_and(|m| m.uid() == 0)
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.metadata().is_ok_and(|m| m.is_file() && m.mode() & 0o111 != 0))
+}
+
+/// The command line that runs a program as root under `method`, without the program.
+fn prefix(method: &Escalation) -> Vec<String> {
+    match method {
+        Escalation::Auto | Escalation::None => Vec::new(),
+        Escalation::Custom(words) => words.clone(),
+        named => vec![named.to_string()],
+    }
+}
+
+/// `program` run as root.
+pub fn command(program: impl AsRef<OsStr>) -> Command {
+    match prefix(method()).split_first() {
+        Some((launcher, args)) => {
+            let mut command = Command::new(launcher);
+            command.args(args).arg(program);
+            command
+        }
+        None => Command::new(program),
+    }
+}
+
+/// Error for a command from `command` or `unprivileged` that could not be started,
+/// naming the program actually launched.
+pub fn spawn_error(command: &Command, e: std::io::Error) -> DpkgError {
+    DpkgError::spawn(&command.get_program().to_string_lossy(), e)
+}
+
+/// Ask for credentials once, before a sync changes anything, so no password prompt
+/// appears halfway through.
+pub fn validate() -> Result<(), DpkgError> {
+    let mut command = match method() {
+        Escalation::None => return Ok(()),
+        Escalation::Sudo => {
+            let mut sudo = Command::new("sudo");
+            sudo.arg("-v");
+            sudo
+        }
+        _ => command("true"),
+    };
+    let status = command.status().map_err(|e| spawn_error(&command, e))?;
+    if !status.success() {
+        return Err(DpkgError::PermissionDenied(format!(
+            "Could not obtain root privileges through {}; choose another method with escalate:<method> or DPKG_ESCALATE",
+            method()
+        )));
+    }
+    Ok(())
+}
+
+/// yay escalates by itself; tell it to use the same method.
+pub fn yay_args() -> Vec<String> {
+    match method() {
+        Escalation::Auto | Escalation::None | Escalation::Sudo => Vec::new(),
+        Escalation::Custom(words) => {
+            let mut args = vec!["--sudo".to_string(), words[0].clone()];
+            if words.len() > 1 {
+                args.extend(["--sudoflags".to_string(), words[1..].join(" ")]);
+            }
+            args
+        }
+        named => vec!["--sudo".to_string(), named.to_string()],
+    }
+}
+
+/// `program` run without root. makepkg and yay refuse to run as root, so when dpkg is
+/// root they run through `runuser` as the build user.
+pub fn unprivileged(program: impl AsRef<OsStr>) -> Result<Command, DpkgError> {
+    if !is_root() {
+        return Ok(Command::new(program));
+    }
+    let user = build_user().ok_or_else(|| {
+        DpkgError::PermissionDenied(format!(
+            "{} cannot run as root; set a build user with --aur-user or aur-user:<name>, or run dpkg through sudo or doas from your user account",
+            program.as_ref().to_string_lossy()
+        ))
+    })?;
+    let mut command = Command::new("runuser");
+    command.args(["-u", &user, "--"]).arg(program);
+    // runuser keeps root's environment; caches and configs belong in the user's home
+    if let Some(home) = build_home() {
+        command.env("HOME", home);
+    }
+    Ok(command)
+}
+
+/// Home directory of the user AUR packages and local PKGBUILDs are built as, when
+/// dpkg runs as root.
+pub fn build_home() -> Option<PathBuf> {
+    if !is_root() {
+        return None;
+    }
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd_home(&passwd, &build_user()?)
+}
+
+/// Home directory of `user` in /etc/passwd content.
+fn passwd_home(passwd: &str, user: &str) -> Option<PathBuf> {
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        (fields.len() >= 6 && fields[0] == user).then(|| PathBuf::from(fields[5]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(true, |_| true), Escalation::None);
+        assert_eq!(detect(false, |p| p == "doas" || p == "run0"), Escalation::Doas);
+        assert_eq!(detect(false, |_| false), Escalation::Sudo);
+        assert_eq!(
+            prefix(&Escalation::Custom(vec!["sudo".to_string(), "-A".to_string()])),
+            vec!["sudo", "-A"]
+        );
+        assert!(prefix(&Escalation::None).is_empty());
+    }
+
+    #[test]
+    fn test_passwd_home() {
+        let passwd = "root:x:0:0::/root:/bin/bash\nalice:x:1000:1000:Alice:/home/alice:/bin/zsh\n";
+        assert_eq!(passwd_home(passwd, "alice"), Some(PathBuf::from("/home/alice")));
+        assert_eq!(passwd_home(passwd, "bob"), None);
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::process::Command;
+
+use crate::config::FlatpakApp;
+use crate::error::DpkgError;
+use crate::system::escalate;
+
+/// Remote used for apps declared without one.
+pub const DEFAULT_REMOTE: &str = "flathub";
+
+fn flatpak_bin() -> String {
+    std::env::var("FLATPAK").unwrap_or_else(|_| "flatpak".to_string())
+}
+
+fn scope_flag(user: bool) -> &'static str {
+    if user { "--user" } else { "--system" }
+}
+
+/// Whether the flatpak CLI is available at all.
+pub fn is_available() -> bool {
+    Command::new(flatpak_bin())
+        .arg("--version")
+        .output()
+        .is_ok_and(|o| o.status.success())
+}
+
+/// Application IDs installed in the system (`user = false`) or user installation.
+pub fn list_installed(user: bool) -> Result<Vec<String>, DpkgError> {
+    let output = Command::new(flatpak_bin())
+        .args(["list", "--app", "--columns=application", scope_flag(user)])
+        .output()
+        .map_err(|e| DpkgError::spawn(&flatpak_bin(), e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(DpkgError::QueryFailed(format!(
+            "flatpak list failed: {}",
+            stderr.trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect())
+}
+
+/// Install apps, one `flatpak install` per (installation, remote) pair.
+/// System installations go through the configured escalation method, like pacman.
+pub fn install(apps: &[FlatpakApp], verbose: bool) -> Result<(), DpkgError> {
+    let mut batches: BTreeMap<(bool, &str), Vec<&str>> = BTreeMap::new();
+    for app in apps {
+        let remote = app.remote.as_deref().unwrap_or(DEFAULT_REMOTE);
+        batches.entry((app.user, remote)).or_default().push(&app.id);
+    }
+
+    for ((user, remote), ids) in batches {
+        if verbose {
+            eprintln!("Installing {} Flatpak apps from {remote}...", ids.len());
+        }
+        let mut command = flatpak_command(user);
+        command
+            .args(["install", "--noninteractive", scope_flag(user), remote])
+            .args(&ids);
+        run(command, "flatpak install", DpkgError::InstallFailed)?;
+    }
+
+    Ok(())
+}
+
+/// Uninstall apps, then drop runtimes no longer used by any app.
+pub fn uninstall(apps: &[FlatpakApp], verbose: bool) -> Result<(), DpkgError> {
+    for user in [false, true] {
+        let ids: Vec<&str> = apps
+            .iter()
+            .filter(|a| a.user == user)
+            .map(|a| a.id.as_str())
+            .collect();
+        if ids.is_empty() {
+            continue;
+        }
+
+        if verbose {
+            eprintln!("Removing {} Flatpak apps...", ids.len());
+        }
+        let mut command = flatpak_command(user);
+        command
+            .args(["uninstall", "--noninteractive", scope_flag(user)])
+            .args(&ids);
+        run(command, "flatpak uninstall", DpkgError::RemoveFailed)?;
+
+        let mut unused = flatpak_command(user);
+        unused.args(["uninstall", "--noninteractive", "--unused", scope_flag(user)]);
+        run(unused, "flatpak uninstall --unused", DpkgError::RemoveFailed)?;
+    }
+
+    Ok(())
+}
+
+fn flatpak_command(user: bool) -> Command {
+    if user {
+        Command::new(flatpak_bin())
+    } else {
+        escalate::command(flatpak_bin())
+    }
+}
+
+/// Run a flatpak transaction; a failure becomes `error` with flatpak's stderr.
+fn run(mut command: Command, what: &str, error: fn(String) -> DpkgError) -> Result<(), DpkgError> {
+    let output = command
+        .output()
+        .map_err(|e| DpkgError::spawn(&flatpak_bin(), e))?;
+
+    if !output.status.success() {
+        return Err(error(format!(
+            "{what} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
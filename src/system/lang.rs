@@ -0,0 +1,177 @@
+use std::process::Command;
+
+use crate::config::LangTool;
+use crate::error::{DpkgError, PackageOutcome};
+
+/// Global npm packages that ship with node itself and are never removed.
+const NPM_BUNDLED: &[&str] = &["npm", "corepack"];
+
+/// Binary for `tool`, overridable with `CARGO`, `PIPX` or `NPM`.
+fn tool_bin(tool: LangTool) -> String {
+    let (var, default) = match tool {
+        LangTool::Cargo => ("CARGO", "cargo"),
+        LangTool::Pipx => ("PIPX", "pipx"),
+        LangTool::Npm => ("NPM", "npm"),
+    };
+    std::env::var(var).unwrap_or_else(|_| default.to_string())
+}
+
+/// Names of the tools currently installed through `tool`.
+pub fn list_installed(tool: LangTool) -> Result<Vec<String>, DpkgError> {
+    let args: &[&str] = match tool {
+        LangTool::Cargo => &["install", "--list"],
+        LangTool::Pipx => &["list", "--short"],
+        LangTool::Npm => &["ls", "--global", "--depth=0", "--json"],
+    };
+    let stdout = run(tool, args, "list")?;
+
+    Ok(match tool {
+        LangTool::Cargo => parse_cargo_list(&stdout),
+        LangTool::Pipx => parse_pipx_list(&stdout),
+        LangTool::Npm => parse_npm_list(&stdout)?,
+    })
+}
+
+/// Install tools with the user's own toolchain; these are never escalated.
+pub fn install(tool: LangTool, names: &[String], verbose: bool) -> Result<(), DpkgError> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    if verbose {
+        eprintln!("Installing {} {} packages...", names.len(), tool.name());
+    }
+
+    match tool {
+        // pipx takes one package per invocation, so one failure does not stop the rest
+        LangTool::Pipx => {
+            let mut outcomes = Vec::new();
+            let mut first_error = None;
+            for name in names {
+                let result = run(tool, &["install", name], "install");
+                outcomes.push(PackageOutcome {
+                    name: name.clone(),
+                    error: result.as_ref().err().map(|e| e.to_string()),
+                });
+                if let Err(e) = result {
+                    first_error.get_or_insert(e);
+                }
+            }
+            match first_error {
+                None => Ok(()),
+                Some(e) if outcomes.iter().all(|o| o.error.is_some()) => Err(e),
+                Some(_) => Err(DpkgError::PartialInstall(outcomes)),
+            }
+        }
+        LangTool::Cargo => run_with(tool, &["install"], names, "install"),
+        LangTool::Npm => run_with(tool, &["install", "--global"], names, "install"),
+    }
+}
+
+/// Uninstall tools through the package manager that installed them.
+pub fn uninstall(tool: LangTool, names: &[String], verbose: bool) -> Result<(), DpkgError> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    if verbose {
+        eprintln!("Removing {} {} packages...", names.len(), tool.name());
+    }
+
+    match tool {
+        LangTool::Pipx => {
+            for name in names {
+                run(tool, &["uninstall", name], "uninstall")?;
+            }
+            Ok(())
+        }
+        LangTool::Cargo => run_with(tool, &["uninstall"], names, "uninstall"),
+        LangTool::Npm => run_with(tool, &["uninstall", "--global"], names, "uninstall"),
+    }
+}
+
+fn run_with(tool: LangTool, args: &[&str], names: &[String], what: &str) -> Result<(), DpkgError> {
+    let mut all: Vec<&str> = args.to_vec();
+    all.extend(names.iter().map(|s| s.as_str()));
+    run(tool, &all, what).map(|_| ())
+}
+
+fn run(tool: LangTool, args: &[&str], what: &str) -> Result<String, DpkgError> {
+    let output = Command::new(tool_bin(tool))
+        .args(args)
+        .output()
+        .map_err(|e| DpkgError::spawn(&tool_bin(tool), e))?;
+
+    if !output.status.success() {
+        let error = match what {
+            "install" => DpkgError::InstallFailed,
+            "uninstall" => DpkgError::RemoveFailed,
+            _ => DpkgError::QueryFailed,
+        };
+        return Err(error(format!(
+            "{} {what} failed: {}",
+            tool.name(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// `cargo install --list` prints `name vX.Y.Z:` followed by indented binaries.
+fn parse_cargo_list(stdout: &str) -> Vec<String> {
+    stdout
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|name| name.trim_end_matches(':').to_string())
+        .collect()
+}
+
+/// `pipx list --short` prints `name version` per line.
+fn parse_pipx_list(stdout: &str) -> Vec<String> {
+    stdout
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// `npm ls --global --json` lists top-level packages under `dependencies`.
+fn parse_npm_list(stdout: &str) -> Result<Vec<String>, DpkgError> {
+    if stdout.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let value: serde_json::Value = serde_json::from_str(stdout)
+        .map_err(|e| DpkgError::QueryFailed(format!("Cannot parse npm ls output: {e}")))?;
+    Ok(value
+        .get("dependencies")
+        .and_then(|deps| deps.as_object())
+        .map(|deps| {
+            deps.keys()
+                .filter(|name| !NPM_BUNDLED.contains(&name.as_str()))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cargo_list() {
+        let stdout = "cargo-audit v0.20.0:\n    cargo-audit\nripgrep v14.1.0:\n    rg\n";
+        assert_eq!(parse_cargo_list(stdout), vec!["cargo-audit", "ripgrep"]);
+    }
+
+    #[test]
+    fn test_parse_pipx_list() {
+        assert_eq!(parse_pipx_list("black 24.1.0\nhttpie 3.2.2\n"), vec!["black", "httpie"]);
+    }
+
+    #[test]
+    fn test_parse_npm_list_skips_bundled() {
+        let stdout = r#"{"dependencies":{"@angular/cli":{"version":"17.0.0"},"corepack":{},"npm":{},"prettier":{}}}"#;
+        assert_eq!(parse_npm_list(stdout).unwrap(), vec!["@angular/cli", "prettier"]);
+        assert!(parse_npm_list("{}").unwrap().is_empty());
+    }
+}
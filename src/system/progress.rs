@@ -0,0 +1,354 @@
+use std::io::{self, Read, Write};
+use std::process::{Command, Output, Stdio};
+use std::sync::OnceLock;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use serde::Serialize;
+
+use crate::cli::Progress;
+use crate::output;
+
+static PROGRESS: OnceLock<Progress> = OnceLock::new();
+
+/// Something pacman, yay or makepkg reported while running.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// `:: Retrieving packages...`, or one package being downloaded
+    Downloading { package: Option<String> },
+    /// A transaction check such as `checking package integrity...`
+    Checking { what: String },
+    /// `(n/m) installing X`; also upgrading, reinstalling, downgrading and removing.
+    /// Without a progress bar pacman leaves out the counter.
+    Package {
+        action: String,
+        package: String,
+        index: Option<usize>,
+        total: Option<usize>,
+    },
+    /// `(n/m) <description>` under a `:: Running ...-transaction hooks...` heading
+    Hook { name: String, index: usize, total: usize },
+    /// `==> Making package: X` from makepkg, run directly or by yay
+    Building { package: String },
+    /// A question the child is waiting on
+    Prompt { message: String },
+    Warning { message: String },
+    Error { message: String },
+}
+
+/// Turns output lines into events. Hook lines look like any other counted line, so
+/// the parser remembers whether it is inside a hooks block.
+#[derive(Debug, Default)]
+pub struct EventParser {
+    in_hooks: bool,
+}
+
+const ACTIONS: [&str; 5] = ["installing", "upgrading", "reinstalling", "downgrading", "removing"];
+
+impl EventParser {
+    pub fn parse(&mut self, line: &str) -> Option<ProgressEvent> {
+        let line = strip_ansi(line);
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+
+        if let Some(heading) = line.strip_prefix(":: ") {
+            self.in_hooks = heading.starts_with("Running ") && heading.contains("-transaction hooks");
+            if heading.starts_with("Retrieving packages") {
+                return Some(ProgressEvent::Downloading { package: None });
+            }
+        }
+        if is_prompt(line) {
+            return Some(ProgressEvent::Prompt { message: line.to_string() });
+        }
+        if let Some(message) = line.strip_prefix("error: ").or_else(|| line.strip_prefix("==> ERROR: ")) {
+            return Some(ProgressEvent::Error { message: message.to_string() });
+        }
+        if let Some(message) = line.strip_prefix("warning: ").or_else(|| line.strip_prefix("==> WARNING: ")) {
+            return Some(ProgressEvent::Warning { message: message.to_string() });
+        }
+        if let Some(rest) = line.strip_prefix("==> Making package: ") {
+            let package = rest.split_whitespace().next()?.to_string();
+            return Some(ProgressEvent::Building { package });
+        }
+        if let Some(package) = line.strip_suffix(" downloading...") {
+            return Some(ProgressEvent::Downloading { package: Some(package.trim().to_string()) });
+        }
+        if let Some(what) = line.strip_prefix("checking ") {
+            return Some(ProgressEvent::Checking { what: trim_dots(what).to_string() });
+        }
+
+        let (counter, rest) = split_counter(line);
+        if self.in_hooks {
+            let (index, total) = counter?;
+            return Some(ProgressEvent::Hook { name: trim_dots(rest).to_string(), index, total });
+        }
+        let (action, rest) = rest.split_once(' ')?;
+        if !ACTIONS.contains(&action) {
+            return None;
+        }
+        let package = trim_dots(rest.split_whitespace().next()?);
+        Some(ProgressEvent::Package {
+            action: action.to_string(),
+            package: package.to_string(),
+            index: counter.map(|(index, _)| index),
+            total: counter.map(|(_, total)| total),
+        })
+    }
+}
+
+fn is_prompt(line: &str) -> bool {
+    line.contains("[Y/n]") || line.contains("[y/N]")
+}
+
+/// Split a leading `(n/m) ` counter off `line`.
+fn split_counter(line: &str) -> (Option<(usize, usize)>, &str) {
+    let counter = line.strip_prefix('(').and_then(|rest| {
+        let (counter, rest) = rest.split_once(") ")?;
+        let (index, total) = counter.trim().split_once('/')?;
+        Some(((index.trim().parse().ok()?, total.trim().parse().ok()?), rest))
+    });
+    match counter {
+        Some((counter, rest)) => (Some(counter), rest.trim()),
+        None => (None, line),
+    }
+}
+
+fn trim_dots(text: &str) -> &str {
+    text.trim().trim_end_matches("...").trim_end()
+}
+
+/// Drop terminal color sequences (`ESC [ ... letter`); makepkg colors its output.
+fn strip_ansi(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Remove the complete lines from `pending`, leaving a trailing partial line.
+/// Progress bars redraw with `\r`, so it ends a line too.
+fn take_lines(pending: &mut Vec<u8>) -> Vec<String> {
+    let Some(end) = pending.iter().rposition(|&b| b == b'\n' || b == b'\r') else {
+        return Vec::new();
+    };
+    let rest = pending.split_off(end + 1);
+    let lines = String::from_utf8_lossy(pending)
+        .split(['\n', '\r'])
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.to_string())
+        .collect();
+    *pending = rest;
+    lines
+}
+
+/// Select how package operations show their output; set once at startup.
+pub fn set_mode(mode: Progress) {
+    let _ = PROGRESS.set(mode);
+}
+
+fn mode() -> Progress {
+    PROGRESS.get().copied().unwrap_or(Progress::Compact)
+}
+
+/// Show an event from a running package operation.
+fn show(event: &ProgressEvent) {
+    match mode() {
+        Progress::Json => {
+            if let Ok(line) = serde_json::to_string(event) {
+                println!("{line}");
+            }
+        }
+        Progress::Compact => match event {
+            ProgressEvent::Downloading { package: Some(package) } => output::info(&format!("  downloading {package}")),
+            ProgressEvent::Downloading { package: None } => output::info("  downloading packages"),
+            ProgressEvent::Checking { what } => output::plain(&format!("  checking {what}")),
+            ProgressEvent::Package { action, package, index, total } => match (index, total) {
+                (Some(index), Some(total)) => output::plain(&format!("  {action} {package} ({index}/{total})")),
+                _ => output::plain(&format!("  {action} {package}")),
+            },
+            ProgressEvent::Hook { name, index, total } => {
+                output::plain(&format!("  hook ({index}/{total}): {name}"))
+            }
+            ProgressEvent::Building { package } => output::info(&format!("  building {package}")),
+            ProgressEvent::Prompt { message } => output::warning(&format!("  {message}")),
+            ProgressEvent::Warning { message } => output::warning(&format!("  warning: {message}")),
+            ProgressEvent::Error { message } => output::error(&format!("  error: {message}")),
+        },
+        Progress::Raw | Progress::Off => {}
+    }
+}
+
+/// Like `Command::output`, but shows the child's output while it runs, as selected
+/// by `--progress`. Output is still captured for error messages; stdin stays
+/// attached so prompts can be answered.
+pub fn run(command: &mut Command) -> io::Result<Output> {
+    let mode = mode();
+    let mut child = command
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let (tx, rx) = mpsc::channel();
+    let readers = [
+        child.stdout.take().map(|out| forward(out, 0, tx.clone())),
+        child.stderr.take().map(|err| forward(err, 1, tx.clone())),
+    ];
+    drop(tx);
+
+    let mut captured = [Vec::new(), Vec::new()];
+    let mut pending = [Vec::new(), Vec::new()];
+    let mut parser = EventParser::default();
+    for (stream, chunk) in rx {
+        captured[stream].extend_from_slice(&chunk);
+        match mode {
+            Progress::Raw => passthrough(stream, &chunk),
+            Progress::Compact | Progress::Json => {
+                pending[stream].extend_from_slice(&chunk);
+                for line in take_lines(&mut pending[stream]) {
+                    if let Some(event) = parser.parse(&line) {
+                        show(&event);
+                    }
+                }
+                // A prompt has no newline until it is answered
+                let partial = String::from_utf8_lossy(&pending[stream]).into_owned();
+                if is_prompt(&partial) {
+                    show(&ProgressEvent::Prompt { message: partial.trim().to_string() });
+                    pending[stream].clear();
+                }
+            }
+            Progress::Off => {}
+        }
+    }
+    for reader in readers.into_iter().flatten() {
+        let _ = reader.join();
+    }
+    if matches!(mode, Progress::Compact | Progress::Json) {
+        for rest in pending {
+            if let Some(event) = parser.parse(&String::from_utf8_lossy(&rest)) {
+                show(&event);
+            }
+        }
+    }
+
+    let [stdout, stderr] = captured;
+    Ok(Output { status: child.wait()?, stdout, stderr })
+}
+
+fn forward(mut source: impl Read + Send + 'static, stream: usize, tx: Sender<(usize, Vec<u8>)>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        while let Ok(n) = source.read(&mut buf) {
+            if n == 0 || tx.send((stream, buf[..n].to_vec())).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+fn passthrough(stream: usize, chunk: &[u8]) {
+    // Nothing useful to do if the terminal is gone
+    let _ = if stream == 0 {
+        let mut out = io::stdout().lock();
+        out.write_all(chunk).and_then(|_| out.flush())
+    } else {
+        let mut err = io::stderr().lock();
+        err.write_all(chunk).and_then(|_| err.flush())
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(action: &str, package: &str, counter: Option<(usize, usize)>) -> ProgressEvent {
+        ProgressEvent::Package {
+            action: action.to_string(),
+            package: package.to_string(),
+            index: counter.map(|c| c.0),
+            total: counter.map(|c| c.1),
+        }
+    }
+
+    #[test]
+    fn test_parse_pacman_transcript() {
+        let transcript = "\
+resolving dependencies...
+:: Retrieving packages...
+ ripgrep-14.1.0-1-x86_64 downloading...
+checking keyring...
+checking package integrity...
+:: Processing package changes...
+(1/2) installing ripgrep                     [#####################] 100%
+upgrading fd...
+warning: fd: local (9.0.0-1) is newer than extra (8.7.1-1)
+:: Running post-transaction hooks...
+(1/2) Arming ConditionNeedsUpdate...
+( 2/12) Updating the info directory file...
+error: failed to commit transaction (conflicting files)
+:: Proceed with installation? [Y/n]
+\x1b[1m\x1b[32m==>\x1b[0m\x1b[1m Making package: ripgrep-git 14.1.0-1 (Sun 19 Oct 2026)\x1b[0m";
+        let mut parser = EventParser::default();
+        let events: Vec<ProgressEvent> = transcript.lines().filter_map(|l| parser.parse(l)).collect();
+        assert_eq!(
+            events,
+            vec![
+                ProgressEvent::Downloading { package: None },
+                ProgressEvent::Downloading { package: Some("ripgrep-14.1.0-1-x86_64".to_string()) },
+                ProgressEvent::Checking { what: "keyring".to_string() },
+                ProgressEvent::Checking { what: "package integrity".to_string() },
+                package("installing", "ripgrep", Some((1, 2))),
+                package("upgrading", "fd", None),
+                ProgressEvent::Warning {
+                    message: "fd: local (9.0.0-1) is newer than extra (8.7.1-1)".to_string()
+                },
+                ProgressEvent::Hook { name: "Arming ConditionNeedsUpdate".to_string(), index: 1, total: 2 },
+                ProgressEvent::Hook {
+                    name: "Updating the info directory file".to_string(),
+                    index: 2,
+                    total: 12
+                },
+                ProgressEvent::Error {
+                    message: "failed to commit transaction (conflicting files)".to_string()
+                },
+                ProgressEvent::Prompt { message: ":: Proceed with installation? [Y/n]".to_string() },
+                ProgressEvent::Building { package: "ripgrep-git".to_string() },
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(package("removing", "fd", Some((3, 4)))).unwrap(),
+            serde_json::json!({ "event": "package", "action": "removing", "package": "fd", "index": 3, "total": 4 })
+        );
+    }
+
+    #[test]
+    fn test_take_lines_keeps_partial_line() {
+        let mut pending = b"one\ntwo\r[###] 50%\rthree".to_vec();
+        assert_eq!(take_lines(&mut pending), vec!["one", "two", "[###] 50%"]);
+        assert_eq!(pending, b"three");
+        assert!(take_lines(&mut pending).is_empty());
+    }
+
+    #[test]
+    fn test_run_captures_both_streams() {
+        let output = run(Command::new("sh").args(["-c", "echo out; echo err >&2; exit 3"])).unwrap();
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+        assert_eq!(output.status.code(), Some(3));
+    }
+}
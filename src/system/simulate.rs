@@ -0,0 +1,379 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::Serialize;
+
+use crate::error::DpkgError;
+use crate::system::{self, pacman, pacman_bin};
+
+/// `--print-format` for simulated transactions: name, version, repository, size
+/// (download size when installing, installed size when removing), depends, provides.
+const PRINT_FORMAT: &str = "%n\t%v\t%r\t%s\t%D\t%P";
+
+/// One package of a transaction as printed by `pacman --print`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionPackage {
+    pub name: String,
+    pub version: String,
+    pub repo: String,
+    pub size: u64,
+    pub depends: Vec<String>,
+    pub provides: Vec<String>,
+}
+
+/// What installing or removing one requested package does, its dependencies included.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SimulatedPackage {
+    pub package: String,
+    pub version: String,
+    /// Packages the transaction adds (or removes) only because of this one
+    pub dependencies: Vec<String>,
+    pub download_size: u64,
+    /// Change of the installed size in bytes
+    pub installed_size_delta: i64,
+    /// Conflicts pacman reported for this package or its dependencies
+    pub conflicts: Vec<String>,
+}
+
+/// The transactions a sync would run, as pacman resolves them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Simulation {
+    pub install: Vec<SimulatedPackage>,
+    pub remove: Vec<SimulatedPackage>,
+    /// Dependencies no requested package could be matched to
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub other_dependencies: Vec<String>,
+    pub download_size: u64,
+    pub installed_size_delta: i64,
+    pub conflicts: Vec<String>,
+    /// Why pacman could not resolve a transaction
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+/// Ask pacman for the full install (`-S --print`) and removal (`-Rns --print`)
+/// transactions without applying them. Neither needs root.
+pub fn simulate(install: &[String], remove: &[String]) -> Result<Simulation, DpkgError> {
+    let mut simulation = Simulation::default();
+
+    if !install.is_empty() {
+        let Printed { packages, conflicts, errors } =
+            print_transaction(&["-S", "--needed", "--noconfirm"], install)?;
+        let names: Vec<String> = packages.iter().map(|p| format!("{}/{}", p.repo, p.name)).collect();
+        let new_sizes = installed_sizes("-Si", &names)?;
+        let old_sizes =
+            installed_sizes("-Qi", &packages.iter().map(|p| p.name.clone()).collect::<Vec<_>>())?;
+        let delta = |p: &TransactionPackage| {
+            new_sizes.get(&p.name).copied().unwrap_or(0) as i64
+                - old_sizes.get(&p.name).copied().unwrap_or(0) as i64
+        };
+        let (summaries, other) = summarize(install, &packages, &conflicts, |p| p.size, delta);
+        simulation.install = summaries;
+        simulation.other_dependencies.extend(other);
+        simulation.conflicts.extend(conflicts);
+        simulation.errors.extend(errors);
+    }
+
+    if !remove.is_empty() {
+        let Printed { packages, conflicts, errors } = print_transaction(&["-Rns", "--noconfirm"], remove)?;
+        let (summaries, other) = summarize(remove, &packages, &conflicts, |_| 0, |p| -(p.size as i64));
+        simulation.remove = summaries;
+        simulation.other_dependencies.extend(other);
+        simulation.conflicts.extend(conflicts);
+        simulation.errors.extend(errors);
+    }
+
+    let all = simulation.install.iter().chain(&simulation.remove);
+    simulation.download_size = all.clone().map(|p| p.download_size).sum();
+    simulation.installed_size_delta = all.map(|p| p.installed_size_delta).sum();
+    Ok(simulation)
+}
+
+/// What `pacman --print` resolved, with the conflicts and errors it reported.
+struct Printed {
+    packages: Vec<TransactionPackage>,
+    conflicts: Vec<String>,
+    errors: Vec<String>,
+}
+
+/// Run `pacman <op> --print`. pacman exits non-zero when the transaction cannot be
+/// resolved, with the reasons as errors.
+fn print_transaction(op: &[&str], packages: &[String]) -> Result<Printed, DpkgError> {
+    let output = pacman()
+        .args(op)
+        .args(["--print", "--print-format", PRINT_FORMAT])
+        .args(packages)
+        .output()
+        .map_err(|e| DpkgError::spawn(&pacman_bin(), e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let conflicts = parse_conflicts(&format!("{stdout}\n{stderr}"));
+    let errors = if output.status.success() {
+        Vec::new()
+    } else {
+        stderr
+            .lines()
+            .filter_map(|line| line.strip_prefix("error: "))
+            .map(|line| line.to_string())
+            .collect()
+    };
+    Ok(Printed { packages: parse_print(&stdout), conflicts, errors })
+}
+
+/// `Installed Size` of each package `pacman <flag>` knows, by name.
+fn installed_sizes(flag: &str, packages: &[String]) -> Result<HashMap<String, u64>, DpkgError> {
+    if packages.is_empty() {
+        return Ok(HashMap::new());
+    }
+    // Exits non-zero if any target is unknown but still prints the rest
+    let output = pacman()
+        .arg(flag)
+        .args(packages)
+        .output()
+        .map_err(|e| DpkgError::spawn(&pacman_bin(), e))?;
+    Ok(system::parse_package_info(&String::from_utf8_lossy(&output.stdout))
+        .into_iter()
+        .filter_map(|fields| {
+            let size = parse_size(fields.get("Installed Size")?)?;
+            Some((fields.get("Name")?.clone(), size))
+        })
+        .collect())
+}
+
+/// Pure computation: parse `pacman --print` lines in `PRINT_FORMAT`. Anything else
+/// (warnings, `:: ` messages) is skipped.
+pub fn parse_print(text: &str) -> Vec<TransactionPackage> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [name, version, repo, size, depends, provides] = fields[..] else {
+                return None;
+            };
+            Some(TransactionPackage {
+                name: name.to_string(),
+                version: version.to_string(),
+                repo: repo.to_string(),
+                size: size.trim().parse().ok()?,
+                depends: system::parse_depends(depends),
+                provides: system::parse_depends(provides),
+            })
+        })
+        .collect()
+}
+
+/// Pure computation: `a and b` for each `:: a and b are in conflict` pacman printed,
+/// once each.
+pub fn parse_conflicts(text: &str) -> Vec<String> {
+    let mut conflicts: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim().trim_start_matches(":: ");
+        if let Some((pair, _)) = line.split_once(" are in conflict")
+            && !conflicts.iter().any(|c| c == pair)
+        {
+            conflicts.push(pair.to_string());
+        }
+    }
+    conflicts
+}
+
+/// Pure computation: a `Installed Size` value such as `5.43 MiB` in bytes.
+pub fn parse_size(text: &str) -> Option<u64> {
+    let (number, unit) = text.trim().split_once(' ')?;
+    let number: f64 = number.parse().ok()?;
+    let factor = match unit.trim() {
+        "B" => 1u64,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        "TiB" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * factor as f64).round() as u64)
+}
+
+/// Pure computation: a byte count the way pacman shows it, e.g. `1.21 GiB`, with a sign
+/// when `signed`.
+pub fn format_size(bytes: i64, signed: bool) -> String {
+    let sign = match (signed, bytes < 0) {
+        (_, true) => "-",
+        (true, false) => "+",
+        (false, false) => "",
+    };
+    let mut value = bytes.unsigned_abs() as f64;
+    let mut unit = "B";
+    for next in ["KiB", "MiB", "GiB", "TiB"] {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    if unit == "B" {
+        format!("{sign}{value} {unit}")
+    } else {
+        format!("{sign}{value:.2} {unit}")
+    }
+}
+
+/// Whether a side of a conflict (`name` or `name-version`) is `package`.
+fn names_package(side: &str, package: &str) -> bool {
+    side == package
+        || side
+            .strip_prefix(package)
+            .and_then(|rest| rest.strip_prefix('-'))
+            .is_some_and(|version| version.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// Pure computation: split a transaction among the requested packages. Each other
+/// package goes to the first requested package whose dependencies reach it, so the
+/// sizes add up to the whole transaction; those reached by none are returned apart.
+pub fn summarize(
+    requested: &[String],
+    transaction: &[TransactionPackage],
+    conflicts: &[String],
+    download_size: impl Fn(&TransactionPackage) -> u64,
+    installed_size_delta: impl Fn(&TransactionPackage) -> i64,
+) -> (Vec<SimulatedPackage>, Vec<String>) {
+    let by_name: HashMap<&str, &TransactionPackage> =
+        transaction.iter().map(|p| (p.name.as_str(), p)).collect();
+    let mut providers: HashMap<&str, &str> = HashMap::new();
+    for package in transaction {
+        for provided in &package.provides {
+            providers.entry(provided.as_str()).or_insert(package.name.as_str());
+        }
+    }
+
+    let mut claimed: HashSet<&str> =
+        requested.iter().map(|p| p.as_str()).filter(|p| by_name.contains_key(p)).collect();
+    let mut summaries = Vec::new();
+    for name in requested {
+        let Some(package) = by_name.get(name.as_str()) else {
+            continue;
+        };
+        let mut dependencies: Vec<&TransactionPackage> = Vec::new();
+        let mut queue: VecDeque<&TransactionPackage> = VecDeque::from([*package]);
+        while let Some(current) = queue.pop_front() {
+            for dep in &current.depends {
+                let target = by_name
+                    .get(dep.as_str())
+                    .or_else(|| providers.get(dep.as_str()).and_then(|p| by_name.get(p)));
+                if let Some(target) = target
+                    && claimed.insert(target.name.as_str())
+                {
+                    dependencies.push(target);
+                    queue.push_back(target);
+                }
+            }
+        }
+
+        let members: Vec<&TransactionPackage> =
+            std::iter::once(*package).chain(dependencies.iter().copied()).collect();
+        summaries.push(SimulatedPackage {
+            package: name.clone(),
+            version: package.version.clone(),
+            dependencies: dependencies.iter().map(|p| p.name.clone()).collect(),
+            download_size: members.iter().map(|p| download_size(p)).sum(),
+            installed_size_delta: members.iter().map(|p| installed_size_delta(p)).sum(),
+            conflicts: conflicts
+                .iter()
+                .filter(|conflict| {
+                    conflict.split(" and ").any(|side| {
+                        let side = side.split_whitespace().next().unwrap_or(side);
+                        members.iter().any(|p| names_package(side, &p.name))
+                    })
+                })
+                .cloned()
+                .collect(),
+        });
+    }
+
+    let other = transaction
+        .iter()
+        .filter(|p| !claimed.contains(p.name.as_str()))
+        .map(|p| p.name.clone())
+        .collect();
+    (summaries, other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, size: u64, depends: &[&str], provides: &[&str]) -> TransactionPackage {
+        TransactionPackage {
+            name: name.to_string(),
+            version: "1.0-1".to_string(),
+            repo: "multilib".to_string(),
+            size,
+            depends: depends.iter().map(|d| d.to_string()).collect(),
+            provides: provides.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_print_and_conflicts() {
+        let stdout = "\
+warning: git-2.45.0-1 is up to date -- skipping
+lib32-glibc\t2.40-1\tmultilib\t1048576\tglibc=2.40\tNone
+steam\t1.0.0.81-1\tmultilib\t4404019\tbash  lib32-glibc>=2.27  lib32-vulkan-driver\tNone
+";
+        assert_eq!(
+            parse_print(stdout),
+            vec![
+                TransactionPackage { version: "2.40-1".to_string(), ..package("lib32-glibc", 1048576, &["glibc"], &[]) },
+                TransactionPackage {
+                    version: "1.0.0.81-1".to_string(),
+                    ..package("steam", 4404019, &["bash", "lib32-glibc", "lib32-vulkan-driver"], &[])
+                },
+            ]
+        );
+
+        let stderr = "\
+:: iptables-nft-1:1.8.10-2 and iptables-1:1.8.10-2 are in conflict. Remove iptables? [y/N]
+error: unresolvable package conflicts detected
+:: iptables-nft-1:1.8.10-2 and iptables-1:1.8.10-2 are in conflict
+:: foo and bar are in conflict (baz)
+";
+        assert_eq!(
+            parse_conflicts(stderr),
+            vec!["iptables-nft-1:1.8.10-2 and iptables-1:1.8.10-2", "foo and bar"]
+        );
+    }
+
+    #[test]
+    fn test_sizes() {
+        assert_eq!(parse_size("5.43 MiB"), Some(5693768));
+        assert_eq!(parse_size("512.00 B"), Some(512));
+        assert_eq!(parse_size("1.00 GiB"), Some(1 << 30));
+        assert_eq!(parse_size("huge"), None);
+        assert_eq!(format_size(1288490189, false), "1.20 GiB");
+        assert_eq!(format_size(-2048, true), "-2.00 KiB");
+        assert_eq!(format_size(900, true), "+900 B");
+    }
+
+    #[test]
+    fn test_summarize_attributes_dependencies() {
+        let transaction = vec![
+            package("lib32-glibc", 100, &["glibc"], &[]),
+            package("lib32-mesa", 300, &["lib32-glibc"], &["lib32-vulkan-driver"]),
+            package("steam", 1000, &["lib32-glibc", "lib32-vulkan-driver"], &[]),
+            package("wine", 2000, &["lib32-glibc"], &[]),
+            package("iptables-nft", 10, &[], &[]),
+        ];
+        let requested = vec!["steam".to_string(), "wine".to_string(), "missing".to_string()];
+        let conflicts = vec!["lib32-mesa-1.0-1 and lib32-amdvlk-2.0-1".to_string()];
+        let (summaries, other) =
+            summarize(&requested, &transaction, &conflicts, |p| p.size, |p| 2 * p.size as i64);
+
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].package, "steam");
+        assert_eq!(summaries[0].dependencies, vec!["lib32-glibc", "lib32-mesa"]);
+        assert_eq!(summaries[0].download_size, 1400);
+        assert_eq!(summaries[0].installed_size_delta, 2800);
+        assert_eq!(summaries[0].conflicts, conflicts);
+        assert!(summaries[1].dependencies.is_empty());
+        assert_eq!(summaries[1].download_size, 2000);
+        assert!(summaries[1].conflicts.is_empty());
+        assert_eq!(other, vec!["iptables-nft"]);
+        assert!(!names_package("lib32-mesa-git", "lib32-mesa"));
+    }
+}
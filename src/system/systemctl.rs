@@ -0,0 +1,90 @@
+use std::process::Command;
+
+use crate::config::Service;
+use crate::error::DpkgError;
+use crate::system::{self, escalate};
+
+fn systemctl_bin() -> String {
+    std::env::var("SYSTEMCTL").unwrap_or_else(|_| "systemctl".to_string())
+}
+
+fn base_command(user: bool) -> Command {
+    let mut command = Command::new(systemctl_bin());
+    if user {
+        command.arg("--user");
+    } else {
+        command.args(root_arg());
+    }
+    command
+}
+
+/// Under `--root`, system units are looked up and enabled in the target.
+fn root_arg() -> Option<String> {
+    system::target().root.as_ref().map(|root| format!("--root={}", root.display()))
+}
+
+/// Whether `unit` is enabled. Units that don't exist yet (e.g. shipped by a package
+/// that is about to be installed) count as not enabled.
+pub fn is_enabled(unit: &str, user: bool) -> Result<bool, DpkgError> {
+    let output = base_command(user)
+        .args(["is-enabled", unit])
+        .output()
+        .map_err(|e| DpkgError::spawn(&systemctl_bin(), e))?;
+
+    // is-enabled exits non-zero for disabled and unknown units; the state is on stdout
+    let state = String::from_utf8_lossy(&output.stdout);
+    Ok(matches!(
+        state.lines().next().unwrap_or("").trim(),
+        "enabled" | "enabled-runtime" | "alias"
+    ))
+}
+
+/// Enable (`enable = true`) or disable the given units of one manager.
+/// System units go through the configured escalation method; user units run as the invoking user.
+pub fn set_enabled(units: &[String], enable: bool, user: bool, verbose: bool) -> Result<(), DpkgError> {
+    if units.is_empty() {
+        return Ok(());
+    }
+
+    let verb = if enable { "enable" } else { "disable" };
+    if verbose {
+        eprintln!("Running systemctl {verb} for {} units...", units.len());
+    }
+
+    let mut command = if user {
+        base_command(true)
+    } else {
+        let mut command = escalate::command(systemctl_bin());
+        command.args(root_arg());
+        command
+    };
+    let output = command
+        .arg(verb)
+        .args(units)
+        .output()
+        .map_err(|e| DpkgError::spawn(&systemctl_bin(), e))?;
+
+    if !output.status.success() {
+        return Err(DpkgError::ServiceFailed(format!(
+            "systemctl {verb} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Apply enable/disable changes, batching system and user units separately.
+pub fn apply(services: &[Service], verbose: bool) -> Result<(), DpkgError> {
+    for enable in [true, false] {
+        for user in [false, true] {
+            let units: Vec<String> = services
+                .iter()
+                .filter(|s| s.enabled == enable && s.user == user)
+                .map(|s| s.unit.clone())
+                .collect();
+            set_enabled(&units, enable, user, verbose)?;
+        }
+    }
+    Ok(())
+}